@@ -0,0 +1,2 @@
+DROP TABLE food_table;
+DROP TABLE user_table;
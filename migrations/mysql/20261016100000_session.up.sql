@@ -0,0 +1,11 @@
+CREATE TABLE session_table (
+    id          INT AUTO_INCREMENT NOT NULL,
+    token_hash  VARCHAR(64) NOT NULL,
+    user_id     VARCHAR(40) NOT NULL,
+    expires_at  DATETIME NOT NULL,
+    UNIQUE KEY session_token_hash_idx (token_hash),
+    CONSTRAINT fk_session_user FOREIGN KEY (user_id) REFERENCES user_table(user_id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE,
+    PRIMARY KEY (id)
+);
@@ -0,0 +1 @@
+DROP INDEX usr_id_exp ON food_table;
@@ -0,0 +1 @@
+CREATE INDEX usr_id_exp ON food_table (user_id, exp);
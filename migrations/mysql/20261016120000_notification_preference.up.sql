@@ -0,0 +1,13 @@
+CREATE TABLE notification_preference_table (
+    id              INT AUTO_INCREMENT NOT NULL,
+    user_id         VARCHAR(40) NOT NULL,
+    enabled         BOOLEAN NOT NULL DEFAULT FALSE,
+    send_time       TIME NOT NULL DEFAULT '08:00:00',
+    lead_days       INT UNSIGNED NOT NULL DEFAULT 3,
+    last_sent_on    DATE NULL,
+    UNIQUE KEY pref_user_id_idx (user_id),
+    CONSTRAINT fk_pref_user FOREIGN KEY (user_id) REFERENCES user_table(user_id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE,
+    PRIMARY KEY (id)
+);
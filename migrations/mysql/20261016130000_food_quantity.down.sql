@@ -0,0 +1,3 @@
+ALTER TABLE food_table
+    DROP COLUMN quantity,
+    DROP COLUMN unit;
@@ -0,0 +1,3 @@
+ALTER TABLE food_table
+    ADD COLUMN quantity DOUBLE NOT NULL DEFAULT 1,
+    ADD COLUMN unit VARCHAR(16) NOT NULL DEFAULT 'count';
@@ -0,0 +1,6 @@
+ALTER TABLE food_table
+    DROP FOREIGN KEY fk_food_location,
+    DROP INDEX food_location_id,
+    DROP COLUMN location_id;
+
+DROP TABLE storage_location_table;
@@ -0,0 +1,33 @@
+CREATE TABLE storage_location_table (
+    id              INT AUTO_INCREMENT NOT NULL,
+    location_id     VARCHAR(40) NOT NULL,
+    location_name   VARCHAR(255) NOT NULL,
+    kind            VARCHAR(16) NOT NULL,
+    temperature     DOUBLE NULL,
+    user_id         VARCHAR(40) NOT NULL,
+    UNIQUE KEY location_id_idx (location_id),
+    INDEX location_usr_id (user_id),
+    CONSTRAINT fk_location_user FOREIGN KEY (user_id) REFERENCES user_table(user_id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE,
+    PRIMARY KEY (id)
+);
+
+-- every existing user gets a default fridge holding all of their foods
+INSERT INTO storage_location_table (location_id, location_name, kind, user_id)
+SELECT UUID(), 'Fridge', 'fridge', user_id
+FROM user_table;
+
+ALTER TABLE food_table ADD COLUMN location_id VARCHAR(40) NULL;
+
+UPDATE food_table f
+INNER JOIN storage_location_table l ON l.user_id = f.user_id
+SET f.location_id = l.location_id;
+
+ALTER TABLE food_table
+    MODIFY location_id VARCHAR(40) NOT NULL,
+    ADD INDEX food_location_id (location_id),
+    ADD CONSTRAINT fk_food_location FOREIGN KEY (location_id)
+        REFERENCES storage_location_table(location_id)
+        ON DELETE RESTRICT
+        ON UPDATE CASCADE;
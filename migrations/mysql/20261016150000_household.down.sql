@@ -0,0 +1,31 @@
+DROP INDEX location_id_exp ON food_table;
+CREATE INDEX usr_id_exp ON food_table (user_id, exp);
+
+-- foods and locations added by members who have since deleted their account have no owner to
+-- go back to
+DELETE FROM food_table
+WHERE user_id NOT IN (SELECT user_id FROM user_table);
+
+DELETE f FROM food_table f
+INNER JOIN storage_location_table l ON l.location_id = f.location_id
+WHERE l.user_id NOT IN (SELECT user_id FROM user_table);
+
+DELETE FROM storage_location_table
+WHERE user_id NOT IN (SELECT user_id FROM user_table);
+
+ALTER TABLE food_table
+    ADD CONSTRAINT fk_food_user FOREIGN KEY (user_id) REFERENCES user_table(user_id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE;
+
+ALTER TABLE storage_location_table
+    DROP FOREIGN KEY fk_location_household,
+    DROP INDEX location_household_id,
+    DROP COLUMN household_id,
+    ADD CONSTRAINT fk_location_user FOREIGN KEY (user_id) REFERENCES user_table(user_id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE;
+
+DROP TABLE household_invite_table;
+DROP TABLE household_member_table;
+DROP TABLE household_table;
@@ -0,0 +1,93 @@
+CREATE TABLE household_table (
+    id              INT AUTO_INCREMENT NOT NULL,
+    household_id    VARCHAR(40) NOT NULL,
+    household_name  VARCHAR(255) NOT NULL,
+    UNIQUE KEY household_id_idx (household_id),
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE household_member_table (
+    id              INT AUTO_INCREMENT NOT NULL,
+    household_id    VARCHAR(40) NOT NULL,
+    user_id         VARCHAR(40) NOT NULL,
+    role            VARCHAR(16) NOT NULL,
+    UNIQUE KEY member_household_user_idx (household_id, user_id),
+    INDEX member_usr_id (user_id),
+    CONSTRAINT fk_member_household FOREIGN KEY (household_id)
+        REFERENCES household_table(household_id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE,
+    CONSTRAINT fk_member_user FOREIGN KEY (user_id) REFERENCES user_table(user_id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE,
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE household_invite_table (
+    id              INT AUTO_INCREMENT NOT NULL,
+    token_hash      VARCHAR(64) NOT NULL,
+    household_id    VARCHAR(40) NOT NULL,
+    mail            VARCHAR(255) NOT NULL,
+    role            VARCHAR(16) NOT NULL,
+    expires_at      DATETIME NOT NULL,
+    UNIQUE KEY invite_token_hash_idx (token_hash),
+    CONSTRAINT fk_invite_household FOREIGN KEY (household_id)
+        REFERENCES household_table(household_id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE,
+    PRIMARY KEY (id)
+);
+
+-- every existing user becomes the owner of a household holding all of their locations
+ALTER TABLE household_table ADD COLUMN legacy_user_id VARCHAR(40) NULL;
+
+INSERT INTO household_table (household_id, household_name, legacy_user_id)
+SELECT UUID(), 'Home', user_id
+FROM user_table;
+
+INSERT INTO household_member_table (household_id, user_id, role)
+SELECT household_id, legacy_user_id, 'owner'
+FROM household_table;
+
+ALTER TABLE storage_location_table ADD COLUMN household_id VARCHAR(40) NULL;
+
+UPDATE storage_location_table l
+INNER JOIN household_table h ON h.legacy_user_id = l.user_id
+SET l.household_id = h.household_id;
+
+ALTER TABLE household_table DROP COLUMN legacy_user_id;
+
+ALTER TABLE storage_location_table
+    MODIFY household_id VARCHAR(40) NOT NULL,
+    ADD INDEX location_household_id (household_id),
+    ADD CONSTRAINT fk_location_household FOREIGN KEY (household_id)
+        REFERENCES household_table(household_id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE;
+
+-- foods and locations belong to the household now, `user_id` only records who added them,
+-- so a member deleting their account must not take shared foods down with them
+ALTER TABLE storage_location_table DROP FOREIGN KEY fk_location_user;
+
+-- the original schema left naming the key of food_table to MySQL, so it is looked up rather
+-- than guessed; a hand-made schema may not have it at all
+SET @food_user_fk = (
+    SELECT CONSTRAINT_NAME
+    FROM information_schema.KEY_COLUMN_USAGE
+    WHERE TABLE_SCHEMA = DATABASE()
+        AND TABLE_NAME = 'food_table'
+        AND COLUMN_NAME = 'user_id'
+        AND REFERENCED_TABLE_NAME = 'user_table'
+    LIMIT 1
+);
+SET @drop_food_user_fk = COALESCE(
+    CONCAT('ALTER TABLE food_table DROP FOREIGN KEY `', @food_user_fk, '`'),
+    'DO 0'
+);
+PREPARE drop_food_user_fk FROM @drop_food_user_fk;
+EXECUTE drop_food_user_fk;
+DEALLOCATE PREPARE drop_food_user_fk;
+
+-- reads go through the location now, nothing looks foods up by user any more
+DROP INDEX usr_id_exp ON food_table;
+CREATE INDEX location_id_exp ON food_table (location_id, exp);
@@ -0,0 +1 @@
+DROP INDEX user_mail_idx ON user_table;
@@ -0,0 +1,12 @@
+-- only a hash of the token is kept, the token itself is in the mail and nowhere else
+CREATE TABLE password_reset_table (
+    id          INT AUTO_INCREMENT NOT NULL,
+    token_hash  VARCHAR(64) NOT NULL,
+    user_id     VARCHAR(40) NOT NULL,
+    expires_at  DATETIME NOT NULL,
+    UNIQUE KEY reset_token_hash_idx (token_hash),
+    CONSTRAINT fk_reset_user FOREIGN KEY (user_id) REFERENCES user_table(user_id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE,
+    PRIMARY KEY (id)
+);
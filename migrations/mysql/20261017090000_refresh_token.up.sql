@@ -0,0 +1,16 @@
+-- one row per refresh token, used ones stay until their family expires or is revoked, so reuse
+-- of a token can be told apart from a token that never existed
+CREATE TABLE refresh_token_table (
+    id          INT AUTO_INCREMENT NOT NULL,
+    token_hash  VARCHAR(64) NOT NULL,
+    family_id   VARCHAR(40) NOT NULL,
+    user_id     VARCHAR(40) NOT NULL,
+    expires_at  DATETIME NOT NULL,
+    used        BOOLEAN NOT NULL DEFAULT FALSE,
+    UNIQUE KEY refresh_token_hash_idx (token_hash),
+    KEY refresh_family_idx (family_id),
+    CONSTRAINT fk_refresh_user FOREIGN KEY (user_id) REFERENCES user_table(user_id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE,
+    PRIMARY KEY (id)
+);
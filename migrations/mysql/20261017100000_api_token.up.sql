@@ -0,0 +1,18 @@
+-- personal API tokens, only the hash of the secret is kept; scopes are space separated
+CREATE TABLE api_token_table (
+    id          INT AUTO_INCREMENT NOT NULL,
+    token_id    VARCHAR(40) NOT NULL,
+    user_id     VARCHAR(40) NOT NULL,
+    token_name  VARCHAR(255) NOT NULL,
+    token_hash  VARCHAR(64) NOT NULL,
+    scopes      VARCHAR(255) NOT NULL,
+    created_at  DATETIME NOT NULL,
+    expires_at  DATETIME NULL,
+    UNIQUE KEY api_token_id_idx (token_id),
+    UNIQUE KEY api_token_hash_idx (token_hash),
+    INDEX api_token_usr_id (user_id),
+    CONSTRAINT fk_api_token_user FOREIGN KEY (user_id) REFERENCES user_table(user_id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE,
+    PRIMARY KEY (id)
+);
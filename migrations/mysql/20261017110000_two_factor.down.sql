@@ -0,0 +1,3 @@
+DROP TABLE login_challenge_table;
+DROP TABLE recovery_code_table;
+DROP TABLE totp_table;
@@ -0,0 +1,39 @@
+-- the TOTP secret of a user, pending until the first code confirms it; last_step is the time step
+-- of the last code accepted, so no code works twice
+CREATE TABLE totp_table (
+    id          INT AUTO_INCREMENT NOT NULL,
+    user_id     VARCHAR(40) NOT NULL,
+    secret      VARCHAR(64) NOT NULL,
+    enabled     BOOLEAN NOT NULL DEFAULT FALSE,
+    last_step   BIGINT NOT NULL DEFAULT 0,
+    UNIQUE KEY totp_usr_idx (user_id),
+    CONSTRAINT fk_totp_user FOREIGN KEY (user_id) REFERENCES user_table(user_id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE,
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE recovery_code_table (
+    id          INT AUTO_INCREMENT NOT NULL,
+    user_id     VARCHAR(40) NOT NULL,
+    code_hash   VARCHAR(64) NOT NULL,
+    UNIQUE KEY recovery_usr_code_idx (user_id, code_hash),
+    CONSTRAINT fk_recovery_user FOREIGN KEY (user_id) REFERENCES user_table(user_id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE,
+    PRIMARY KEY (id)
+);
+
+-- logins whose password was right, waiting for the second factor
+CREATE TABLE login_challenge_table (
+    id          INT AUTO_INCREMENT NOT NULL,
+    token_hash  VARCHAR(64) NOT NULL,
+    user_id     VARCHAR(40) NOT NULL,
+    expires_at  DATETIME NOT NULL,
+    UNIQUE KEY challenge_token_hash_idx (token_hash),
+    INDEX challenge_usr_id (user_id),
+    CONSTRAINT fk_challenge_user FOREIGN KEY (user_id) REFERENCES user_table(user_id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE,
+    PRIMARY KEY (id)
+);
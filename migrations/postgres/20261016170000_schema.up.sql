@@ -0,0 +1,79 @@
+-- Postgres starts out at the schema the MySQL migrations have built up to this point.
+CREATE TABLE user_table (
+    id          BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_id     UUID NOT NULL UNIQUE,
+    user_name   VARCHAR(255) NOT NULL,
+    mail        VARCHAR(255) NOT NULL,
+    password    TEXT NOT NULL
+);
+
+CREATE TABLE session_table (
+    id          BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    token_hash  VARCHAR(64) NOT NULL UNIQUE,
+    user_id     UUID NOT NULL
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    expires_at  TIMESTAMP NOT NULL
+);
+
+CREATE TABLE notification_preference_table (
+    id              BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_id         UUID NOT NULL UNIQUE
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    enabled         BOOLEAN NOT NULL DEFAULT FALSE,
+    send_time       TIME NOT NULL DEFAULT '08:00:00',
+    lead_days       INTEGER NOT NULL DEFAULT 3 CHECK (lead_days >= 0),
+    last_sent_on    DATE NULL
+);
+
+CREATE TABLE household_table (
+    id              BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    household_id    UUID NOT NULL UNIQUE,
+    household_name  VARCHAR(255) NOT NULL
+);
+
+CREATE TABLE household_member_table (
+    id              BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    household_id    UUID NOT NULL
+        REFERENCES household_table(household_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    user_id         UUID NOT NULL
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    role            VARCHAR(16) NOT NULL,
+    UNIQUE (household_id, user_id)
+);
+CREATE INDEX member_usr_id ON household_member_table (user_id);
+
+CREATE TABLE household_invite_table (
+    id              BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    token_hash      VARCHAR(64) NOT NULL UNIQUE,
+    household_id    UUID NOT NULL
+        REFERENCES household_table(household_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    mail            VARCHAR(255) NOT NULL,
+    role            VARCHAR(16) NOT NULL,
+    expires_at      TIMESTAMP NOT NULL
+);
+
+-- `user_id` only records who added a location or food, they belong to the household
+CREATE TABLE storage_location_table (
+    id              BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    location_id     UUID NOT NULL UNIQUE,
+    location_name   VARCHAR(255) NOT NULL,
+    kind            VARCHAR(16) NOT NULL,
+    temperature     DOUBLE PRECISION NULL,
+    user_id         UUID NOT NULL,
+    household_id    UUID NOT NULL
+        REFERENCES household_table(household_id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+CREATE INDEX location_household_id ON storage_location_table (household_id);
+
+CREATE TABLE food_table (
+    id          BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    food_id     UUID NOT NULL,
+    food_name   TEXT NOT NULL,
+    exp         DATE NOT NULL,
+    quantity    DOUBLE PRECISION NOT NULL DEFAULT 1,
+    unit        VARCHAR(16) NOT NULL DEFAULT 'count',
+    user_id     UUID NOT NULL,
+    location_id UUID NOT NULL
+        REFERENCES storage_location_table(location_id) ON DELETE RESTRICT ON UPDATE CASCADE
+);
+CREATE INDEX location_id_exp ON food_table (location_id, exp);
@@ -0,0 +1,6 @@
+-- mails are compared lowercased and trimmed from now on. Accounts that only differ in the case
+-- of their mail have to be merged by hand before this runs, the index refuses them otherwise.
+UPDATE user_table SET mail = LOWER(TRIM(mail));
+UPDATE household_invite_table SET mail = LOWER(TRIM(mail));
+
+CREATE UNIQUE INDEX user_mail_idx ON user_table (mail);
@@ -0,0 +1,8 @@
+-- only a hash of the token is kept, the token itself is in the mail and nowhere else
+CREATE TABLE password_reset_table (
+    id          BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    token_hash  VARCHAR(64) NOT NULL UNIQUE,
+    user_id     UUID NOT NULL
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    expires_at  TIMESTAMP NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE user_table DROP COLUMN mail_verified;
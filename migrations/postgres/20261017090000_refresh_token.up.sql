@@ -0,0 +1,12 @@
+-- one row per refresh token, used ones stay until their family expires or is revoked, so reuse
+-- of a token can be told apart from a token that never existed
+CREATE TABLE refresh_token_table (
+    id          BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    token_hash  VARCHAR(64) NOT NULL UNIQUE,
+    family_id   VARCHAR(40) NOT NULL,
+    user_id     UUID NOT NULL
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    expires_at  TIMESTAMP NOT NULL,
+    used        BOOLEAN NOT NULL DEFAULT FALSE
+);
+CREATE INDEX refresh_family_idx ON refresh_token_table (family_id);
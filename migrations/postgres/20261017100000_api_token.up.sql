@@ -0,0 +1,13 @@
+-- personal API tokens, only the hash of the secret is kept; scopes are space separated
+CREATE TABLE api_token_table (
+    id          BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    token_id    UUID NOT NULL UNIQUE,
+    user_id     UUID NOT NULL
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    token_name  VARCHAR(255) NOT NULL,
+    token_hash  VARCHAR(64) NOT NULL UNIQUE,
+    scopes      VARCHAR(255) NOT NULL,
+    created_at  TIMESTAMP NOT NULL,
+    expires_at  TIMESTAMP NULL
+);
+CREATE INDEX api_token_usr_id ON api_token_table (user_id);
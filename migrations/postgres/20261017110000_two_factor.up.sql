@@ -0,0 +1,28 @@
+-- the TOTP secret of a user, pending until the first code confirms it; last_step is the time step
+-- of the last code accepted, so no code works twice
+CREATE TABLE totp_table (
+    id          BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_id     UUID NOT NULL UNIQUE
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    secret      VARCHAR(64) NOT NULL,
+    enabled     BOOLEAN NOT NULL DEFAULT FALSE,
+    last_step   BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE TABLE recovery_code_table (
+    id          BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_id     UUID NOT NULL
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    code_hash   VARCHAR(64) NOT NULL,
+    UNIQUE (user_id, code_hash)
+);
+
+-- logins whose password was right, waiting for the second factor
+CREATE TABLE login_challenge_table (
+    id          BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    token_hash  VARCHAR(64) NOT NULL UNIQUE,
+    user_id     UUID NOT NULL
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    expires_at  TIMESTAMP NOT NULL
+);
+CREATE INDEX challenge_usr_id ON login_challenge_table (user_id);
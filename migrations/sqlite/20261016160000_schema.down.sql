@@ -0,0 +1,8 @@
+DROP TABLE food_table;
+DROP TABLE storage_location_table;
+DROP TABLE household_invite_table;
+DROP TABLE household_member_table;
+DROP TABLE household_table;
+DROP TABLE notification_preference_table;
+DROP TABLE session_table;
+DROP TABLE user_table;
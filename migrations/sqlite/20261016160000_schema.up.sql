@@ -0,0 +1,79 @@
+-- SQLite starts out at the schema the MySQL migrations have built up to this point.
+CREATE TABLE user_table (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id     TEXT NOT NULL UNIQUE,
+    user_name   TEXT NOT NULL,
+    mail        TEXT NOT NULL,
+    password    TEXT NOT NULL
+);
+
+CREATE TABLE session_table (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    token_hash  TEXT NOT NULL UNIQUE,
+    user_id     TEXT NOT NULL
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    expires_at  TEXT NOT NULL
+);
+
+CREATE TABLE notification_preference_table (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id         TEXT NOT NULL UNIQUE
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    enabled         BOOLEAN NOT NULL DEFAULT FALSE,
+    send_time       TEXT NOT NULL DEFAULT '08:00:00',
+    lead_days       INTEGER NOT NULL DEFAULT 3 CHECK (lead_days >= 0),
+    last_sent_on    TEXT NULL
+);
+
+CREATE TABLE household_table (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    household_id    TEXT NOT NULL UNIQUE,
+    household_name  TEXT NOT NULL
+);
+
+CREATE TABLE household_member_table (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    household_id    TEXT NOT NULL
+        REFERENCES household_table(household_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    user_id         TEXT NOT NULL
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    role            TEXT NOT NULL,
+    UNIQUE (household_id, user_id)
+);
+CREATE INDEX member_usr_id ON household_member_table (user_id);
+
+CREATE TABLE household_invite_table (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    token_hash      TEXT NOT NULL UNIQUE,
+    household_id    TEXT NOT NULL
+        REFERENCES household_table(household_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    mail            TEXT NOT NULL,
+    role            TEXT NOT NULL,
+    expires_at      TEXT NOT NULL
+);
+
+-- `user_id` only records who added a location or food, they belong to the household
+CREATE TABLE storage_location_table (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    location_id     TEXT NOT NULL UNIQUE,
+    location_name   TEXT NOT NULL,
+    kind            TEXT NOT NULL,
+    temperature     REAL NULL,
+    user_id         TEXT NOT NULL,
+    household_id    TEXT NOT NULL
+        REFERENCES household_table(household_id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+CREATE INDEX location_household_id ON storage_location_table (household_id);
+
+CREATE TABLE food_table (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    food_id     TEXT NOT NULL,
+    food_name   TEXT NOT NULL,
+    exp         TEXT NOT NULL,
+    quantity    REAL NOT NULL DEFAULT 1,
+    unit        TEXT NOT NULL DEFAULT 'count',
+    user_id     TEXT NOT NULL,
+    location_id TEXT NOT NULL
+        REFERENCES storage_location_table(location_id) ON DELETE RESTRICT ON UPDATE CASCADE
+);
+CREATE INDEX location_id_exp ON food_table (location_id, exp);
@@ -0,0 +1,8 @@
+-- only a hash of the token is kept, the token itself is in the mail and nowhere else
+CREATE TABLE password_reset_table (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    token_hash  TEXT NOT NULL UNIQUE,
+    user_id     TEXT NOT NULL
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    expires_at  TEXT NOT NULL
+);
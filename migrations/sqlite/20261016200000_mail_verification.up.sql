@@ -0,0 +1,3 @@
+-- accounts made before mails were verified keep working as they did
+ALTER TABLE user_table ADD COLUMN mail_verified BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE user_table SET mail_verified = TRUE;
@@ -0,0 +1,12 @@
+-- one row per refresh token, used ones stay until their family expires or is revoked, so reuse
+-- of a token can be told apart from a token that never existed
+CREATE TABLE refresh_token_table (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    token_hash  TEXT NOT NULL UNIQUE,
+    family_id   TEXT NOT NULL,
+    user_id     TEXT NOT NULL
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    expires_at  TEXT NOT NULL,
+    used        BOOLEAN NOT NULL DEFAULT FALSE
+);
+CREATE INDEX refresh_family_idx ON refresh_token_table (family_id);
@@ -0,0 +1,13 @@
+-- personal API tokens, only the hash of the secret is kept; scopes are space separated
+CREATE TABLE api_token_table (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    token_id    TEXT NOT NULL UNIQUE,
+    user_id     TEXT NOT NULL
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    token_name  TEXT NOT NULL,
+    token_hash  TEXT NOT NULL UNIQUE,
+    scopes      TEXT NOT NULL,
+    created_at  TEXT NOT NULL,
+    expires_at  TEXT NULL
+);
+CREATE INDEX api_token_usr_id ON api_token_table (user_id);
@@ -0,0 +1,28 @@
+-- the TOTP secret of a user, pending until the first code confirms it; last_step is the time step
+-- of the last code accepted, so no code works twice
+CREATE TABLE totp_table (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id     TEXT NOT NULL UNIQUE
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    secret      TEXT NOT NULL,
+    enabled     BOOLEAN NOT NULL DEFAULT FALSE,
+    last_step   INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE recovery_code_table (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id     TEXT NOT NULL
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    code_hash   TEXT NOT NULL,
+    UNIQUE (user_id, code_hash)
+);
+
+-- logins whose password was right, waiting for the second factor
+CREATE TABLE login_challenge_table (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    token_hash  TEXT NOT NULL UNIQUE,
+    user_id     TEXT NOT NULL
+        REFERENCES user_table(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    expires_at  TEXT NOT NULL
+);
+CREATE INDEX challenge_usr_id ON login_challenge_table (user_id);
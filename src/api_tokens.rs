@@ -0,0 +1,337 @@
+use std::fmt;
+
+use chrono::{DateTime, NaiveDateTime, SubsecRound, Utc};
+use hyper::Method;
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{
+    db::{classify, impl_from_row, impl_uuid_type, DbErrorKind},
+    users::{PubUserInfo, UserId},
+    util::{gen_random_token, hash_token},
+    validation::{trimmed_text, InvalidValue},
+};
+
+pub(crate) mod handler;
+pub(crate) mod memory;
+pub(crate) mod repo;
+
+// Every API token starts with this, so it is told apart from an access token at a glance, by the
+// server and by secret scanners alike.
+static TOKEN_PREFIX: &str = "fridge_";
+const MAX_TOKEN_NAME_CHARS: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ApiTokenId(String);
+
+impl_uuid_type!(ApiTokenId);
+
+impl<T: ToString> From<T> for ApiTokenId {
+    fn from(value: T) -> Self {
+        Self(value.to_string())
+    }
+}
+
+// What the token is for, so its owner recognises it in the list, like "receipt scanner".
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub struct TokenName(String);
+
+impl TryFrom<String> for TokenName {
+    type Error = InvalidValue;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        trimmed_text(&value, MAX_TOKEN_NAME_CHARS).map(Self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    #[serde(rename = "foods:read")]
+    FoodsRead,
+    #[serde(rename = "foods:write")]
+    FoodsWrite,
+    // Everything else a signed in user can do, apart from managing API tokens.
+    #[serde(rename = "account")]
+    Account,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::FoodsRead => "foods:read",
+            Scope::FoodsWrite => "foods:write",
+            Scope::Account => "account",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        [Scope::FoodsRead, Scope::FoodsWrite, Scope::Account]
+            .into_iter()
+            .find(|scope| scope.as_str() == value)
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Scopes are stored space separated in one column, like OAuth sends them.
+fn join_scopes(scopes: &[Scope]) -> String {
+    scopes
+        .iter()
+        .map(Scope::as_str)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn split_scopes(value: &str) -> Result<Vec<Scope>, sqlx::Error> {
+    value
+        .split_whitespace()
+        .map(|scope| {
+            Scope::parse(scope).ok_or_else(|| sqlx::Error::ColumnDecode {
+                index: "scopes".to_string(),
+                source: format!("unknown scope {}", scope).into(),
+            })
+        })
+        .collect()
+}
+
+// What a request may do: anything with a session cookie or an access token, only what its
+// scopes allow with an API token.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Access {
+    Full,
+    Scoped(Vec<Scope>),
+}
+
+impl Access {
+    // Whether a request for `segments` with `method` may go ahead.
+    pub(crate) fn check(&self, method: &Method, segments: &[&str]) -> Result<(), ApiTokenError> {
+        let Access::Scoped(scopes) = self else {
+            return Ok(());
+        };
+        let required = match segments {
+            // A leaked token must not be able to mint more tokens, turn 2FA on or off, or lock the
+            // owner out by changing the password or deleting the account.
+            ["users", "me", "tokens" | "2fa" | "password", ..] => {
+                return Err(ApiTokenError::SessionRequired)
+            }
+            ["users", "me"] if method == Method::DELETE => {
+                return Err(ApiTokenError::SessionRequired)
+            }
+            ["foods", ..] if method == Method::GET => Scope::FoodsRead,
+            ["foods", ..] => Scope::FoodsWrite,
+            _ => Scope::Account,
+        };
+        if scopes.contains(&required) {
+            Ok(())
+        } else {
+            Err(ApiTokenError::MissingScope(required))
+        }
+    }
+}
+
+// The token itself, shown once when it is created. Only its hash is stored.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ApiSecret(String);
+
+impl ApiSecret {
+    fn generate() -> Self {
+        Self(format!("{}{}", TOKEN_PREFIX, gen_random_token()))
+    }
+
+    // The bearer token as an API token, unless it is something else.
+    pub(crate) fn parse(bearer: &str) -> Option<Self> {
+        bearer
+            .starts_with(TOKEN_PREFIX)
+            .then(|| Self(bearer.to_string()))
+    }
+
+    pub(crate) fn hash(&self) -> String {
+        hash_token(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateApiTokenPayload {
+    pub(crate) name: TokenName,
+    pub(crate) scopes: Vec<Scope>,
+    // Never expires when left out.
+    #[serde(default)]
+    pub(crate) expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ApiToken {
+    token_id: ApiTokenId,
+    #[serde(skip)]
+    user_id: UserId,
+    name: TokenName,
+    scopes: Vec<Scope>,
+    #[serde(skip)]
+    token_hash: String,
+    created_at: NaiveDateTime,
+    expires_at: Option<NaiveDateTime>,
+}
+
+impl ApiToken {
+    // A token for the user and its secret, which has to go back to the user right away as it
+    // cannot be recovered later. `payload` must have been checked already.
+    pub(crate) fn new(user_id: UserId, payload: CreateApiTokenPayload) -> (Self, ApiSecret) {
+        let secret = ApiSecret::generate();
+        let token = Self {
+            token_id: ApiTokenId::from(Uuid::new_v4()),
+            user_id,
+            name: payload.name,
+            scopes: payload.scopes,
+            token_hash: secret.hash(),
+            // Whole seconds, as a MySQL DATETIME keeps them, so the token reads back as issued.
+            created_at: Utc::now().naive_utc().trunc_subsecs(0),
+            expires_at: payload.expires_at.map(|at| at.naive_utc().trunc_subsecs(0)),
+        };
+        (token, secret)
+    }
+
+    pub(crate) fn user_id(&self) -> &UserId {
+        &self.user_id
+    }
+}
+
+impl_from_row!(ApiToken, |row| {
+    Ok(ApiToken {
+        token_id: row.try_get("token_id")?,
+        user_id: row.try_get("user_id")?,
+        name: TokenName(row.try_get("token_name")?),
+        scopes: split_scopes(row.try_get("scopes")?)?,
+        token_hash: row.try_get("token_hash")?,
+        created_at: row.try_get("created_at")?,
+        expires_at: row.try_get("expires_at")?,
+    })
+});
+
+// A new token with its secret, the only time the secret is shown.
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuedApiToken {
+    #[serde(flatten)]
+    api_token: ApiToken,
+    token: ApiSecret,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AllApiTokens {
+    tokens: Vec<ApiToken>,
+}
+
+// Who a live token acts for and what it may do.
+#[derive(Debug, Clone)]
+pub(crate) struct TokenOwner {
+    pub(crate) user: PubUserInfo,
+    pub(crate) scopes: Vec<Scope>,
+}
+
+impl_from_row!(TokenOwner, |row| {
+    Ok(TokenOwner {
+        user: sqlx::FromRow::from_row(row)?,
+        scopes: split_scopes(row.try_get("scopes")?)?,
+    })
+});
+
+#[derive(Debug, Error)]
+pub enum ApiTokenError {
+    #[error("Not found")]
+    NotFound,
+    #[error("Invalid or expired API token")]
+    InvalidToken,
+    #[error("API token lacks the {0} scope")]
+    MissingScope(Scope),
+    #[error("Only a password login can do this")]
+    SessionRequired,
+    #[error("API token already exists")]
+    Duplicate(#[source] sqlx::Error),
+    #[error("Referenced record does not exist")]
+    ForeignKey(#[source] sqlx::Error),
+    #[error("Invalid API token")]
+    Validation(#[source] sqlx::Error),
+    #[error("Database unavailable")]
+    Unavailable(#[source] sqlx::Error),
+    #[error("Database error")]
+    Database(#[source] sqlx::Error),
+}
+
+impl From<sqlx::Error> for ApiTokenError {
+    fn from(value: sqlx::Error) -> Self {
+        match classify(&value) {
+            DbErrorKind::NotFound => ApiTokenError::NotFound,
+            DbErrorKind::UniqueViolation => ApiTokenError::Duplicate(value),
+            DbErrorKind::ForeignKeyViolation => ApiTokenError::ForeignKey(value),
+            DbErrorKind::Validation => ApiTokenError::Validation(value),
+            DbErrorKind::Unavailable => ApiTokenError::Unavailable(value),
+            DbErrorKind::Other => ApiTokenError::Database(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::Method;
+
+    use super::{join_scopes, split_scopes, Access, ApiSecret, ApiTokenError, Scope};
+
+    #[test]
+    fn test_scopes_round_trip() {
+        let scopes = vec![Scope::FoodsRead, Scope::Account];
+        assert_eq!(join_scopes(&scopes), "foods:read account");
+        assert_eq!(split_scopes("foods:read account").unwrap(), scopes);
+        assert!(split_scopes("foods:read admin").is_err());
+    }
+
+    #[test]
+    fn test_access_check() {
+        let access = Access::Scoped(vec![Scope::FoodsWrite]);
+        assert!(access.check(&Method::POST, &["foods"]).is_ok());
+        assert!(access.check(&Method::DELETE, &["foods", "id"]).is_ok());
+        assert!(matches!(
+            access.check(&Method::GET, &["foods"]),
+            Err(ApiTokenError::MissingScope(Scope::FoodsRead))
+        ));
+        assert!(matches!(
+            access.check(&Method::GET, &["users", "me"]),
+            Err(ApiTokenError::MissingScope(Scope::Account))
+        ));
+
+        let account = Access::Scoped(vec![Scope::Account]);
+        assert!(account.check(&Method::GET, &["locations"]).is_ok());
+        assert!(matches!(
+            account.check(&Method::POST, &["users", "me", "tokens"]),
+            Err(ApiTokenError::SessionRequired)
+        ));
+        assert!(matches!(
+            account.check(&Method::POST, &["users", "me", "2fa", "disable"]),
+            Err(ApiTokenError::SessionRequired)
+        ));
+        assert!(matches!(
+            account.check(&Method::PUT, &["users", "me", "password"]),
+            Err(ApiTokenError::SessionRequired)
+        ));
+        assert!(matches!(
+            account.check(&Method::DELETE, &["users", "me"]),
+            Err(ApiTokenError::SessionRequired)
+        ));
+        assert!(account.check(&Method::GET, &["users", "me"]).is_ok());
+        assert!(Access::Full
+            .check(&Method::POST, &["users", "me", "tokens"])
+            .is_ok());
+    }
+
+    #[test]
+    fn test_secret_prefix() {
+        let secret = ApiSecret::generate();
+        assert_eq!(ApiSecret::parse(&secret.0), Some(secret));
+        assert_eq!(ApiSecret::parse("eyJhbGciOiJIUzI1NiJ9.e30.sig"), None);
+    }
+}
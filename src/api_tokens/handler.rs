@@ -0,0 +1,79 @@
+use chrono::Utc;
+use hyper::{body::Bytes, StatusCode};
+
+use crate::{
+    server::{empty_response, json_response, parse_json, ApiError, AppState, HttpResponse},
+    users::PubUserInfo,
+};
+
+use super::{
+    AllApiTokens, ApiToken, ApiTokenError, ApiTokenId, CreateApiTokenPayload, IssuedApiToken,
+};
+
+impl From<ApiTokenError> for ApiError {
+    fn from(value: ApiTokenError) -> Self {
+        match value {
+            ApiTokenError::NotFound => ApiError::new(StatusCode::NOT_FOUND, value),
+            ApiTokenError::InvalidToken => ApiError::new(StatusCode::UNAUTHORIZED, value),
+            ApiTokenError::MissingScope(_) | ApiTokenError::SessionRequired => {
+                ApiError::new(StatusCode::FORBIDDEN, value)
+            }
+            ApiTokenError::Duplicate(_) | ApiTokenError::ForeignKey(_) => {
+                ApiError::new(StatusCode::CONFLICT, value)
+            }
+            ApiTokenError::Validation(_) => ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, value),
+            ApiTokenError::Unavailable(_) => {
+                ApiError::logged(StatusCode::SERVICE_UNAVAILABLE, &value)
+            }
+            ApiTokenError::Database(_) => {
+                ApiError::logged(StatusCode::INTERNAL_SERVER_ERROR, &value)
+            }
+        }
+    }
+}
+
+pub(crate) async fn create(
+    state: &AppState,
+    user: PubUserInfo,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let mut payload: CreateApiTokenPayload = parse_json(&body)?;
+    if payload.scopes.is_empty() {
+        return Err(ApiError::invalid_field("scopes", "must not be empty"));
+    }
+    payload.scopes.sort();
+    payload.scopes.dedup();
+    if payload.expires_at.is_some_and(|at| at <= Utc::now()) {
+        return Err(ApiError::invalid_field(
+            "expires_at",
+            "must be in the future",
+        ));
+    }
+
+    let (api_token, token) = ApiToken::new(user.user_id, payload);
+    state.api_tokens.insert(&api_token).await?;
+    Ok(json_response(
+        StatusCode::CREATED,
+        &IssuedApiToken { api_token, token },
+    ))
+}
+
+pub(crate) async fn read_all(
+    state: &AppState,
+    user: PubUserInfo,
+) -> Result<HttpResponse, ApiError> {
+    let tokens = state.api_tokens.read_all(&user.user_id).await?;
+    Ok(json_response(StatusCode::OK, &AllApiTokens { tokens }))
+}
+
+pub(crate) async fn delete(
+    state: &AppState,
+    user: PubUserInfo,
+    token_id: &str,
+) -> Result<HttpResponse, ApiError> {
+    state
+        .api_tokens
+        .delete(&user.user_id, &ApiTokenId::from(token_id))
+        .await?;
+    Ok(empty_response(StatusCode::NO_CONTENT))
+}
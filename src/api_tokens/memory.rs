@@ -0,0 +1,88 @@
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::{
+    memory::{ConstraintError, MemoryStore},
+    users::{PubUserInfo, UserId},
+};
+
+use super::{repo::ApiTokenStore, ApiSecret, ApiToken, ApiTokenError, ApiTokenId, TokenOwner};
+
+pub struct MemoryApiTokenRepository {
+    store: MemoryStore,
+}
+
+impl MemoryApiTokenRepository {
+    pub(crate) fn new(store: MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl ApiTokenStore for MemoryApiTokenRepository {
+    async fn insert(&self, token: &ApiToken) -> Result<(), ApiTokenError> {
+        let mut tables = self.store.lock();
+        if tables.user(&token.user_id).is_none() {
+            return Err(ConstraintError::foreign_key("api_token_usr_id").into());
+        }
+        if tables
+            .api_tokens
+            .iter()
+            .any(|stored| stored.token_id == token.token_id)
+        {
+            return Err(ConstraintError::unique("api_token_id_idx").into());
+        }
+        if tables
+            .api_tokens
+            .iter()
+            .any(|stored| stored.token_hash == token.token_hash)
+        {
+            return Err(ConstraintError::unique("api_token_hash_idx").into());
+        }
+        tables.api_tokens.push(token.clone());
+        Ok(())
+    }
+
+    async fn read_all(&self, user_id: &UserId) -> Result<Vec<ApiToken>, ApiTokenError> {
+        Ok(self
+            .store
+            .lock()
+            .api_tokens
+            .iter()
+            .filter(|token| &token.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete(&self, user_id: &UserId, token_id: &ApiTokenId) -> Result<(), ApiTokenError> {
+        let mut tables = self.store.lock();
+        let index = tables
+            .api_tokens
+            .iter()
+            .position(|token| &token.token_id == token_id && &token.user_id == user_id)
+            .ok_or(ApiTokenError::NotFound)?;
+        tables.api_tokens.remove(index);
+        Ok(())
+    }
+
+    async fn resolve(&self, secret: &ApiSecret) -> Result<TokenOwner, ApiTokenError> {
+        let tables = self.store.lock();
+        let now = Utc::now().naive_utc();
+        let token_hash = secret.hash();
+        let token = tables
+            .api_tokens
+            .iter()
+            .find(|token| {
+                token.token_hash == token_hash
+                    && token.expires_at.is_none_or(|expires_at| expires_at > now)
+            })
+            .ok_or(ApiTokenError::InvalidToken)?;
+        let user = tables
+            .user(&token.user_id)
+            .ok_or(ApiTokenError::InvalidToken)?;
+        Ok(TokenOwner {
+            user: PubUserInfo::from(user.clone()),
+            scopes: token.scopes.clone(),
+        })
+    }
+}
@@ -0,0 +1,209 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{query, query_as};
+
+use crate::{
+    db::{with_pool, DbPool},
+    users::UserId,
+};
+
+use super::{join_scopes, ApiSecret, ApiToken, ApiTokenError, ApiTokenId, TokenOwner};
+
+// What the handlers need from an API token backend, either `ApiTokenRepository` or
+// `MemoryApiTokenRepository`.
+#[async_trait]
+pub(crate) trait ApiTokenStore: Send + Sync {
+    async fn insert(&self, token: &ApiToken) -> Result<(), ApiTokenError>;
+    // Every token of the user, the expired ones too, oldest first.
+    async fn read_all(&self, user_id: &UserId) -> Result<Vec<ApiToken>, ApiTokenError>;
+    async fn delete(&self, user_id: &UserId, token_id: &ApiTokenId) -> Result<(), ApiTokenError>;
+    // Who the secret acts for, as long as its token is there and has not expired.
+    async fn resolve(&self, secret: &ApiSecret) -> Result<TokenOwner, ApiTokenError>;
+}
+
+pub struct ApiTokenRepository {
+    pool: DbPool,
+}
+
+impl ApiTokenRepository {
+    pub(crate) fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl ApiTokenStore for ApiTokenRepository {
+    async fn insert(&self, token: &ApiToken) -> Result<(), ApiTokenError> {
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    INSERT INTO api_token_table
+                    (token_id, user_id, token_name, token_hash, scopes, created_at, expires_at)
+                    VALUES (?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(&token.token_id)
+            .bind(&token.user_id)
+            .bind(&token.name.0)
+            .bind(&token.token_hash)
+            .bind(join_scopes(&token.scopes))
+            .bind(token.created_at)
+            .bind(token.expires_at)
+            .execute(pool)
+            .await?;
+        });
+        Ok(())
+    }
+
+    async fn read_all(&self, user_id: &UserId) -> Result<Vec<ApiToken>, ApiTokenError> {
+        let tokens = with_pool!(&self.pool, |pool| {
+            query_as::<_, ApiToken>(
+                r#"
+                    SELECT token_id, user_id, token_name, token_hash, scopes, created_at, expires_at
+                    FROM api_token_table
+                    WHERE user_id = ?
+                    ORDER BY id
+                "#,
+            )
+            .bind(user_id)
+            .fetch_all(pool)
+            .await
+        })?;
+        Ok(tokens)
+    }
+
+    async fn delete(&self, user_id: &UserId, token_id: &ApiTokenId) -> Result<(), ApiTokenError> {
+        let rows = with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    DELETE FROM api_token_table
+                    WHERE token_id = ? AND user_id = ?
+                "#,
+            )
+            .bind(token_id)
+            .bind(user_id)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        if rows == 0 {
+            return Err(ApiTokenError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn resolve(&self, secret: &ApiSecret) -> Result<TokenOwner, ApiTokenError> {
+        with_pool!(&self.pool, |pool| {
+            query_as::<_, TokenOwner>(
+                r#"
+                    SELECT u.user_id, u.user_name, t.scopes
+                    FROM api_token_table t
+                    INNER JOIN user_table u ON u.user_id = t.user_id
+                    WHERE t.token_hash = ? AND (t.expires_at IS NULL OR t.expires_at > ?)
+                "#,
+            )
+            .bind(secret.hash())
+            .bind(Utc::now().naive_utc())
+            .fetch_optional(pool)
+            .await
+        })?
+        .ok_or(ApiTokenError::InvalidToken)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+    use uuid::Uuid;
+
+    use crate::{
+        api_tokens::{ApiToken, ApiTokenError, CreateApiTokenPayload, Scope, TokenName},
+        testing::TestDb,
+        users::UserId,
+    };
+
+    fn payload(name: &str, scopes: Vec<Scope>) -> CreateApiTokenPayload {
+        CreateApiTokenPayload {
+            name: TokenName::try_from(name.to_string()).unwrap(),
+            scopes,
+            expires_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_token_lifecycle() {
+        for db in TestDb::each().await {
+            let repo = db.api_tokens();
+
+            let (token, secret) = ApiToken::new(
+                db.alice.user_id.clone(),
+                payload("receipt scanner", vec![Scope::FoodsRead, Scope::FoodsWrite]),
+            );
+            repo.insert(&token).await.unwrap();
+            let (other, _) = ApiToken::new(
+                db.bob.user_id.clone(),
+                payload("dashboard", vec![Scope::Account]),
+            );
+            repo.insert(&other).await.unwrap();
+
+            let owner = repo.resolve(&secret).await.unwrap();
+            assert_eq!(owner.user.user_id, db.alice.user_id);
+            assert_eq!(owner.scopes, vec![Scope::FoodsRead, Scope::FoodsWrite]);
+            assert_eq!(
+                repo.read_all(&db.alice.user_id).await.unwrap(),
+                vec![token.clone()]
+            );
+
+            // Only the owner deletes a token.
+            assert!(matches!(
+                repo.delete(&db.bob.user_id, &token.token_id).await,
+                Err(ApiTokenError::NotFound)
+            ));
+            repo.delete(&db.alice.user_id, &token.token_id)
+                .await
+                .unwrap();
+            assert!(matches!(
+                repo.resolve(&secret).await,
+                Err(ApiTokenError::InvalidToken)
+            ));
+            assert!(matches!(
+                repo.delete(&db.alice.user_id, &token.token_id).await,
+                Err(ApiTokenError::NotFound)
+            ));
+            assert_eq!(repo.read_all(&db.bob.user_id).await.unwrap(), vec![other]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_expired_token() {
+        for db in TestDb::each().await {
+            let repo = db.api_tokens();
+
+            let mut expired = payload("old script", vec![Scope::Account]);
+            expired.expires_at = Some(Utc::now() - Duration::minutes(1));
+            let (token, secret) = ApiToken::new(db.alice.user_id.clone(), expired);
+            repo.insert(&token).await.unwrap();
+
+            assert!(matches!(
+                repo.resolve(&secret).await,
+                Err(ApiTokenError::InvalidToken)
+            ));
+            // Still listed, so its owner sees what stopped working.
+            assert_eq!(repo.read_all(&db.alice.user_id).await.unwrap().len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_token_of_unknown_user() {
+        for db in TestDb::each().await {
+            let (token, _) = ApiToken::new(
+                UserId::from(Uuid::new_v4().to_string()),
+                payload("nobody", vec![Scope::Account]),
+            );
+            assert!(matches!(
+                db.api_tokens().insert(&token).await,
+                Err(ApiTokenError::ForeignKey(_))
+            ));
+        }
+    }
+}
@@ -1 +1,300 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use thiserror::Error;
+use uuid::Uuid;
 
+use crate::{
+    db::{classify, impl_from_row, DbErrorKind},
+    mail::{MailError, OutgoingMail},
+    users::{Mail, Password, UserId},
+    util::{gen_random_token, hash_token},
+};
+
+use token::TokenError;
+
+pub(crate) mod handler;
+pub(crate) mod memory;
+pub(crate) mod repo;
+pub mod token;
+
+pub(crate) static SESSION_COOKIE: &str = "session_id";
+const SESSION_TTL_DAYS: i64 = 7;
+const RESET_TTL_MINUTES: i64 = 60;
+const REFRESH_TTL_DAYS: i64 = 30;
+static RESET_SUBJECT: &str = "Reset your fridge password";
+
+// The session cookie. Like every other bearer secret only its hash is stored, so a leaked
+// database holds no live sessions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionId(String);
+
+impl SessionId {
+    pub(crate) fn hash(&self) -> String {
+        hash_token(&self.0)
+    }
+}
+
+impl<T: ToString> From<T> for SessionId {
+    fn from(value: T) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl From<SessionId> for String {
+    fn from(value: SessionId) -> Self {
+        value.0
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LoginPayload {
+    mail: Mail,
+    password: Password,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    session_id: SessionId,
+    user_id: UserId,
+    expires_at: NaiveDateTime,
+}
+
+impl Session {
+    pub(crate) fn new(user_id: UserId) -> Self {
+        Self {
+            session_id: SessionId(gen_random_token()),
+            user_id,
+            expires_at: Utc::now().naive_utc() + Duration::days(SESSION_TTL_DAYS),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn session_id(&self) -> &SessionId {
+        &self.session_id
+    }
+
+    pub(crate) fn user_id(&self) -> &UserId {
+        &self.user_id
+    }
+
+    pub(crate) fn cookie(&self) -> String {
+        format!(
+            "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Strict",
+            SESSION_COOKIE,
+            self.session_id.0,
+            Duration::days(SESSION_TTL_DAYS).num_seconds()
+        )
+    }
+
+    pub(crate) fn removal_cookie() -> String {
+        format!(
+            "{}=; Path=/; Max-Age=0; HttpOnly; SameSite=Strict",
+            SESSION_COOKIE
+        )
+    }
+}
+
+// Handed out by mail to reset a forgotten password. The token only exists in the mail, what is
+// stored is its hash, so a leaked database does not let anybody reset passwords.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ResetToken(String);
+
+impl ResetToken {
+    pub(crate) fn hash(&self) -> String {
+        hash_token(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResetRequestPayload {
+    mail: Mail,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResetPasswordPayload {
+    token: ResetToken,
+    new_password: Password,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasswordReset {
+    token_hash: String,
+    user_id: UserId,
+    expires_at: NaiveDateTime,
+}
+
+impl PasswordReset {
+    // A reset for the user and the token that redeems it, which has to go out by mail right away
+    // as it cannot be recovered later.
+    pub(crate) fn new(user_id: UserId) -> (Self, ResetToken) {
+        let token = ResetToken(gen_random_token());
+        let reset = Self {
+            token_hash: token.hash(),
+            user_id,
+            expires_at: Utc::now().naive_utc() + Duration::minutes(RESET_TTL_MINUTES),
+        };
+        (reset, token)
+    }
+
+    pub(crate) fn user_id(&self) -> &UserId {
+        &self.user_id
+    }
+
+    pub(crate) fn mail(&self, token: &ResetToken, to: Mail) -> OutgoingMail {
+        let body = format!(
+            "Hi,\n\nsomebody, hopefully you, asked to reset the password of your fridge account.\n\
+             Choose a new password with this token before {} UTC:\n\n{}\n\n\
+             If it was not you, ignore this mail and your password stays as it is.\n",
+            self.expires_at.format("%Y-%m-%d %H:%M"),
+            token.0,
+        );
+        OutgoingMail {
+            to,
+            subject: RESET_SUBJECT.to_string(),
+            body,
+        }
+    }
+}
+
+// Trades for a new access token and a new refresh token, once. Like a reset token only its hash
+// is stored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RefreshToken(String);
+
+impl RefreshToken {
+    pub(crate) fn hash(&self) -> String {
+        hash_token(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RefreshPayload {
+    refresh_token: RefreshToken,
+}
+
+// What a client holding tokens instead of a cookie gets on login and on every refresh.
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenResponse {
+    access_token: String,
+    token_type: &'static str,
+    expires_in: i64,
+    refresh_token: RefreshToken,
+}
+
+impl TokenResponse {
+    pub(crate) fn new(access_token: String, expires_in: i64, refresh_token: RefreshToken) -> Self {
+        Self {
+            access_token,
+            token_type: "Bearer",
+            expires_in,
+            refresh_token,
+        }
+    }
+}
+
+// One refresh token as stored. Each refresh replaces the token with the next one of the same
+// family and keeps the used one around, so a token that shows up a second time gives away that
+// it was copied, and the whole family is revoked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefreshGrant {
+    token_hash: String,
+    family_id: String,
+    user_id: UserId,
+    expires_at: NaiveDateTime,
+    used: bool,
+}
+
+impl RefreshGrant {
+    // The first grant of a new family, on login.
+    pub(crate) fn new(user_id: UserId) -> (Self, RefreshToken) {
+        Self::issue(Uuid::new_v4().to_string(), user_id)
+    }
+
+    // The grant that replaces this one.
+    pub(crate) fn next(&self) -> (Self, RefreshToken) {
+        Self::issue(self.family_id.clone(), self.user_id.clone())
+    }
+
+    fn issue(family_id: String, user_id: UserId) -> (Self, RefreshToken) {
+        let token = RefreshToken(gen_random_token());
+        let grant = Self {
+            token_hash: token.hash(),
+            family_id,
+            user_id,
+            expires_at: Utc::now().naive_utc() + Duration::days(REFRESH_TTL_DAYS),
+            used: false,
+        };
+        (grant, token)
+    }
+
+    pub(crate) fn user_id(&self) -> &UserId {
+        &self.user_id
+    }
+}
+
+impl_from_row!(RefreshGrant, |row| {
+    Ok(RefreshGrant {
+        token_hash: row.try_get("token_hash")?,
+        family_id: row.try_get("family_id")?,
+        user_id: row.try_get("user_id")?,
+        expires_at: row.try_get("expires_at")?,
+        used: row.try_get("used")?,
+    })
+});
+
+// Stored login material for a user, looked up by mail on login.
+#[derive(Debug, Clone)]
+pub(crate) struct Credential {
+    user_id: UserId,
+    password: Password,
+}
+
+#[cfg(test)]
+impl Credential {
+    pub(crate) fn user_id(&self) -> &UserId {
+        &self.user_id
+    }
+
+    pub(crate) fn password(&self) -> &Password {
+        &self.password
+    }
+}
+
+impl_from_row!(Credential, |row| {
+    Ok(Credential {
+        user_id: row.try_get("user_id")?,
+        password: row.try_get("password")?,
+    })
+});
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("Invalid mail or password")]
+    InvalidCredential,
+    #[error("Unauthorized")]
+    Unauthorized,
+    #[error("Invalid or expired reset token")]
+    InvalidResetToken,
+    #[error("Invalid or expired refresh token")]
+    InvalidRefreshToken,
+    #[error("Refresh token was used before, sign in again")]
+    RefreshTokenReused,
+    #[error(transparent)]
+    Token(#[from] TokenError),
+    #[error("Database unavailable")]
+    Unavailable(#[source] sqlx::Error),
+    #[error("Database error")]
+    Database(#[source] sqlx::Error),
+    #[error(transparent)]
+    Mail(#[from] MailError),
+}
+
+impl From<sqlx::Error> for AuthError {
+    fn from(value: sqlx::Error) -> Self {
+        match classify(&value) {
+            DbErrorKind::Unavailable => AuthError::Unavailable(value),
+            _ => AuthError::Database(value),
+        }
+    }
+}
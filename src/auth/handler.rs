@@ -0,0 +1,298 @@
+use hyper::{
+    body::Bytes,
+    header::{self, HeaderValue},
+    HeaderMap, StatusCode,
+};
+
+use crate::{
+    api_tokens::{Access, ApiSecret},
+    server::{empty_response, json_response, parse_json, ApiError, AppState, HttpResponse},
+    two_factor::{self, SecondFactorPayload},
+    users::{handler::check_new_password, Password, PubUserInfo, UserError, UserId},
+};
+
+use super::{
+    token::TokenError, AuthError, LoginPayload, PasswordReset, RefreshGrant, RefreshPayload,
+    RefreshToken, ResetPasswordPayload, ResetRequestPayload, Session, SessionId, TokenResponse,
+    SESSION_COOKIE,
+};
+
+impl From<AuthError> for ApiError {
+    fn from(value: AuthError) -> Self {
+        match value {
+            AuthError::InvalidCredential
+            | AuthError::Unauthorized
+            | AuthError::InvalidRefreshToken
+            | AuthError::RefreshTokenReused
+            | AuthError::Token(TokenError::Invalid) => {
+                ApiError::new(StatusCode::UNAUTHORIZED, value)
+            }
+            AuthError::InvalidResetToken => ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, value),
+            AuthError::Mail(_) => ApiError::logged(StatusCode::BAD_GATEWAY, &value),
+            AuthError::Unavailable(_) => ApiError::logged(StatusCode::SERVICE_UNAVAILABLE, &value),
+            AuthError::Database(_) | AuthError::Token(_) => {
+                ApiError::logged(StatusCode::INTERNAL_SERVER_ERROR, &value)
+            }
+        }
+    }
+}
+
+pub(crate) fn session_id(headers: &HeaderMap) -> Option<SessionId> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| SessionId::from(value))
+}
+
+fn with_cookie(mut res: HttpResponse, cookie: String) -> HttpResponse {
+    if let Ok(value) = HeaderValue::from_str(&cookie) {
+        res.headers_mut().insert(header::SET_COOKIE, value);
+    }
+    res
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+// Resolves the API token, the access token or else the session cookie of a request into the user
+// it belongs to and what it may do. An access token is checked without the store, but the user
+// may have been deleted since.
+pub(crate) async fn authenticate(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<(PubUserInfo, Access), ApiError> {
+    if let Some(token) = bearer_token(headers) {
+        if let Some(secret) = ApiSecret::parse(token) {
+            let owner = state.api_tokens.resolve(&secret).await?;
+            return Ok((owner.user, Access::Scoped(owner.scopes)));
+        }
+        let user_id = state.tokens.check(token).map_err(AuthError::from)?;
+        return match state.users.read(&user_id).await {
+            Ok(user) => Ok((user, Access::Full)),
+            Err(UserError::NotFound) => Err(AuthError::Unauthorized.into()),
+            Err(e) => Err(e.into()),
+        };
+    }
+    let session_id = session_id(headers).ok_or(AuthError::Unauthorized)?;
+    Ok((state.sessions.read(&session_id).await?, Access::Full))
+}
+
+// The user `payload` signs in as, if the password is right.
+async fn check_login(state: &AppState, payload: LoginPayload) -> Result<UserId, ApiError> {
+    let credential = match state.sessions.credential(&payload.mail).await {
+        Err(AuthError::InvalidCredential) => {
+            // Checks the password all the same, so an unknown mail is not turned down any faster
+            // than a wrong password.
+            payload
+                .password
+                .matches(&Password::dummy(&state.hasher), &state.hasher);
+            return Err(AuthError::InvalidCredential.into());
+        }
+        credential => credential?,
+    };
+    if !payload
+        .password
+        .matches(&credential.password, &state.hasher)
+    {
+        return Err(AuthError::InvalidCredential.into());
+    }
+    // The password is at hand only now, so this is when a hash from older settings gets redone.
+    // The login goes ahead with the old hash if that fails.
+    if credential.password.is_outdated(&state.hasher) {
+        match payload.password.hashed(&state.hasher) {
+            Ok(password) => {
+                if let Err(e) = state
+                    .users
+                    .set_password(&credential.user_id, &password)
+                    .await
+                {
+                    eprintln!("cannot store rehashed password: {}", e);
+                }
+            }
+            Err(e) => eprintln!("cannot rehash password: {}", e),
+        }
+    }
+    Ok(credential.user_id)
+}
+
+// Signs in with a session cookie, or answers with a challenge for `login_second_factor` when the
+// user has 2FA on.
+pub(crate) async fn login(state: &AppState, body: Bytes) -> Result<HttpResponse, ApiError> {
+    let payload: LoginPayload = parse_json(&body)?;
+    let user_id = check_login(state, payload).await?;
+    if let Some(res) = two_factor::handler::challenge(state, &user_id).await? {
+        return Ok(res);
+    }
+    start_session(state, user_id).await
+}
+
+pub(crate) async fn login_second_factor(
+    state: &AppState,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let payload: SecondFactorPayload = parse_json(&body)?;
+    let user_id = two_factor::handler::second_factor(state, payload).await?;
+    start_session(state, user_id).await
+}
+
+async fn start_session(state: &AppState, user_id: UserId) -> Result<HttpResponse, ApiError> {
+    let session = Session::new(user_id);
+    state.sessions.insert(&session).await?;
+    let user = state.sessions.read(&session.session_id).await?;
+
+    Ok(with_cookie(
+        json_response(StatusCode::OK, &user),
+        session.cookie(),
+    ))
+}
+
+async fn issue_tokens(
+    state: &AppState,
+    grant: RefreshGrant,
+    token: RefreshToken,
+) -> Result<HttpResponse, ApiError> {
+    state.sessions.insert_refresh(&grant).await?;
+    let access_token = state
+        .tokens
+        .sign(grant.user_id())
+        .map_err(AuthError::from)?;
+    let res = TokenResponse::new(access_token, state.tokens.ttl().num_seconds(), token);
+    Ok(json_response(StatusCode::OK, &res))
+}
+
+// Login for clients that cannot keep a cookie: an access token to send as `Authorization: Bearer`
+// and a refresh token to get the next one with. Users with 2FA get a challenge for
+// `token_second_factor` first.
+pub(crate) async fn token(state: &AppState, body: Bytes) -> Result<HttpResponse, ApiError> {
+    let payload: LoginPayload = parse_json(&body)?;
+    let user_id = check_login(state, payload).await?;
+    if let Some(res) = two_factor::handler::challenge(state, &user_id).await? {
+        return Ok(res);
+    }
+    let (grant, token) = RefreshGrant::new(user_id);
+    issue_tokens(state, grant, token).await
+}
+
+pub(crate) async fn token_second_factor(
+    state: &AppState,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let payload: SecondFactorPayload = parse_json(&body)?;
+    let user_id = two_factor::handler::second_factor(state, payload).await?;
+    let (grant, token) = RefreshGrant::new(user_id);
+    issue_tokens(state, grant, token).await
+}
+
+pub(crate) async fn refresh(state: &AppState, body: Bytes) -> Result<HttpResponse, ApiError> {
+    let payload: RefreshPayload = parse_json(&body)?;
+    let grant = state.sessions.take_refresh(&payload.refresh_token).await?;
+    let (next, token) = grant.next();
+    issue_tokens(state, next, token).await
+}
+
+// The logout of token clients. Access tokens already handed out stay valid until they expire.
+pub(crate) async fn revoke(state: &AppState, body: Bytes) -> Result<HttpResponse, ApiError> {
+    let payload: RefreshPayload = parse_json(&body)?;
+    state
+        .sessions
+        .revoke_refresh(&payload.refresh_token)
+        .await?;
+    Ok(empty_response(StatusCode::NO_CONTENT))
+}
+
+// Mails a reset token if the mail belongs to a user. The answer is the same either way, so
+// nobody can find out this way who has an account.
+pub(crate) async fn request_reset(state: &AppState, body: Bytes) -> Result<HttpResponse, ApiError> {
+    let payload: ResetRequestPayload = parse_json(&body)?;
+    let mailer = state.mailer.as_ref().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "mail is not configured on this server",
+        )
+    })?;
+
+    let user = match state.users.read_by_mail(&payload.mail).await {
+        Ok(user) => user,
+        Err(UserError::NotFound) => return Ok(empty_response(StatusCode::ACCEPTED)),
+        Err(e) => return Err(e.into()),
+    };
+    let (reset, token) = PasswordReset::new(user.user_id);
+    state.sessions.insert_reset(&reset).await?;
+    mailer
+        .send(&reset.mail(&token, payload.mail))
+        .await
+        .map_err(AuthError::from)?;
+    Ok(empty_response(StatusCode::ACCEPTED))
+}
+
+// Sets a new password with a token from `request_reset`. Whoever held on to a session of the
+// user is signed out, in case the old password was in the wrong hands.
+pub(crate) async fn reset_password(
+    state: &AppState,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let payload: ResetPasswordPayload = parse_json(&body)?;
+    check_new_password(state, "new_password", &payload.new_password)?;
+
+    let user_id = state.sessions.take_reset(&payload.token).await?;
+    let password = payload
+        .new_password
+        .hashed(&state.hasher)
+        .map_err(|e| ApiError::logged(StatusCode::INTERNAL_SERVER_ERROR, &e))?;
+    state.users.set_password(&user_id, &password).await?;
+    state.sessions.revoke_all(&user_id).await?;
+    Ok(empty_response(StatusCode::NO_CONTENT))
+}
+
+pub(crate) async fn logout(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(session_id) = session_id(headers) {
+        state.sessions.delete(&session_id).await?;
+    }
+    Ok(with_cookie(
+        empty_response(StatusCode::NO_CONTENT),
+        Session::removal_cookie(),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::{
+        header::{self, HeaderValue},
+        HeaderMap,
+    };
+
+    use super::session_id;
+    use crate::auth::SessionId;
+
+    #[test]
+    fn test_session_id_from_cookie() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("theme=dark; session_id=abc123; lang=ja"),
+        );
+        assert_eq!(session_id(&headers), Some(SessionId::from("abc123")));
+    }
+
+    #[test]
+    fn test_session_id_missing() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(session_id(&headers), None);
+
+        headers.insert(header::COOKIE, HeaderValue::from_static("theme=dark"));
+        assert_eq!(session_id(&headers), None);
+    }
+}
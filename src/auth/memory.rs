@@ -0,0 +1,196 @@
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::{
+    memory::{ConstraintError, MemoryStore},
+    users::{Mail, PubUserInfo, UserId},
+    RepositoryTargetReader,
+};
+
+use super::{
+    repo::SessionStore, AuthError, Credential, PasswordReset, RefreshGrant, RefreshToken,
+    ResetToken, Session, SessionId,
+};
+
+pub struct MemorySessionRepository {
+    store: MemoryStore,
+}
+
+impl MemorySessionRepository {
+    pub(crate) fn new(store: MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionRepository {
+    async fn credential(&self, mail: &Mail) -> Result<Credential, AuthError> {
+        let tables = self.store.lock();
+        let user = tables
+            .users
+            .iter()
+            .find(|user| user.mail() == mail)
+            .ok_or(AuthError::InvalidCredential)?;
+        Ok(Credential {
+            user_id: user.user_id().clone(),
+            password: user.password().clone(),
+        })
+    }
+
+    async fn insert(&self, session: &Session) -> Result<(), AuthError> {
+        let mut tables = self.store.lock();
+        let now = Utc::now().naive_utc();
+        tables.sessions.retain(|session| session.expires_at > now);
+
+        if tables.user(&session.user_id).is_none() {
+            return Err(ConstraintError::foreign_key("session_usr_id").into());
+        }
+        if tables
+            .sessions
+            .iter()
+            .any(|stored| stored.session_id == session.session_id)
+        {
+            return Err(ConstraintError::unique("session_token_hash_idx").into());
+        }
+        tables.sessions.push(session.clone());
+        Ok(())
+    }
+
+    async fn delete(&self, id: &SessionId) -> Result<(), AuthError> {
+        self.store
+            .lock()
+            .sessions
+            .retain(|session| &session.session_id != id);
+        Ok(())
+    }
+
+    async fn insert_reset(&self, reset: &PasswordReset) -> Result<(), AuthError> {
+        let mut tables = self.store.lock();
+        let now = Utc::now().naive_utc();
+        tables
+            .password_resets
+            .retain(|reset| reset.expires_at > now);
+
+        if tables.user(&reset.user_id).is_none() {
+            return Err(ConstraintError::foreign_key("reset_usr_id").into());
+        }
+        if tables
+            .password_resets
+            .iter()
+            .any(|stored| stored.token_hash == reset.token_hash)
+        {
+            return Err(ConstraintError::unique("reset_token_hash_idx").into());
+        }
+        tables.password_resets.push(reset.clone());
+        Ok(())
+    }
+
+    async fn take_reset(&self, token: &ResetToken) -> Result<UserId, AuthError> {
+        let mut tables = self.store.lock();
+        let token_hash = token.hash();
+        let index = tables
+            .password_resets
+            .iter()
+            .position(|reset| reset.token_hash == token_hash)
+            .ok_or(AuthError::InvalidResetToken)?;
+        let reset = tables.password_resets.remove(index);
+        if reset.expires_at <= Utc::now().naive_utc() {
+            return Err(AuthError::InvalidResetToken);
+        }
+        Ok(reset.user_id)
+    }
+
+    async fn insert_refresh(&self, grant: &RefreshGrant) -> Result<(), AuthError> {
+        let mut tables = self.store.lock();
+        let now = Utc::now().naive_utc();
+        tables.refresh_grants.retain(|grant| grant.expires_at > now);
+
+        if tables.user(&grant.user_id).is_none() {
+            return Err(ConstraintError::foreign_key("refresh_usr_id").into());
+        }
+        if tables
+            .refresh_grants
+            .iter()
+            .any(|stored| stored.token_hash == grant.token_hash)
+        {
+            return Err(ConstraintError::unique("refresh_token_hash_idx").into());
+        }
+        tables.refresh_grants.push(grant.clone());
+        Ok(())
+    }
+
+    async fn take_refresh(&self, token: &RefreshToken) -> Result<RefreshGrant, AuthError> {
+        let mut tables = self.store.lock();
+        let token_hash = token.hash();
+        let grant = tables
+            .refresh_grants
+            .iter_mut()
+            .find(|grant| grant.token_hash == token_hash)
+            .ok_or(AuthError::InvalidRefreshToken)?;
+        if grant.used {
+            let family_id = grant.family_id.clone();
+            tables
+                .refresh_grants
+                .retain(|grant| grant.family_id != family_id);
+            return Err(AuthError::RefreshTokenReused);
+        }
+        if grant.expires_at <= Utc::now().naive_utc() {
+            return Err(AuthError::InvalidRefreshToken);
+        }
+        grant.used = true;
+        Ok(grant.clone())
+    }
+
+    async fn revoke_refresh(&self, token: &RefreshToken) -> Result<(), AuthError> {
+        let mut tables = self.store.lock();
+        let token_hash = token.hash();
+        if let Some(family_id) = tables
+            .refresh_grants
+            .iter()
+            .find(|grant| grant.token_hash == token_hash)
+            .map(|grant| grant.family_id.clone())
+        {
+            tables
+                .refresh_grants
+                .retain(|grant| grant.family_id != family_id);
+        }
+        Ok(())
+    }
+
+    async fn revoke_others(
+        &self,
+        user_id: &UserId,
+        keep: Option<&SessionId>,
+    ) -> Result<(), AuthError> {
+        let mut tables = self.store.lock();
+        tables
+            .refresh_grants
+            .retain(|grant| &grant.user_id != user_id);
+        tables
+            .sessions
+            .retain(|session| &session.user_id != user_id || Some(&session.session_id) == keep);
+        tables
+            .password_resets
+            .retain(|reset| &reset.user_id != user_id);
+        tables.api_tokens.retain(|token| token.user_id() != user_id);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'a> RepositoryTargetReader<'a, SessionId> for MemorySessionRepository {
+    type QueryRes = PubUserInfo;
+    type QueryErr = AuthError;
+
+    async fn read(&self, id: &'a SessionId) -> Result<Self::QueryRes, Self::QueryErr> {
+        let tables = self.store.lock();
+        let now = Utc::now().naive_utc();
+        tables
+            .sessions
+            .iter()
+            .find(|session| &session.session_id == id && session.expires_at > now)
+            .and_then(|session| tables.user(&session.user_id))
+            .map(|user| PubUserInfo::from(user.clone()))
+            .ok_or(AuthError::Unauthorized)
+    }
+}
@@ -0,0 +1,628 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{query, query_as, query_scalar};
+
+use crate::{
+    db::{with_pool, DbPool},
+    users::{Mail, PubUserInfo, UserId},
+    RepositoryTargetReader,
+};
+
+use super::{
+    AuthError, Credential, PasswordReset, RefreshGrant, RefreshToken, ResetToken, Session,
+    SessionId,
+};
+
+// What the handlers need from a session backend, either `SessionRepository` or
+// `MemorySessionRepository`. `read` resolves a live session into its user.
+#[async_trait]
+pub(crate) trait SessionStore:
+    for<'a> RepositoryTargetReader<'a, SessionId, QueryRes = PubUserInfo, QueryErr = AuthError>
+    + Send
+    + Sync
+{
+    async fn credential(&self, mail: &Mail) -> Result<Credential, AuthError>;
+    async fn insert(&self, session: &Session) -> Result<(), AuthError>;
+    async fn delete(&self, id: &SessionId) -> Result<(), AuthError>;
+    async fn insert_reset(&self, reset: &PasswordReset) -> Result<(), AuthError>;
+    // Redeems a reset token: the user it was issued for if it is still valid, and the token is
+    // gone afterwards either way so it works only once.
+    async fn take_reset(&self, token: &ResetToken) -> Result<UserId, AuthError>;
+    async fn insert_refresh(&self, grant: &RefreshGrant) -> Result<(), AuthError>;
+    // Redeems a refresh token: its grant if the token is valid and was not used before, marked
+    // used from now on. A token used before revokes its whole family.
+    async fn take_refresh(&self, token: &RefreshToken) -> Result<RefreshGrant, AuthError>;
+    // Revokes the family of the token, if there is one.
+    async fn revoke_refresh(&self, token: &RefreshToken) -> Result<(), AuthError>;
+    // Signs the user out everywhere, API tokens included, and voids the resets still pending.
+    async fn revoke_all(&self, user_id: &UserId) -> Result<(), AuthError> {
+        self.revoke_others(user_id, None).await
+    }
+    // Like `revoke_all`, but keeps the session `keep` the request came with.
+    async fn revoke_others(
+        &self,
+        user_id: &UserId,
+        keep: Option<&SessionId>,
+    ) -> Result<(), AuthError>;
+}
+
+pub struct SessionRepository {
+    pool: DbPool,
+}
+
+impl SessionRepository {
+    pub(crate) fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    async fn refresh_grant(&self, token_hash: &str) -> Result<Option<RefreshGrant>, AuthError> {
+        let grant = with_pool!(&self.pool, |pool| {
+            query_as(
+                r#"
+                    SELECT token_hash, family_id, user_id, expires_at, used
+                    FROM refresh_token_table
+                    WHERE token_hash = ?
+                "#,
+            )
+            .bind(token_hash)
+            .fetch_optional(pool)
+            .await?
+        });
+        Ok(grant)
+    }
+
+    async fn revoke_family(&self, family_id: &str) -> Result<(), AuthError> {
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    DELETE FROM refresh_token_table
+                    WHERE family_id = ?
+                "#,
+            )
+            .bind(family_id)
+            .execute(pool)
+            .await?;
+        });
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionStore for SessionRepository {
+    async fn credential(&self, mail: &Mail) -> Result<Credential, AuthError> {
+        with_pool!(&self.pool, |pool| {
+            query_as::<_, Credential>(
+                r#"
+                    SELECT user_id, password
+                    FROM user_table
+                    WHERE mail = ?
+                "#,
+            )
+            .bind(mail)
+            .fetch_optional(pool)
+            .await
+        })?
+        .ok_or(AuthError::InvalidCredential)
+    }
+
+    async fn insert(&self, session: &Session) -> Result<(), AuthError> {
+        // Expired sessions are never resolved again, so drop them while we are here.
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    DELETE FROM session_table
+                    WHERE expires_at <= ?
+                "#,
+            )
+            .bind(Utc::now().naive_utc())
+            .execute(pool)
+            .await?;
+        });
+
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    INSERT INTO session_table
+                    (token_hash, user_id, expires_at)
+                    VALUES (?, ?, ?)
+                "#,
+            )
+            .bind(session.session_id.hash())
+            .bind(&session.user_id)
+            .bind(session.expires_at)
+            .execute(pool)
+            .await?;
+        });
+        Ok(())
+    }
+
+    async fn delete(&self, id: &SessionId) -> Result<(), AuthError> {
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    DELETE FROM session_table
+                    WHERE token_hash = ?
+                "#,
+            )
+            .bind(id.hash())
+            .execute(pool)
+            .await?;
+        });
+        Ok(())
+    }
+
+    async fn insert_reset(&self, reset: &PasswordReset) -> Result<(), AuthError> {
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    DELETE FROM password_reset_table
+                    WHERE expires_at <= ?
+                "#,
+            )
+            .bind(Utc::now().naive_utc())
+            .execute(pool)
+            .await?;
+        });
+
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    INSERT INTO password_reset_table
+                    (token_hash, user_id, expires_at)
+                    VALUES (?, ?, ?)
+                "#,
+            )
+            .bind(&reset.token_hash)
+            .bind(&reset.user_id)
+            .bind(reset.expires_at)
+            .execute(pool)
+            .await?;
+        });
+        Ok(())
+    }
+
+    async fn take_reset(&self, token: &ResetToken) -> Result<UserId, AuthError> {
+        let token_hash = token.hash();
+        let user_id = with_pool!(&self.pool, |pool| {
+            query_scalar::<_, UserId>(
+                r#"
+                    SELECT user_id
+                    FROM password_reset_table
+                    WHERE token_hash = ? AND expires_at > ?
+                "#,
+            )
+            .bind(&token_hash)
+            .bind(Utc::now().naive_utc())
+            .fetch_optional(pool)
+            .await?
+        });
+
+        // Only the request that gets to delete the row may use it, two racing ones do not both
+        // get through.
+        let rows = with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    DELETE FROM password_reset_table
+                    WHERE token_hash = ?
+                "#,
+            )
+            .bind(&token_hash)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        match user_id {
+            Some(user_id) if rows > 0 => Ok(user_id),
+            _ => Err(AuthError::InvalidResetToken),
+        }
+    }
+
+    async fn insert_refresh(&self, grant: &RefreshGrant) -> Result<(), AuthError> {
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    DELETE FROM refresh_token_table
+                    WHERE expires_at <= ?
+                "#,
+            )
+            .bind(Utc::now().naive_utc())
+            .execute(pool)
+            .await?;
+        });
+
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    INSERT INTO refresh_token_table
+                    (token_hash, family_id, user_id, expires_at, used)
+                    VALUES (?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(&grant.token_hash)
+            .bind(&grant.family_id)
+            .bind(&grant.user_id)
+            .bind(grant.expires_at)
+            .bind(grant.used)
+            .execute(pool)
+            .await?;
+        });
+        Ok(())
+    }
+
+    async fn take_refresh(&self, token: &RefreshToken) -> Result<RefreshGrant, AuthError> {
+        let grant = self
+            .refresh_grant(&token.hash())
+            .await?
+            .ok_or(AuthError::InvalidRefreshToken)?;
+        if grant.used {
+            self.revoke_family(&grant.family_id).await?;
+            return Err(AuthError::RefreshTokenReused);
+        }
+        if grant.expires_at <= Utc::now().naive_utc() {
+            return Err(AuthError::InvalidRefreshToken);
+        }
+
+        // Of two requests racing with the same token only one gets to mark it, the other one is
+        // as suspicious as a later reuse.
+        let rows = with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    UPDATE refresh_token_table
+                    SET used = TRUE
+                    WHERE token_hash = ? AND used = FALSE
+                "#,
+            )
+            .bind(&grant.token_hash)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        if rows == 0 {
+            self.revoke_family(&grant.family_id).await?;
+            return Err(AuthError::RefreshTokenReused);
+        }
+        Ok(RefreshGrant {
+            used: true,
+            ..grant
+        })
+    }
+
+    // MySQL cannot delete from a table it selects from in the same statement, so the family is
+    // looked up first.
+    async fn revoke_refresh(&self, token: &RefreshToken) -> Result<(), AuthError> {
+        if let Some(grant) = self.refresh_grant(&token.hash()).await? {
+            self.revoke_family(&grant.family_id).await?;
+        }
+        Ok(())
+    }
+
+    async fn revoke_others(
+        &self,
+        user_id: &UserId,
+        keep: Option<&SessionId>,
+    ) -> Result<(), AuthError> {
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    DELETE FROM refresh_token_table
+                    WHERE user_id = ?
+                "#,
+            )
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+        });
+
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    DELETE FROM password_reset_table
+                    WHERE user_id = ?
+                "#,
+            )
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+        });
+
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    DELETE FROM api_token_table
+                    WHERE user_id = ?
+                "#,
+            )
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+        });
+
+        match keep {
+            Some(keep) => with_pool!(&self.pool, |pool| {
+                query(
+                    r#"
+                        DELETE FROM session_table
+                        WHERE user_id = ? AND token_hash <> ?
+                    "#,
+                )
+                .bind(user_id)
+                .bind(keep.hash())
+                .execute(pool)
+                .await?;
+            }),
+            None => with_pool!(&self.pool, |pool| {
+                query(
+                    r#"
+                        DELETE FROM session_table
+                        WHERE user_id = ?
+                    "#,
+                )
+                .bind(user_id)
+                .execute(pool)
+                .await?;
+            }),
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'a> RepositoryTargetReader<'a, SessionId> for SessionRepository {
+    type QueryRes = PubUserInfo;
+    type QueryErr = AuthError;
+
+    async fn read(&self, id: &'a SessionId) -> Result<Self::QueryRes, Self::QueryErr> {
+        with_pool!(&self.pool, |pool| {
+            query_as::<_, PubUserInfo>(
+                r#"
+                    SELECT u.user_id, u.user_name
+                    FROM session_table s
+                    INNER JOIN user_table u ON u.user_id = s.user_id
+                    WHERE s.token_hash = ? AND s.expires_at > ?
+                "#,
+            )
+            .bind(id.hash())
+            .bind(Utc::now().naive_utc())
+            .fetch_optional(pool)
+            .await
+        })?
+        .ok_or(AuthError::Unauthorized)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use crate::{
+        api_tokens::{ApiToken, ApiTokenError, CreateApiTokenPayload, Scope, TokenName},
+        auth::{AuthError, PasswordReset, RefreshGrant, Session},
+        testing::{TestDb, FIXTURE_PASSWORD},
+        users::Mail,
+        util::HashConfig,
+    };
+
+    #[tokio::test]
+    async fn test_credential_by_mail() {
+        for db in TestDb::each().await {
+            let repo = db.sessions();
+
+            let credential = repo.credential(&db.alice.mail).await.unwrap();
+            assert_eq!(credential.user_id, db.alice.user_id);
+            HashConfig::default()
+                .verify(FIXTURE_PASSWORD, &String::from(credential.password))
+                .unwrap();
+
+            assert!(matches!(
+                repo.credential(&Mail::try_from("nobody@mail.com").unwrap())
+                    .await,
+                Err(AuthError::InvalidCredential)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_lifecycle() {
+        for db in TestDb::each().await {
+            let repo = db.sessions();
+
+            let session = Session::new(db.alice.user_id.clone());
+            repo.insert(&session).await.unwrap();
+
+            let user_info = repo.read(&session.session_id).await.unwrap();
+            assert_eq!(user_info.user_id, session.user_id);
+
+            repo.delete(&session.session_id).await.unwrap();
+            assert!(matches!(
+                repo.read(&session.session_id).await,
+                Err(AuthError::Unauthorized)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reset_token_works_once() {
+        for db in TestDb::each().await {
+            let repo = db.sessions();
+
+            let (reset, token) = PasswordReset::new(db.alice.user_id.clone());
+            repo.insert_reset(&reset).await.unwrap();
+
+            assert_eq!(repo.take_reset(&token).await.unwrap(), db.alice.user_id);
+            assert!(matches!(
+                repo.take_reset(&token).await,
+                Err(AuthError::InvalidResetToken)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_expired_reset_token() {
+        for db in TestDb::each().await {
+            let repo = db.sessions();
+
+            let (mut reset, token) = PasswordReset::new(db.alice.user_id.clone());
+            reset.expires_at = Utc::now().naive_utc() - Duration::minutes(1);
+            repo.insert_reset(&reset).await.unwrap();
+
+            assert!(matches!(
+                repo.take_reset(&token).await,
+                Err(AuthError::InvalidResetToken)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_rotation() {
+        for db in TestDb::each().await {
+            let repo = db.sessions();
+
+            let (grant, token) = RefreshGrant::new(db.alice.user_id.clone());
+            repo.insert_refresh(&grant).await.unwrap();
+            let taken = repo.take_refresh(&token).await.unwrap();
+            assert_eq!(taken.user_id, db.alice.user_id);
+            assert!(taken.used);
+
+            let (next, next_token) = taken.next();
+            assert_eq!(next.family_id, grant.family_id);
+            repo.insert_refresh(&next).await.unwrap();
+
+            // The first token again: somebody kept a copy, so the live token goes too.
+            assert!(matches!(
+                repo.take_refresh(&token).await,
+                Err(AuthError::RefreshTokenReused)
+            ));
+            assert!(matches!(
+                repo.take_refresh(&next_token).await,
+                Err(AuthError::InvalidRefreshToken)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_revoke_refresh() {
+        for db in TestDb::each().await {
+            let repo = db.sessions();
+
+            let (grant, token) = RefreshGrant::new(db.alice.user_id.clone());
+            repo.insert_refresh(&grant).await.unwrap();
+            let (other, other_token) = RefreshGrant::new(db.alice.user_id.clone());
+            repo.insert_refresh(&other).await.unwrap();
+            let (mut expired, expired_token) = RefreshGrant::new(db.alice.user_id.clone());
+            expired.expires_at = Utc::now().naive_utc() - Duration::minutes(1);
+            repo.insert_refresh(&expired).await.unwrap();
+
+            repo.revoke_refresh(&token).await.unwrap();
+            assert!(matches!(
+                repo.take_refresh(&token).await,
+                Err(AuthError::InvalidRefreshToken)
+            ));
+            assert!(matches!(
+                repo.take_refresh(&expired_token).await,
+                Err(AuthError::InvalidRefreshToken)
+            ));
+            // Other logins of the same user stay.
+            repo.take_refresh(&other_token).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_revoke_all() {
+        for db in TestDb::each().await {
+            let repo = db.sessions();
+
+            let session = Session::new(db.alice.user_id.clone());
+            repo.insert(&session).await.unwrap();
+            let other = Session::new(db.bob.user_id.clone());
+            repo.insert(&other).await.unwrap();
+            let (reset, token) = PasswordReset::new(db.alice.user_id.clone());
+            repo.insert_reset(&reset).await.unwrap();
+            let (grant, refresh_token) = RefreshGrant::new(db.alice.user_id.clone());
+            repo.insert_refresh(&grant).await.unwrap();
+            let payload = CreateApiTokenPayload {
+                name: TokenName::try_from("script".to_string()).unwrap(),
+                scopes: vec![Scope::Account],
+                expires_at: None,
+            };
+            let (api_token, secret) = ApiToken::new(db.alice.user_id.clone(), payload);
+            db.api_tokens().insert(&api_token).await.unwrap();
+
+            repo.revoke_all(&db.alice.user_id).await.unwrap();
+            assert!(matches!(
+                db.api_tokens().resolve(&secret).await,
+                Err(ApiTokenError::InvalidToken)
+            ));
+            assert!(matches!(
+                repo.take_refresh(&refresh_token).await,
+                Err(AuthError::InvalidRefreshToken)
+            ));
+            assert!(matches!(
+                repo.read(&session.session_id).await,
+                Err(AuthError::Unauthorized)
+            ));
+            assert!(matches!(
+                repo.take_reset(&token).await,
+                Err(AuthError::InvalidResetToken)
+            ));
+            assert_eq!(
+                repo.read(&other.session_id).await.unwrap().user_id,
+                db.bob.user_id
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_revoke_others() {
+        for db in TestDb::each().await {
+            let repo = db.sessions();
+
+            let current = Session::new(db.alice.user_id.clone());
+            repo.insert(&current).await.unwrap();
+            let session = Session::new(db.alice.user_id.clone());
+            repo.insert(&session).await.unwrap();
+            let (grant, refresh_token) = RefreshGrant::new(db.alice.user_id.clone());
+            repo.insert_refresh(&grant).await.unwrap();
+            let (reset, reset_token) = PasswordReset::new(db.alice.user_id.clone());
+            repo.insert_reset(&reset).await.unwrap();
+            let payload = CreateApiTokenPayload {
+                name: TokenName::try_from("script".to_string()).unwrap(),
+                scopes: vec![Scope::Account],
+                expires_at: None,
+            };
+            let (api_token, secret) = ApiToken::new(db.alice.user_id.clone(), payload);
+            db.api_tokens().insert(&api_token).await.unwrap();
+
+            repo.revoke_others(&db.alice.user_id, Some(&current.session_id))
+                .await
+                .unwrap();
+            // A reset link mailed before the change must not undo it.
+            assert!(matches!(
+                repo.take_reset(&reset_token).await,
+                Err(AuthError::InvalidResetToken)
+            ));
+            assert!(matches!(
+                db.api_tokens().resolve(&secret).await,
+                Err(ApiTokenError::InvalidToken)
+            ));
+            assert!(matches!(
+                repo.read(&session.session_id).await,
+                Err(AuthError::Unauthorized)
+            ));
+            assert!(matches!(
+                repo.take_refresh(&refresh_token).await,
+                Err(AuthError::InvalidRefreshToken)
+            ));
+            assert_eq!(
+                repo.read(&current.session_id).await.unwrap().user_id,
+                db.alice.user_id
+            );
+
+            // Without a session to keep, all of them go.
+            repo.revoke_others(&db.alice.user_id, None).await.unwrap();
+            assert!(matches!(
+                repo.read(&current.session_id).await,
+                Err(AuthError::Unauthorized)
+            ));
+        }
+    }
+}
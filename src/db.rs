@@ -0,0 +1,306 @@
+use sqlx::{error::ErrorKind, mysql::MySqlPoolOptions, MySql, Pool};
+#[cfg(feature = "postgres")]
+use sqlx::{postgres::PgPoolOptions, Postgres};
+#[cfg(feature = "sqlite")]
+use {
+    sqlx::{
+        sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
+        Sqlite,
+    },
+    std::str::FromStr,
+};
+
+#[cfg(feature = "postgres")]
+pub(crate) mod postgres;
+
+// Connection pool of whichever backend `DATABASE_URL` points at.
+#[derive(Debug, Clone)]
+pub enum DbPool {
+    MySql(Pool<MySql>),
+    #[cfg(feature = "sqlite")]
+    Sqlite(Pool<Sqlite>),
+    #[cfg(feature = "postgres")]
+    Postgres(Pool<Postgres>),
+}
+
+impl DbPool {
+    // Picks the backend by the scheme of `url`, e.g. `mysql://...` or `sqlite://fridge.db`.
+    pub async fn connect(url: &str) -> Result<Self, sqlx::Error> {
+        let scheme = url.split_once(':').map(|(scheme, _)| scheme);
+        match scheme {
+            Some("mysql" | "mariadb") => {
+                Ok(DbPool::MySql(MySqlPoolOptions::new().connect(url).await?))
+            }
+            #[cfg(feature = "sqlite")]
+            Some("sqlite") => {
+                let options = SqliteConnectOptions::from_str(url)?
+                    .create_if_missing(true)
+                    .foreign_keys(true)
+                    .journal_mode(SqliteJournalMode::Wal);
+                Ok(DbPool::Sqlite(
+                    SqlitePoolOptions::new().connect_with(options).await?,
+                ))
+            }
+            #[cfg(feature = "postgres")]
+            Some("postgres" | "postgresql") => {
+                Ok(DbPool::Postgres(PgPoolOptions::new().connect(url).await?))
+            }
+            _ => Err(sqlx::Error::Configuration(
+                format!(
+                    "unsupported DATABASE_URL scheme {}, is the matching cargo feature enabled?",
+                    scheme.unwrap_or_default()
+                )
+                .into(),
+            )),
+        }
+    }
+}
+
+impl From<Pool<MySql>> for DbPool {
+    fn from(value: Pool<MySql>) -> Self {
+        DbPool::MySql(value)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<Pool<Sqlite>> for DbPool {
+    fn from(value: Pool<Sqlite>) -> Self {
+        DbPool::Sqlite(value)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<Pool<Postgres>> for DbPool {
+    fn from(value: Pool<Postgres>) -> Self {
+        DbPool::Postgres(value)
+    }
+}
+
+// Runs `$body` once per backend with `$pool` bound to the concrete pool, so the same query code
+// compiles against every enabled driver. The closure-like syntax is only there to keep rustfmt
+// working on the body, `?` and `return` apply to the enclosing function.
+//
+// Queries are written with `?` placeholders. For Postgres `query`, `query_as` and `query_scalar`
+// are shadowed by versions that number them.
+macro_rules! with_pool {
+    ($db:expr, |$pool:ident| $body:expr) => {
+        match $db {
+            $crate::db::DbPool::MySql($pool) => $body,
+            #[cfg(feature = "sqlite")]
+            $crate::db::DbPool::Sqlite($pool) => $body,
+            #[cfg(feature = "postgres")]
+            $crate::db::DbPool::Postgres($pool) => {
+                #[allow(unused_imports)]
+                use $crate::db::postgres::{query, query_as, query_scalar};
+                $body
+            }
+        }
+    };
+}
+pub(crate) use with_pool;
+
+// Implements `FromRow` for the row type of every enabled backend from one body, with `$row` bound
+// to the row being decoded.
+macro_rules! impl_from_row {
+    ($ty:ty, |$row:ident| $body:block) => {
+        impl sqlx::FromRow<'_, sqlx::mysql::MySqlRow> for $ty {
+            fn from_row($row: &sqlx::mysql::MySqlRow) -> Result<Self, sqlx::Error> {
+                $body
+            }
+        }
+
+        #[cfg(feature = "sqlite")]
+        impl sqlx::FromRow<'_, sqlx::sqlite::SqliteRow> for $ty {
+            fn from_row($row: &sqlx::sqlite::SqliteRow) -> Result<Self, sqlx::Error> {
+                $body
+            }
+        }
+
+        #[cfg(feature = "postgres")]
+        impl sqlx::FromRow<'_, sqlx::postgres::PgRow> for $ty {
+            fn from_row($row: &sqlx::postgres::PgRow) -> Result<Self, sqlx::Error> {
+                $body
+            }
+        }
+    };
+}
+pub(crate) use impl_from_row;
+
+// Ids are UUIDs kept as strings by the domain types. MySQL and SQLite store them as text, Postgres
+// in its native `UUID` type.
+macro_rules! impl_uuid_type {
+    ($ty:ident) => {
+        impl sqlx::Type<sqlx::MySql> for $ty {
+            fn type_info() -> sqlx::mysql::MySqlTypeInfo {
+                <String as sqlx::Type<sqlx::MySql>>::type_info()
+            }
+
+            fn compatible(ty: &sqlx::mysql::MySqlTypeInfo) -> bool {
+                <String as sqlx::Type<sqlx::MySql>>::compatible(ty)
+            }
+        }
+
+        impl sqlx::Encode<'_, sqlx::MySql> for $ty {
+            fn encode_by_ref(
+                &self,
+                buf: &mut Vec<u8>,
+            ) -> Result<sqlx::encode::IsNull, sqlx::error::BoxDynError> {
+                <String as sqlx::Encode<sqlx::MySql>>::encode_by_ref(&self.0, buf)
+            }
+        }
+
+        impl sqlx::Decode<'_, sqlx::MySql> for $ty {
+            fn decode(
+                value: sqlx::mysql::MySqlValueRef<'_>,
+            ) -> Result<Self, sqlx::error::BoxDynError> {
+                <String as sqlx::Decode<sqlx::MySql>>::decode(value).map(Self)
+            }
+        }
+
+        #[cfg(feature = "sqlite")]
+        impl sqlx::Type<sqlx::Sqlite> for $ty {
+            fn type_info() -> sqlx::sqlite::SqliteTypeInfo {
+                <String as sqlx::Type<sqlx::Sqlite>>::type_info()
+            }
+
+            fn compatible(ty: &sqlx::sqlite::SqliteTypeInfo) -> bool {
+                <String as sqlx::Type<sqlx::Sqlite>>::compatible(ty)
+            }
+        }
+
+        #[cfg(feature = "sqlite")]
+        impl<'q> sqlx::Encode<'q, sqlx::Sqlite> for $ty {
+            fn encode_by_ref(
+                &self,
+                buf: &mut Vec<sqlx::sqlite::SqliteArgumentValue<'q>>,
+            ) -> Result<sqlx::encode::IsNull, sqlx::error::BoxDynError> {
+                <String as sqlx::Encode<sqlx::Sqlite>>::encode_by_ref(&self.0, buf)
+            }
+        }
+
+        #[cfg(feature = "sqlite")]
+        impl sqlx::Decode<'_, sqlx::Sqlite> for $ty {
+            fn decode(
+                value: sqlx::sqlite::SqliteValueRef<'_>,
+            ) -> Result<Self, sqlx::error::BoxDynError> {
+                <String as sqlx::Decode<sqlx::Sqlite>>::decode(value).map(Self)
+            }
+        }
+
+        #[cfg(feature = "postgres")]
+        impl sqlx::Type<sqlx::Postgres> for $ty {
+            fn type_info() -> sqlx::postgres::PgTypeInfo {
+                <uuid::Uuid as sqlx::Type<sqlx::Postgres>>::type_info()
+            }
+        }
+
+        #[cfg(feature = "postgres")]
+        impl sqlx::Encode<'_, sqlx::Postgres> for $ty {
+            fn encode_by_ref(
+                &self,
+                buf: &mut sqlx::postgres::PgArgumentBuffer,
+            ) -> Result<sqlx::encode::IsNull, sqlx::error::BoxDynError> {
+                let uuid = uuid::Uuid::parse_str(&self.0)?;
+                <uuid::Uuid as sqlx::Encode<sqlx::Postgres>>::encode_by_ref(&uuid, buf)
+            }
+        }
+
+        #[cfg(feature = "postgres")]
+        impl sqlx::Decode<'_, sqlx::Postgres> for $ty {
+            fn decode(
+                value: sqlx::postgres::PgValueRef<'_>,
+            ) -> Result<Self, sqlx::error::BoxDynError> {
+                <uuid::Uuid as sqlx::Decode<sqlx::Postgres>>::decode(value)
+                    .map(|uuid| Self(uuid.to_string()))
+            }
+        }
+    };
+}
+pub(crate) use impl_uuid_type;
+
+// Unsigned counts are `INT UNSIGNED` in MySQL, which sqlx only decodes into unsigned integers, while
+// Postgres has no unsigned integers at all.
+pub(crate) trait UnsignedColumn {
+    fn try_get_u32(&self, column: &str) -> Result<u32, sqlx::Error>;
+}
+
+impl UnsignedColumn for sqlx::mysql::MySqlRow {
+    fn try_get_u32(&self, column: &str) -> Result<u32, sqlx::Error> {
+        sqlx::Row::try_get(self, column)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl UnsignedColumn for sqlx::sqlite::SqliteRow {
+    fn try_get_u32(&self, column: &str) -> Result<u32, sqlx::Error> {
+        sqlx::Row::try_get(self, column)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl UnsignedColumn for sqlx::postgres::PgRow {
+    fn try_get_u32(&self, column: &str) -> Result<u32, sqlx::Error> {
+        let value: i32 = sqlx::Row::try_get(self, column)?;
+        u32::try_from(value).map_err(|e| sqlx::Error::ColumnDecode {
+            index: column.to_string(),
+            source: e.into(),
+        })
+    }
+}
+
+// Coarse classification of sqlx failures shared by every repository error type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum DbErrorKind {
+    NotFound,
+    UniqueViolation,
+    ForeignKeyViolation,
+    Validation,
+    Unavailable,
+    Other,
+}
+
+pub(crate) fn classify(err: &sqlx::Error) -> DbErrorKind {
+    match err {
+        sqlx::Error::RowNotFound => DbErrorKind::NotFound,
+        // Only ids fail to encode, when Postgres is handed one that is not a UUID. No record can
+        // have such an id.
+        sqlx::Error::Encode(_) => DbErrorKind::NotFound,
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => DbErrorKind::Unavailable,
+        sqlx::Error::Database(db_err) => match db_err.kind() {
+            ErrorKind::UniqueViolation => DbErrorKind::UniqueViolation,
+            ErrorKind::ForeignKeyViolation => DbErrorKind::ForeignKeyViolation,
+            ErrorKind::NotNullViolation | ErrorKind::CheckViolation => DbErrorKind::Validation,
+            // SQLSTATE class 22 is "data exception", e.g. a value too long for its column.
+            _ if db_err.code().is_some_and(|code| code.starts_with("22")) => {
+                DbErrorKind::Validation
+            }
+            _ => DbErrorKind::Other,
+        },
+        _ => DbErrorKind::Other,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{classify, DbErrorKind};
+
+    #[test]
+    fn test_classify_connection_errors() {
+        assert_eq!(classify(&sqlx::Error::RowNotFound), DbErrorKind::NotFound);
+        assert_eq!(
+            classify(&sqlx::Error::PoolTimedOut),
+            DbErrorKind::Unavailable
+        );
+
+        let io_err = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+        assert_eq!(classify(&sqlx::Error::Io(io_err)), DbErrorKind::Unavailable);
+        assert_eq!(
+            classify(&sqlx::Error::ColumnNotFound("exp".to_string())),
+            DbErrorKind::Other
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{Mutex, OnceLock},
+};
+
+use sqlx::{
+    query::{Query, QueryAs, QueryScalar},
+    Database, FromRow,
+};
+
+// Stand-ins for `sqlx::query*` that `with_pool!` puts in scope for Postgres, which wants `$1, $2,
+// ...` where the queries say `?`.
+pub(crate) fn query<DB>(sql: &str) -> Query<'_, DB, <DB as Database>::Arguments<'_>>
+where
+    DB: Database,
+{
+    sqlx::query(numbered(sql))
+}
+
+pub(crate) fn query_as<'q, DB, O>(
+    sql: &'q str,
+) -> QueryAs<'q, DB, O, <DB as Database>::Arguments<'q>>
+where
+    DB: Database,
+    O: for<'r> FromRow<'r, DB::Row>,
+{
+    sqlx::query_as(numbered(sql))
+}
+
+pub(crate) fn query_scalar<'q, DB, O>(
+    sql: &'q str,
+) -> QueryScalar<'q, DB, O, <DB as Database>::Arguments<'q>>
+where
+    DB: Database,
+    (O,): for<'r> FromRow<'r, DB::Row>,
+{
+    sqlx::query_scalar(numbered(sql))
+}
+
+// Every query is a literal, so each is rewritten once and kept for the life of the process.
+fn numbered(sql: &str) -> &'static str {
+    static CACHE: OnceLock<Mutex<HashMap<String, &'static str>>> = OnceLock::new();
+    let mut cache = CACHE
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+
+    if let Some(numbered) = cache.get(sql) {
+        return numbered;
+    }
+    let numbered: &'static str = Box::leak(number_placeholders(sql).into_boxed_str());
+    cache.insert(sql.to_string(), numbered);
+    numbered
+}
+
+// Numbers the `?` outside of string literals, quoted identifiers, dollar-quoted bodies and
+// comments. `??` stands for a literal `?`, e.g. for the JSON operators `?`, `?|` and `?&`.
+fn number_placeholders(sql: &str) -> String {
+    let mut numbered = String::with_capacity(sql.len() + 16);
+    let mut count = 0;
+    let mut rest = sql;
+
+    while let Some(c) = rest.chars().next() {
+        let skipped = match c {
+            '\'' => {
+                // `E'...'` strings know backslash escapes, plain ones only doubled quotes.
+                let escapes = numbered.ends_with(['E', 'e'])
+                    && !numbered[..numbered.len() - 1].ends_with(is_word_char);
+                quoted_len(rest, '\'', escapes)
+            }
+            '"' => quoted_len(rest, '"', false),
+            '-' if rest.starts_with("--") => rest.find('\n').unwrap_or(rest.len()),
+            '/' if rest.starts_with("/*") => block_comment_len(rest),
+            '$' if !numbered.ends_with(is_word_char) => dollar_quoted_len(rest).unwrap_or(1),
+            '?' if rest.starts_with("??") => {
+                numbered.push('?');
+                rest = &rest[2..];
+                continue;
+            }
+            '?' => {
+                count += 1;
+                let _ = write!(numbered, "${}", count);
+                rest = &rest[1..];
+                continue;
+            }
+            _ => c.len_utf8(),
+        };
+        numbered.push_str(&rest[..skipped]);
+        rest = &rest[skipped..];
+    }
+    numbered
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Length of the literal at the start of `sql`, up to and with its closing `quote`, or the rest
+// of `sql` if it is never closed.
+fn quoted_len(sql: &str, quote: char, escapes: bool) -> usize {
+    let mut chars = sql.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        if escapes && c == '\\' {
+            chars.next();
+        } else if c == quote {
+            // A doubled quote stands for itself and keeps the literal going.
+            if sql[i + 1..].starts_with(quote) {
+                chars.next();
+            } else {
+                return i + 1;
+            }
+        }
+    }
+    sql.len()
+}
+
+// Length of the comment at the start of `sql`, which may nest like Postgres allows.
+fn block_comment_len(sql: &str) -> usize {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < sql.len() {
+        if sql[i..].starts_with("/*") {
+            depth += 1;
+            i += 2;
+        } else if sql[i..].starts_with("*/") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return i;
+            }
+        } else {
+            i += sql[i..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    sql.len()
+}
+
+// Length of the `$tag$...$tag$` body at the start of `sql`, or `None` if the `$` opens none, as
+// in a positional parameter.
+fn dollar_quoted_len(sql: &str) -> Option<usize> {
+    let tag_len = sql[1..].find('$')? + 2;
+    let tag = &sql[..tag_len];
+    let name = &tag[1..tag_len - 1];
+    if name.starts_with(|c: char| c.is_ascii_digit()) || !name.chars().all(is_word_char) {
+        return None;
+    }
+    let end = sql[tag_len..]
+        .find(tag)
+        .map_or(sql.len(), |i| tag_len + i + tag_len);
+    Some(end)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{number_placeholders, numbered};
+
+    #[test]
+    fn test_number_placeholders() {
+        assert_eq!(
+            number_placeholders("SELECT a FROM t WHERE b = ? AND c <> ?"),
+            "SELECT a FROM t WHERE b = $1 AND c <> $2"
+        );
+        assert_eq!(
+            number_placeholders("UPDATE t SET a = 'why?' WHERE b = ?"),
+            "UPDATE t SET a = 'why?' WHERE b = $1"
+        );
+        assert_eq!(numbered("VALUES (?, ?)"), "VALUES ($1, $2)");
+        assert!(std::ptr::eq(
+            numbered("VALUES (?, ?)"),
+            numbered("VALUES (?, ?)")
+        ));
+    }
+
+    #[test]
+    fn test_number_placeholders_skips_quoting() {
+        assert_eq!(
+            number_placeholders("SELECT 'it''s?', \"odd?\" FROM t WHERE a = ?"),
+            "SELECT 'it''s?', \"odd?\" FROM t WHERE a = $1"
+        );
+        assert_eq!(
+            number_placeholders(r"SELECT E'a\'?' WHERE a = ?"),
+            r"SELECT E'a\'?' WHERE a = $1"
+        );
+        // Outside of `E''` the backslash is just a character.
+        assert_eq!(
+            number_placeholders(r"SELECT 'a\' WHERE a = ?"),
+            r"SELECT 'a\' WHERE a = $1"
+        );
+        assert_eq!(
+            number_placeholders("SELECT $$why?$$, $body$ 'x?' $$ $body$ WHERE a = ?"),
+            "SELECT $$why?$$, $body$ 'x?' $$ $body$ WHERE a = $1"
+        );
+    }
+
+    #[test]
+    fn test_number_placeholders_skips_comments() {
+        assert_eq!(
+            number_placeholders("-- who?\nSELECT a FROM t WHERE b = ? -- and c?"),
+            "-- who?\nSELECT a FROM t WHERE b = $1 -- and c?"
+        );
+        assert_eq!(
+            number_placeholders("SELECT /* a? /* b? */ c? */ a FROM t WHERE b = ?"),
+            "SELECT /* a? /* b? */ c? */ a FROM t WHERE b = $1"
+        );
+        assert_eq!(
+            number_placeholders("SELECT 5 - -? - ?"),
+            "SELECT 5 - -$1 - $2"
+        );
+    }
+
+    #[test]
+    fn test_number_placeholders_escaped_question_mark() {
+        assert_eq!(
+            number_placeholders("SELECT a FROM t WHERE data ?? 'key' AND data ??| ?"),
+            "SELECT a FROM t WHERE data ? 'key' AND data ?| $1"
+        );
+    }
+}
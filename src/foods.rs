@@ -1,21 +1,34 @@
 use serde::{Deserialize, Serialize};
-use sqlx::{mysql::MySqlRow, prelude::Type, types::chrono::NaiveDate, FromRow, Row};
+use sqlx::{prelude::Type, types::chrono::NaiveDate, Row};
 use thiserror::Error;
 use uuid::Uuid;
 
-use crate::users::{PubUserInfo, UserId};
+use crate::{
+    db::{classify, impl_from_row, impl_uuid_type, DbErrorKind},
+    locations::LocationId,
+    users::{PubUserInfo, UserId},
+    validation::{trimmed_text, InvalidValue},
+};
 
-mod repo;
+pub(crate) mod handler;
+pub(crate) mod memory;
+pub(crate) mod repo;
 
-static FOOD_ID_COLUMN: &'static str = "food_id";
-static FOOD_NAME_COLUMN: &'static str = "food_name";
-static FOOD_EXP_COLUMN: &'static str = "exp";
-static USER_ID_COLUMN: &'static str = "user_id";
+static FOOD_ID_COLUMN: &str = "food_id";
+static FOOD_NAME_COLUMN: &str = "food_name";
+static FOOD_EXP_COLUMN: &str = "exp";
+static USER_ID_COLUMN: &str = "user_id";
+static FOOD_QUANTITY_COLUMN: &str = "quantity";
+static FOOD_UNIT_COLUMN: &str = "unit";
+static LOCATION_ID_COLUMN: &str = "location_id";
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Type)]
-#[sqlx(transparent)]
+const MAX_FOOD_NAME_CHARS: usize = 128;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct FoodId(String);
 
+impl_uuid_type!(FoodId);
+
 impl From<FoodId> for String {
     fn from(value: FoodId) -> Self {
         value.0
@@ -32,6 +45,7 @@ where
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Type)]
+#[serde(try_from = "String")]
 #[sqlx(transparent)]
 pub struct FoodName(String);
 
@@ -41,58 +55,289 @@ impl From<FoodName> for String {
     }
 }
 
-impl<T> From<T> for FoodName
-where
-    T: ToString,
-{
-    fn from(value: T) -> Self {
-        Self(value.to_string())
+impl FoodName {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for FoodName {
+    type Error = InvalidValue;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        trimmed_text(&value, MAX_FOOD_NAME_CHARS).map(Self)
+    }
+}
+
+impl TryFrom<&str> for FoodName {
+    type Error = InvalidValue;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::try_from(value.to_string())
+    }
+}
+
+// A food id scoped to the user who must be a member of the household the food is stored in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedFoodId {
+    pub food_id: FoodId,
+    pub user_id: UserId,
+}
+
+impl OwnedFoodId {
+    pub fn new(food_id: FoodId, user_id: UserId) -> Self {
+        Self { food_id, user_id }
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Unit {
+    #[default]
+    Count,
+    G,
+    Kg,
+    Ml,
+    L,
+    Pieces,
+}
+
+impl Unit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Unit::Count => "count",
+            Unit::G => "g",
+            Unit::Kg => "kg",
+            Unit::Ml => "ml",
+            Unit::L => "l",
+            Unit::Pieces => "pieces",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        [
+            Unit::Count,
+            Unit::G,
+            Unit::Kg,
+            Unit::Ml,
+            Unit::L,
+            Unit::Pieces,
+        ]
+        .into_iter()
+        .find(|unit| unit.as_str() == value)
+    }
+}
+
+fn default_quantity() -> f64 {
+    1.0
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct CreateFoodPayload {
     food_name: FoodName,
     exp: NaiveDate,
+    #[serde(default = "default_quantity")]
+    quantity: f64,
+    #[serde(default)]
+    unit: Unit,
+    // Falls back to the user's default location when left out.
+    #[serde(default)]
+    location_id: Option<LocationId>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConsumeFoodPayload {
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MoveFoodPayload {
+    pub location_id: LocationId,
 }
 
+// Foods belong to the household of their location, `user_id` records who added them.
 #[derive(Debug, Clone, Serialize, PartialEq)]
 pub struct Food {
     food_id: FoodId,
     food_name: FoodName,
     exp: NaiveDate,
+    quantity: f64,
+    unit: Unit,
+    location_id: LocationId,
     user_id: UserId,
 }
 
 impl Food {
-    pub fn new(payload: CreateFoodPayload, user: PubUserInfo) -> Self {
+    pub fn new(payload: CreateFoodPayload, user: PubUserInfo, location_id: LocationId) -> Self {
         Self {
             food_id: FoodId::from(Uuid::new_v4().to_string().as_str()),
             food_name: payload.food_name,
             exp: payload.exp,
+            quantity: payload.quantity,
+            unit: payload.unit,
+            location_id,
             user_id: user.user_id,
         }
     }
-}
 
-impl FromRow<'_, MySqlRow> for Food {
-    fn from_row(row: &'_ MySqlRow) -> Result<Self, sqlx::Error> {
-        Ok(Food {
-            food_id: FoodId(row.try_get(FOOD_ID_COLUMN)?),
-            food_name: FoodName(row.try_get(FOOD_NAME_COLUMN)?),
-            exp: row.try_get(FOOD_EXP_COLUMN)?,
-            user_id: UserId(row.try_get(USER_ID_COLUMN)?),
-        })
+    pub fn owned_id(&self) -> OwnedFoodId {
+        OwnedFoodId::new(self.food_id.clone(), self.user_id.clone())
+    }
+
+    pub fn food_name(&self) -> &FoodName {
+        &self.food_name
+    }
+
+    pub fn exp(&self) -> NaiveDate {
+        self.exp
+    }
+
+    pub fn location_id(&self) -> &LocationId {
+        &self.location_id
     }
 }
 
+impl_from_row!(Food, |row| {
+    Ok(Food {
+        food_id: row.try_get(FOOD_ID_COLUMN)?,
+        food_name: FoodName(row.try_get(FOOD_NAME_COLUMN)?),
+        exp: row.try_get(FOOD_EXP_COLUMN)?,
+        quantity: row.try_get(FOOD_QUANTITY_COLUMN)?,
+        unit: Unit::parse(row.try_get(FOOD_UNIT_COLUMN)?).ok_or_else(|| {
+            sqlx::Error::ColumnDecode {
+                index: FOOD_UNIT_COLUMN.to_string(),
+                source: "unknown unit".into(),
+            }
+        })?,
+        location_id: row.try_get(LOCATION_ID_COLUMN)?,
+        user_id: row.try_get(USER_ID_COLUMN)?,
+    })
+});
+
 #[derive(Debug, Clone, Serialize)]
 pub struct AllFoods {
     foods: Vec<Food>,
 }
 
-#[derive(Debug, Clone, Error)]
+// Foods past their `exp` and foods reaching it within the requested window, both sorted by `exp`.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ExpiringFoods {
+    expired: Vec<Food>,
+    expiring: Vec<Food>,
+}
+
+impl ExpiringFoods {
+    pub(crate) fn split(foods: Vec<Food>, today: NaiveDate) -> Self {
+        let (expired, expiring) = foods.into_iter().partition(|food| food.exp < today);
+        Self { expired, expiring }
+    }
+
+    pub fn expired(&self) -> &[Food] {
+        &self.expired
+    }
+
+    pub fn expiring(&self) -> &[Food] {
+        &self.expiring
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.expired.is_empty() && self.expiring.is_empty()
+    }
+}
+
+#[derive(Debug, Error)]
 pub enum FoodsError {
     #[error("Not found")]
     NotFound,
+    #[error("Food belongs to another household or is read-only")]
+    Forbidden,
+    #[error("Food already exists")]
+    Duplicate(#[source] sqlx::Error),
+    #[error("Location of the food does not exist")]
+    ForeignKey(#[source] sqlx::Error),
+    #[error("Invalid food")]
+    Validation(#[source] sqlx::Error),
+    #[error("Database unavailable")]
+    Unavailable(#[source] sqlx::Error),
+    #[error("Database error")]
+    Database(#[source] sqlx::Error),
+}
+
+impl From<sqlx::Error> for FoodsError {
+    fn from(value: sqlx::Error) -> Self {
+        match classify(&value) {
+            DbErrorKind::NotFound => FoodsError::NotFound,
+            DbErrorKind::UniqueViolation => FoodsError::Duplicate(value),
+            DbErrorKind::ForeignKeyViolation => FoodsError::ForeignKey(value),
+            DbErrorKind::Validation => FoodsError::Validation(value),
+            DbErrorKind::Unavailable => FoodsError::Unavailable(value),
+            DbErrorKind::Other => FoodsError::Database(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+
+    use super::{ExpiringFoods, Food, FoodId, FoodName, Unit};
+    use crate::{locations::LocationId, users::UserId};
+
+    fn food_expiring_on(day: u32) -> Food {
+        Food {
+            food_id: FoodId::from(format!("food_{}", day)),
+            food_name: FoodName::try_from("milk").unwrap(),
+            exp: NaiveDate::from_ymd_opt(2025, 4, day).unwrap(),
+            quantity: 1.0,
+            unit: Unit::Count,
+            location_id: LocationId::from("test_location_id"),
+            user_id: UserId::from("test_user_id"),
+        }
+    }
+
+    #[test]
+    fn test_split_expiring_foods() {
+        let today = NaiveDate::from_ymd_opt(2025, 4, 10).unwrap();
+        let foods = vec![
+            food_expiring_on(8),
+            food_expiring_on(9),
+            food_expiring_on(10),
+            food_expiring_on(12),
+        ];
+
+        let res = ExpiringFoods::split(foods, today);
+        assert_eq!(res.expired, vec![food_expiring_on(8), food_expiring_on(9)]);
+        assert_eq!(
+            res.expiring,
+            vec![food_expiring_on(10), food_expiring_on(12)]
+        );
+    }
+
+    #[test]
+    fn test_food_name_validation() {
+        let name: FoodName = serde_json::from_str(r#"" milk ""#).unwrap();
+        assert_eq!(name, FoodName::try_from("milk").unwrap());
+        assert!(FoodName::try_from("   ").is_err());
+        assert!(FoodName::try_from("x".repeat(129)).is_err());
+        assert!(serde_json::from_str::<FoodName>(r#""""#).is_err());
+    }
+
+    #[test]
+    fn test_unit_round_trip() {
+        for unit in [
+            Unit::Count,
+            Unit::G,
+            Unit::Kg,
+            Unit::Ml,
+            Unit::L,
+            Unit::Pieces,
+        ] {
+            assert_eq!(Unit::parse(unit.as_str()), Some(unit));
+            assert_eq!(
+                serde_json::to_string(&unit).unwrap(),
+                format!("\"{}\"", unit.as_str())
+            );
+        }
+        assert_eq!(Unit::parse("lb"), None);
+    }
 }
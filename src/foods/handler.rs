@@ -0,0 +1,243 @@
+use chrono::Local;
+use hyper::{body::Bytes, StatusCode};
+
+use crate::{
+    locations::{LocationId, OwnedLocationId},
+    server::{
+        empty_response, json_response, parse_json, query_param, ApiError, AppState, HttpResponse,
+    },
+    users::PubUserInfo,
+};
+
+use super::{
+    ConsumeFoodPayload, CreateFoodPayload, Food, FoodId, FoodsError, MoveFoodPayload, OwnedFoodId,
+};
+
+const DEFAULT_EXPIRING_DAYS: u32 = 3;
+const MAX_EXPIRING_DAYS: u32 = 365;
+
+impl From<FoodsError> for ApiError {
+    fn from(value: FoodsError) -> Self {
+        match value {
+            FoodsError::NotFound => ApiError::new(StatusCode::NOT_FOUND, value),
+            FoodsError::Forbidden => ApiError::new(StatusCode::FORBIDDEN, value),
+            FoodsError::Duplicate(_) => ApiError::new(StatusCode::CONFLICT, value),
+            FoodsError::ForeignKey(_) | FoodsError::Validation(_) => {
+                ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, value)
+            }
+            FoodsError::Unavailable(_) => ApiError::logged(StatusCode::SERVICE_UNAVAILABLE, &value),
+            FoodsError::Database(_) => ApiError::logged(StatusCode::INTERNAL_SERVER_ERROR, &value),
+        }
+    }
+}
+
+fn check_quantity(field: &str, value: f64) -> Result<(), ApiError> {
+    if value.is_finite() && value > 0.0 {
+        Ok(())
+    } else {
+        Err(ApiError::invalid_field(field, "must be a positive number"))
+    }
+}
+
+// Makes sure the location exists and is in a household of the user.
+async fn owned_location(
+    state: &AppState,
+    user: &PubUserInfo,
+    location_id: LocationId,
+) -> Result<LocationId, ApiError> {
+    let id = OwnedLocationId::new(location_id, user.user_id.clone());
+    let location = state.locations.read(&id).await?;
+    Ok(location.location_id().clone())
+}
+
+// Like `owned_location`, but also checks the user may put foods there.
+async fn writable_location(
+    state: &AppState,
+    user: &PubUserInfo,
+    location_id: LocationId,
+) -> Result<LocationId, ApiError> {
+    let id = OwnedLocationId::new(location_id, user.user_id.clone());
+    state.locations.check_writer(&id).await?;
+    Ok(id.location_id)
+}
+
+async fn default_location(state: &AppState, user: &PubUserInfo) -> Result<LocationId, ApiError> {
+    let household = state.households.default_for(&user.user_id).await?;
+    let location = state
+        .locations
+        .default_for(household.household().household_id(), &user.user_id)
+        .await?;
+    Ok(location.location_id().clone())
+}
+
+pub(crate) async fn create(
+    state: &AppState,
+    user: PubUserInfo,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let mut payload: CreateFoodPayload = parse_json(&body)?;
+    check_quantity("quantity", payload.quantity)?;
+
+    let location_id = match payload.location_id.take() {
+        Some(location_id) => writable_location(state, &user, location_id).await?,
+        None => default_location(state, &user).await?,
+    };
+    let food = state
+        .foods
+        .insert(&Food::new(payload, user, location_id))
+        .await?;
+    Ok(json_response(StatusCode::CREATED, &food))
+}
+
+pub(crate) async fn read(
+    state: &AppState,
+    user: PubUserInfo,
+    food_id: &str,
+) -> Result<HttpResponse, ApiError> {
+    let id = OwnedFoodId::new(FoodId::from(food_id), user.user_id);
+    let food = state.foods.read(&id).await?;
+    Ok(json_response(StatusCode::OK, &food))
+}
+
+pub(crate) async fn read_all(
+    state: &AppState,
+    user: PubUserInfo,
+    query: Option<&str>,
+) -> Result<HttpResponse, ApiError> {
+    let foods = match query_param(query, "location_id") {
+        Some(location_id) => {
+            let location_id = owned_location(state, &user, LocationId::from(location_id)).await?;
+            state.foods.read_all_in(&location_id).await?
+        }
+        None => state.foods.read_all(user.user_id).await?,
+    };
+    Ok(json_response(StatusCode::OK, &foods))
+}
+
+pub(crate) async fn read_expiring(
+    state: &AppState,
+    user: PubUserInfo,
+    query: Option<&str>,
+) -> Result<HttpResponse, ApiError> {
+    let days = match query_param(query, "days") {
+        Some(days) => days
+            .parse::<u32>()
+            .ok()
+            .filter(|days| *days <= MAX_EXPIRING_DAYS)
+            .ok_or_else(|| {
+                ApiError::invalid_field(
+                    "days",
+                    format!("must be an integer between 0 and {}", MAX_EXPIRING_DAYS),
+                )
+            })?,
+        None => DEFAULT_EXPIRING_DAYS,
+    };
+
+    let today = Local::now().date_naive();
+    let foods = state
+        .foods
+        .read_expiring(&user.user_id, today, days)
+        .await?;
+    Ok(json_response(StatusCode::OK, &foods))
+}
+
+pub(crate) async fn update(
+    state: &AppState,
+    user: PubUserInfo,
+    food_id: &str,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let mut payload: CreateFoodPayload = parse_json(&body)?;
+    check_quantity("quantity", payload.quantity)?;
+
+    let id = OwnedFoodId::new(FoodId::from(food_id), user.user_id.clone());
+    let location_id = match payload.location_id.take() {
+        Some(location_id) => writable_location(state, &user, location_id).await?,
+        None => state.foods.read(&id).await?.location_id,
+    };
+    let food = Food {
+        food_id: id.food_id.clone(),
+        food_name: payload.food_name,
+        exp: payload.exp,
+        quantity: payload.quantity,
+        unit: payload.unit,
+        location_id,
+        user_id: user.user_id,
+    };
+    let food = state.foods.update(&id, &food).await?;
+    Ok(json_response(StatusCode::OK, &food))
+}
+
+pub(crate) async fn relocate(
+    state: &AppState,
+    user: PubUserInfo,
+    food_id: &str,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let payload: MoveFoodPayload = parse_json(&body)?;
+    let location_id = writable_location(state, &user, payload.location_id).await?;
+
+    let id = OwnedFoodId::new(FoodId::from(food_id), user.user_id);
+    let food = state.foods.relocate(&id, &location_id).await?;
+    Ok(json_response(StatusCode::OK, &food))
+}
+
+pub(crate) async fn consume(
+    state: &AppState,
+    user: PubUserInfo,
+    food_id: &str,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let payload: ConsumeFoodPayload = parse_json(&body)?;
+    check_quantity("amount", payload.amount)?;
+
+    let id = OwnedFoodId::new(FoodId::from(food_id), user.user_id);
+    match state.foods.consume(&id, payload.amount).await? {
+        Some(food) => Ok(json_response(StatusCode::OK, &food)),
+        None => Ok(empty_response(StatusCode::NO_CONTENT)),
+    }
+}
+
+pub(crate) async fn delete(
+    state: &AppState,
+    user: PubUserInfo,
+    food_id: &str,
+) -> Result<HttpResponse, ApiError> {
+    let id = OwnedFoodId::new(FoodId::from(food_id), user.user_id);
+    state.foods.delete(&id).await?;
+    Ok(empty_response(StatusCode::NO_CONTENT))
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::StatusCode;
+
+    use super::check_quantity;
+    use crate::{foods::FoodsError, server::ApiError};
+
+    fn status_of(err: FoodsError) -> StatusCode {
+        ApiError::from(err).into_response().status()
+    }
+
+    #[test]
+    fn test_foods_error_status() {
+        assert_eq!(status_of(FoodsError::NotFound), StatusCode::NOT_FOUND);
+        assert_eq!(status_of(FoodsError::Forbidden), StatusCode::FORBIDDEN);
+        assert_eq!(
+            status_of(FoodsError::from(sqlx::Error::PoolTimedOut)),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            status_of(FoodsError::from(sqlx::Error::RowNotFound)),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn test_check_quantity() {
+        assert!(check_quantity("amount", 0.5).is_ok());
+        assert!(check_quantity("amount", 0.0).is_err());
+        assert!(check_quantity("amount", -1.0).is_err());
+        assert!(check_quantity("amount", f64::NAN).is_err());
+    }
+}
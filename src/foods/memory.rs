@@ -0,0 +1,209 @@
+use async_trait::async_trait;
+use chrono::NaiveDate;
+
+use crate::{
+    households::Role,
+    locations::LocationId,
+    memory::{ConstraintError, MemoryStore, Tables},
+    users::UserId,
+    RepositoryAllReader, RepositoryTargetReader, RepositoryWriter,
+};
+
+use super::{
+    repo::{expiring_until, FoodsStore, QUANTITY_EPSILON},
+    AllFoods, ExpiringFoods, Food, FoodsError, OwnedFoodId,
+};
+
+pub struct MemoryFoodsRepository {
+    store: MemoryStore,
+}
+
+impl MemoryFoodsRepository {
+    pub(crate) fn new(store: MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+// The role of the user in the household the food is stored in. Tells a missing food apart from
+// one in a household the user is not a member of.
+fn check_access(tables: &Tables, id: &OwnedFoodId) -> Result<Role, FoodsError> {
+    let household_id = tables
+        .foods
+        .iter()
+        .find(|food| food.food_id == id.food_id)
+        .and_then(|food| {
+            tables
+                .locations
+                .iter()
+                .find(|location| location.location_id() == &food.location_id)
+        })
+        .map(|location| location.household_id())
+        .ok_or(FoodsError::NotFound)?;
+    tables
+        .role(household_id, &id.user_id)
+        .ok_or(FoodsError::Forbidden)
+}
+
+fn check_writer(tables: &Tables, id: &OwnedFoodId) -> Result<(), FoodsError> {
+    if check_access(tables, id)?.can_write() {
+        Ok(())
+    } else {
+        Err(FoodsError::Forbidden)
+    }
+}
+
+fn check_location(tables: &Tables, location_id: &LocationId) -> Result<(), FoodsError> {
+    if tables
+        .locations
+        .iter()
+        .any(|location| location.location_id() == location_id)
+    {
+        Ok(())
+    } else {
+        Err(ConstraintError::foreign_key("food_location_id").into())
+    }
+}
+
+fn find_food<'t>(tables: &'t mut Tables, id: &OwnedFoodId) -> Result<&'t mut Food, FoodsError> {
+    tables
+        .foods
+        .iter_mut()
+        .find(|food| food.food_id == id.food_id)
+        .ok_or(FoodsError::NotFound)
+}
+
+// Foods of every household the user is a member of.
+fn foods_of(tables: &Tables, user_id: &UserId) -> Vec<Food> {
+    tables
+        .foods
+        .iter()
+        .filter(|food| {
+            tables.locations.iter().any(|location| {
+                location.location_id() == &food.location_id
+                    && tables.role(location.household_id(), user_id).is_some()
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+#[async_trait]
+impl FoodsStore for MemoryFoodsRepository {
+    async fn read_expiring(
+        &self,
+        user_id: &UserId,
+        today: NaiveDate,
+        days: u32,
+    ) -> Result<ExpiringFoods, FoodsError> {
+        let until = expiring_until(today, days);
+        let mut foods = foods_of(&self.store.lock(), user_id);
+        foods.retain(|food| food.exp <= until);
+        foods.sort_by_key(|food| food.exp);
+        Ok(ExpiringFoods::split(foods, today))
+    }
+
+    async fn read_all_in(&self, location_id: &LocationId) -> Result<AllFoods, FoodsError> {
+        let tables = self.store.lock();
+        let foods = tables
+            .foods
+            .iter()
+            .filter(|food| &food.location_id == location_id)
+            .cloned()
+            .collect();
+        Ok(AllFoods { foods })
+    }
+
+    async fn relocate(
+        &self,
+        id: &OwnedFoodId,
+        location_id: &LocationId,
+    ) -> Result<Food, FoodsError> {
+        let mut tables = self.store.lock();
+        check_writer(&tables, id)?;
+        check_location(&tables, location_id)?;
+        let food = find_food(&mut tables, id)?;
+        food.location_id = location_id.clone();
+        Ok(food.clone())
+    }
+
+    async fn consume(&self, id: &OwnedFoodId, amount: f64) -> Result<Option<Food>, FoodsError> {
+        let mut tables = self.store.lock();
+        check_writer(&tables, id)?;
+        let food = find_food(&mut tables, id)?;
+        food.quantity -= amount;
+        if food.quantity > QUANTITY_EPSILON {
+            return Ok(Some(food.clone()));
+        }
+
+        tables.foods.retain(|food| food.food_id != id.food_id);
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl<'a> RepositoryWriter<'a, Food, OwnedFoodId> for MemoryFoodsRepository {
+    type Output = Food;
+    type Error = FoodsError;
+
+    async fn insert(&self, payload: &Food) -> Result<Self::Output, Self::Error> {
+        let mut tables = self.store.lock();
+        check_location(&tables, &payload.location_id)?;
+        tables.foods.push(payload.clone());
+        Ok(payload.clone())
+    }
+
+    async fn update(
+        &self,
+        id: &'a OwnedFoodId,
+        payload: &Food,
+    ) -> Result<Self::Output, Self::Error> {
+        let mut tables = self.store.lock();
+        check_writer(&tables, id)?;
+        check_location(&tables, &payload.location_id)?;
+        let food = find_food(&mut tables, id)?;
+        food.food_name = payload.food_name.clone();
+        food.exp = payload.exp;
+        food.quantity = payload.quantity;
+        food.unit = payload.unit;
+        food.location_id = payload.location_id.clone();
+        Ok(food.clone())
+    }
+
+    async fn delete(&self, id: &'a OwnedFoodId) -> Result<(), Self::Error> {
+        let mut tables = self.store.lock();
+        check_writer(&tables, id)?;
+        tables.foods.retain(|food| food.food_id != id.food_id);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'a> RepositoryTargetReader<'a, OwnedFoodId> for MemoryFoodsRepository {
+    type QueryRes = Food;
+    type QueryErr = FoodsError;
+
+    async fn read(&self, id: &'a OwnedFoodId) -> Result<Self::QueryRes, Self::QueryErr> {
+        let tables = self.store.lock();
+        check_access(&tables, id)?;
+        tables
+            .foods
+            .iter()
+            .find(|food| food.food_id == id.food_id)
+            .cloned()
+            .ok_or(FoodsError::NotFound)
+    }
+}
+
+#[async_trait]
+impl<T> RepositoryAllReader<T> for MemoryFoodsRepository
+where
+    T: Into<UserId> + Clone + Send + Sync + 'static,
+{
+    type QueryRes = AllFoods;
+    type QueryErr = FoodsError;
+
+    async fn read_all(&self, id: T) -> Result<Self::QueryRes, Self::QueryErr> {
+        let foods = foods_of(&self.store.lock(), &id.into());
+        Ok(AllFoods { foods })
+    }
+}
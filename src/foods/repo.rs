@@ -1,166 +1,405 @@
 use async_trait::async_trait;
-use sqlx::{query, query_as, MySql, Pool};
+use chrono::{Days, NaiveDate};
+use sqlx::{query, query_as, query_scalar};
 
-use crate::{users::UserId, RepositoryAllReader, RepositoryTargetReader, RepositoryWriter};
+use crate::{
+    db::{with_pool, DbPool},
+    households::Role,
+    locations::LocationId,
+    users::UserId,
+    RepositoryAllReader, RepositoryTargetReader, RepositoryWriter,
+};
 
-use super::{AllFoods, Food, FoodId, FoodsError};
+use super::{AllFoods, ExpiringFoods, Food, FoodsError, OwnedFoodId};
+
+// Remaining quantities this close to zero count as used up.
+pub(super) const QUANTITY_EPSILON: f64 = 1e-9;
+
+// What the handlers and the notifier need from a foods backend, either `FoodsRepository` or
+// `MemoryFoodsRepository`.
+#[async_trait]
+pub(crate) trait FoodsStore:
+    for<'a> RepositoryWriter<'a, Food, OwnedFoodId, Output = Food, Error = FoodsError>
+    + for<'a> RepositoryTargetReader<'a, OwnedFoodId, QueryRes = Food, QueryErr = FoodsError>
+    + RepositoryAllReader<UserId, QueryRes = AllFoods, QueryErr = FoodsError>
+    + Send
+    + Sync
+{
+    // Foods in the households of a user whose `exp` is at most `days` days after `today`,
+    // already expired ones included.
+    async fn read_expiring(
+        &self,
+        user_id: &UserId,
+        today: NaiveDate,
+        days: u32,
+    ) -> Result<ExpiringFoods, FoodsError>;
+
+    // Callers make sure the user may see the location first.
+    async fn read_all_in(&self, location_id: &LocationId) -> Result<AllFoods, FoodsError>;
+
+    async fn relocate(
+        &self,
+        id: &OwnedFoodId,
+        location_id: &LocationId,
+    ) -> Result<Food, FoodsError>;
+
+    // Takes `amount` off the quantity of a food. A food that is used up is removed and `None`
+    // is returned.
+    async fn consume(&self, id: &OwnedFoodId, amount: f64) -> Result<Option<Food>, FoodsError>;
+}
+
+// The last `exp` `read_expiring` includes.
+pub(super) fn expiring_until(today: NaiveDate, days: u32) -> NaiveDate {
+    today
+        .checked_add_days(Days::new(days.into()))
+        .unwrap_or(NaiveDate::MAX)
+}
 
 pub struct FoodsRepository {
-    pool: Pool<MySql>,
+    pool: DbPool,
 }
 
 impl FoodsRepository {
-    pub(crate) fn new(pool: Pool<MySql>) -> Self {
+    pub(crate) fn new(pool: DbPool) -> Self {
         Self { pool }
     }
+
+    // The role of the user in the household the food is stored in. Tells a missing food apart
+    // from one in a household the user is not a member of.
+    async fn check_access(&self, id: &OwnedFoodId) -> Result<Role, FoodsError> {
+        let role = with_pool!(&self.pool, |pool| {
+            query_scalar::<_, Option<String>>(
+                r#"
+                    SELECT m.role
+                    FROM food_table f
+                    INNER JOIN storage_location_table l ON l.location_id = f.location_id
+                    LEFT JOIN household_member_table m
+                    ON m.household_id = l.household_id AND m.user_id = ?
+                    WHERE f.food_id = ?
+                "#,
+            )
+            .bind(&id.user_id)
+            .bind(&id.food_id)
+            .fetch_optional(pool)
+            .await
+        })?;
+
+        match role {
+            Some(Some(role)) => Role::parse(&role).ok_or(FoodsError::Forbidden),
+            Some(None) => Err(FoodsError::Forbidden),
+            None => Err(FoodsError::NotFound),
+        }
+    }
+
+    async fn check_writer(&self, id: &OwnedFoodId) -> Result<(), FoodsError> {
+        if self.check_access(id).await?.can_write() {
+            Ok(())
+        } else {
+            Err(FoodsError::Forbidden)
+        }
+    }
 }
 
 #[async_trait]
-impl<'a> RepositoryWriter<'a, '_, Food, FoodId> for FoodsRepository {
-    type Output = ();
+impl FoodsStore for FoodsRepository {
+    async fn read_expiring(
+        &self,
+        user_id: &UserId,
+        today: NaiveDate,
+        days: u32,
+    ) -> Result<ExpiringFoods, FoodsError> {
+        let until = expiring_until(today, days);
+        let foods = with_pool!(&self.pool, |pool| {
+            query_as::<_, Food>(
+                r#"
+                    SELECT f.food_id, f.food_name, f.exp, f.quantity, f.unit, f.location_id, f.user_id
+                    FROM food_table f
+                    INNER JOIN storage_location_table l ON l.location_id = f.location_id
+                    INNER JOIN household_member_table m ON m.household_id = l.household_id
+                    WHERE m.user_id = ? AND f.exp <= ?
+                    ORDER BY f.exp
+                "#,
+            )
+            .bind(user_id)
+            .bind(until)
+            .fetch_all(pool)
+            .await
+        })?;
+        Ok(ExpiringFoods::split(foods, today))
+    }
+
+    async fn read_all_in(&self, location_id: &LocationId) -> Result<AllFoods, FoodsError> {
+        let foods = with_pool!(&self.pool, |pool| {
+            query_as::<_, Food>(
+                r#"
+                    SELECT food_id, food_name, exp, quantity, unit, location_id, user_id
+                    FROM food_table
+                    WHERE location_id = ?
+                "#,
+            )
+            .bind(location_id)
+            .fetch_all(pool)
+            .await
+        })?;
+        Ok(AllFoods { foods })
+    }
+
+    async fn relocate(
+        &self,
+        id: &OwnedFoodId,
+        location_id: &LocationId,
+    ) -> Result<Food, FoodsError> {
+        self.check_writer(id).await?;
+        let rows = with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    UPDATE food_table
+                    SET location_id = ?
+                    WHERE food_id = ?
+                "#,
+            )
+            .bind(location_id)
+            .bind(&id.food_id)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+
+        if rows == 0 {
+            return Err(FoodsError::NotFound);
+        }
+        self.read(id).await
+    }
+
+    async fn consume(&self, id: &OwnedFoodId, amount: f64) -> Result<Option<Food>, FoodsError> {
+        self.check_writer(id).await?;
+        with_pool!(&self.pool, |pool| {
+            let mut tx = pool.begin().await?;
+
+            // Taking the amount off before reading the rest locks the row on every backend, so
+            // concurrent consumes queue up instead of both subtracting from the same quantity.
+            let rows = query(
+                r#"
+                    UPDATE food_table
+                    SET quantity = quantity - ?
+                    WHERE food_id = ?
+                "#,
+            )
+            .bind(amount)
+            .bind(&id.food_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            if rows == 0 {
+                return Err(FoodsError::NotFound);
+            }
+
+            let food = query_as::<_, Food>(
+                r#"
+                    SELECT food_id, food_name, exp, quantity, unit, location_id, user_id
+                    FROM food_table
+                    WHERE food_id = ?
+                "#,
+            )
+            .bind(&id.food_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+            let food = if food.quantity <= QUANTITY_EPSILON {
+                query(
+                    r#"
+                        DELETE FROM food_table
+                        WHERE food_id = ?
+                    "#,
+                )
+                .bind(&id.food_id)
+                .execute(&mut *tx)
+                .await?;
+                None
+            } else {
+                Some(food)
+            };
+
+            tx.commit().await?;
+            Ok(food)
+        })
+    }
+}
+
+#[async_trait]
+impl<'a> RepositoryWriter<'a, Food, OwnedFoodId> for FoodsRepository {
+    type Output = Food;
     type Error = FoodsError;
 
     async fn insert(&self, payload: &Food) -> Result<Self::Output, Self::Error> {
-        query(
-            r#"
-                INSERT INTO food_table
-                (food_id, food_name, exp, user_id)
-                VALUES (?, ?, ?, ?)
-            "#,
-        )
-        .bind(&payload.food_id)
-        .bind(&payload.food_name)
-        .bind(&payload.exp)
-        .bind(&payload.user_id)
-        .execute(&self.pool)
-        .await
-        .map_err(|_e| FoodsError::NotFound)?;
-        Ok(())
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    INSERT INTO food_table
+                    (food_id, food_name, exp, quantity, unit, location_id, user_id)
+                    VALUES (?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(&payload.food_id)
+            .bind(&payload.food_name)
+            .bind(payload.exp)
+            .bind(payload.quantity)
+            .bind(payload.unit.as_str())
+            .bind(&payload.location_id)
+            .bind(&payload.user_id)
+            .execute(pool)
+            .await?;
+        });
+        Ok(payload.clone())
     }
 
-    async fn update(&self, id: &'a FoodId, payload: &Food) -> Result<Self::Output, Self::Error> {
-        query(
-            r#"
-                UPDATE food_table
-                SET
-                food_name = ?, exp = ?
-                WHERE food_id = ?
-            "#,
-        )
-        .bind(&payload.food_name)
-        .bind(&payload.exp)
-        .bind(id)
-        .execute(&self.pool)
-        .await
-        .map_err(|_e| FoodsError::NotFound)?;
-        Ok(())
+    async fn update(
+        &self,
+        id: &'a OwnedFoodId,
+        payload: &Food,
+    ) -> Result<Self::Output, Self::Error> {
+        self.check_writer(id).await?;
+        let rows = with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    UPDATE food_table
+                    SET
+                    food_name = ?, exp = ?, quantity = ?, unit = ?, location_id = ?
+                    WHERE food_id = ?
+                "#,
+            )
+            .bind(&payload.food_name)
+            .bind(payload.exp)
+            .bind(payload.quantity)
+            .bind(payload.unit.as_str())
+            .bind(&payload.location_id)
+            .bind(&id.food_id)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+
+        if rows == 0 {
+            return Err(FoodsError::NotFound);
+        }
+        self.read(id).await
     }
 
-    async fn delete(&self, id: &'a FoodId) -> Result<(), Self::Error> {
-        query(
-            r#"
-                DELETE FROM food_table
-                WHERE food_id = ?
-            "#,
-        )
-        .bind(id)
-        .execute(&self.pool)
-        .await
-        .map_err(|_e| FoodsError::NotFound)?;
+    async fn delete(&self, id: &'a OwnedFoodId) -> Result<(), Self::Error> {
+        self.check_writer(id).await?;
+        let rows = with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    DELETE FROM food_table
+                    WHERE food_id = ?
+                "#,
+            )
+            .bind(&id.food_id)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+
+        if rows == 0 {
+            return Err(FoodsError::NotFound);
+        }
         Ok(())
     }
 }
 
 #[async_trait]
-impl<'a> RepositoryTargetReader<'a, FoodId> for FoodsRepository {
+impl<'a> RepositoryTargetReader<'a, OwnedFoodId> for FoodsRepository {
     type QueryRes = Food;
     type QueryErr = FoodsError;
 
-    async fn read(&self, id: &'a FoodId) -> Result<Self::QueryRes, Self::QueryErr> {
-        query_as::<_, Food>(
-            r#"
-                SELECT food_id, food_name, exp, user_id
-                FROM food_table
-                WHERE food_id = ?
-            "#,
-        )
-        .bind(id)
-        .fetch_one(&self.pool)
-        .await
-        .map_err(|_e| FoodsError::NotFound)
+    async fn read(&self, id: &'a OwnedFoodId) -> Result<Self::QueryRes, Self::QueryErr> {
+        let food = with_pool!(&self.pool, |pool| {
+            query_as::<_, Food>(
+                r#"
+                    SELECT f.food_id, f.food_name, f.exp, f.quantity, f.unit, f.location_id, f.user_id
+                    FROM food_table f
+                    INNER JOIN storage_location_table l ON l.location_id = f.location_id
+                    INNER JOIN household_member_table m ON m.household_id = l.household_id
+                    WHERE f.food_id = ? AND m.user_id = ?
+                "#,
+            )
+            .bind(&id.food_id)
+            .bind(&id.user_id)
+            .fetch_optional(pool)
+            .await
+        })?;
+
+        match food {
+            Some(food) => Ok(food),
+            None => self.check_access(id).await.and(Err(FoodsError::NotFound)),
+        }
     }
 }
 
+// Foods of every household the user is a member of.
 #[async_trait]
-impl<T> RepositoryAllReader<T> for FoodsRepository 
-where 
+impl<T> RepositoryAllReader<T> for FoodsRepository
+where
     T: Into<UserId> + Clone + Send + Sync + 'static,
 {
     type QueryRes = AllFoods;
     type QueryErr = FoodsError;
 
     async fn read_all(&self, id: T) -> Result<Self::QueryRes, Self::QueryErr> {
-        let foods = query_as::<_, Food>(
-            r#"
-                SELECT food_id, food_name, exp, user_id
-                FROM food_table
-                WHERE user_id = ?
-            "#,
-        )
-        .bind::<UserId>(id.clone().into())
-        .fetch_all(&self.pool)
-        .await
-        .map_err(|_e| FoodsError::NotFound)?;
+        let foods = with_pool!(&self.pool, |pool| {
+            query_as::<_, Food>(
+                r#"
+                    SELECT f.food_id, f.food_name, f.exp, f.quantity, f.unit, f.location_id, f.user_id
+                    FROM food_table f
+                    INNER JOIN storage_location_table l ON l.location_id = f.location_id
+                    INNER JOIN household_member_table m ON m.household_id = l.household_id
+                    WHERE m.user_id = ?
+                "#,
+            )
+            .bind::<UserId>(id.clone().into())
+            .fetch_all(pool)
+            .await
+        })?;
         Ok(AllFoods { foods })
     }
 }
 
-// CAUTION: Before running these tests, ensure the `user_table` in your Docker container's MySQL database contains a user with the following credentials:
-//
-// - `user_id`: `test_user_id`
-// - `user_name`: `test_user_name`
-//
-// You'll need to manually insert this user into the `user_table` using a SQL query like this:
-//
-// ```sql
-// INSERT INTO user_table (user_id, user_name, mail, password) VALUES ('test_user_id', 'test_user_name', 'mail', 'pass');
-// ```
 #[cfg(test)]
 mod test {
     use chrono::NaiveDate;
-    use sqlx::{query_as, MySql, MySqlPool, Pool};
+    use serde_json::json;
+    use uuid::Uuid;
 
     use crate::{
-        foods::{CreateFoodPayload, Food, FoodId, FoodName},
-        users::{PubUserInfo, UserId, UserName},
-        RepositoryTargetReader, RepositoryWriter,
+        foods::{CreateFoodPayload, Food, FoodId, FoodName, FoodsError, OwnedFoodId, Unit},
+        households::{Invite, InvitePayload, Role},
+        locations::{LocationId, StorageLocation},
+        testing::TestDb,
     };
 
-    use super::FoodsRepository;
-
-    static USER_ID: &str = "test_user_id";
-    static USER_NAME: &str = "test_user_name";
-
-    async fn set_up_db() -> Pool<MySql> {
-        let db_url = dotenvy::var("DATABASE_URL").unwrap();
-        MySqlPool::connect(&db_url).await.unwrap()
-    }
-
-    fn foodsrepo_new(pool: Pool<MySql>) -> FoodsRepository {
-        FoodsRepository { pool }
+    // The default fridge of alice, where the foods of these tests go.
+    async fn test_location(db: &TestDb) -> LocationId {
+        let user_id = &db.alice.user_id;
+        let home = db.households().default_for(user_id).await.unwrap();
+        let location = db
+            .locations()
+            .default_for(home.household().household_id(), user_id)
+            .await
+            .unwrap();
+        location.location_id().clone()
     }
 
-    fn pub_user_info() -> PubUserInfo {
-        PubUserInfo {
-            user_id: UserId::from(USER_ID.to_string()),
-            user_name: UserName::from(USER_NAME.to_string()),
-        }
+    async fn test_food(db: &TestDb) -> Food {
+        Food::new(create_food(), db.alice.info(), test_location(db).await)
     }
 
     fn create_food() -> CreateFoodPayload {
         CreateFoodPayload {
-            food_name: FoodName::from("test_food"),
+            food_name: FoodName::try_from("test_food").unwrap(),
             exp: NaiveDate::from_ymd_opt(2025, 4, 8).unwrap_or_default(),
+            quantity: 3.0,
+            unit: Unit::Pieces,
+            location_id: None,
         }
     }
 
@@ -172,94 +411,270 @@ mod test {
 
         Food {
             food_id: old_food.food_id.to_owned(),
-            food_name: FoodName::from(&updated_food_name),
+            food_name: FoodName::try_from(updated_food_name.as_str()).unwrap(),
             exp: old_food.exp,
+            quantity: old_food.quantity + 1.0,
+            unit: Unit::Kg,
+            location_id: old_food.location_id.clone(),
             user_id: old_food.user_id.clone(),
         }
     }
 
-    async fn query_full_data(id: &FoodId) -> Result<Food, Box<dyn std::error::Error>> {
-        let pool = set_up_db().await;
-        let repo = FoodsRepository { pool };
-
-        let res = query_as(
-            r#"
-                SELECT food_id, food_name, exp, user_id FROM food_table
-                WHERE food_id = ?
-            "#,
-        )
-        .bind::<String>(id.clone().into())
-        .fetch_one(&repo.pool)
-        .await?;
-        Ok(res)
-    }
-
     #[tokio::test]
     async fn test_insert_food() {
-        let repo = foodsrepo_new(set_up_db().await);
-
-        let user = pub_user_info();
-        let food = Food::new(create_food(), user.clone());
-        repo.insert(&food).await.unwrap();
+        for db in TestDb::each().await {
+            let repo = db.foods();
+            let food = test_food(&db).await;
 
-        let db_food = query_full_data(&food.food_id).await.unwrap();
-
-        assert_eq!(db_food.food_id, food.food_id);
-        assert_eq!(db_food.food_name, food.food_name);
-        assert_eq!(db_food.exp, food.exp);
-        assert_eq!(db_food.user_id, food.user_id);
+            let inserted = repo.insert(&food).await.unwrap();
+            assert_eq!(inserted, food);
+            assert_eq!(repo.read(&food.owned_id()).await.unwrap(), food);
+        }
     }
 
     #[tokio::test]
-    async fn test_query_food() {
-        let repo = foodsrepo_new(set_up_db().await);
-
-        let user = pub_user_info();
-        let food = Food::new(create_food(), user);
-
-        repo.insert(&food).await.unwrap();
-
-        println!("{:?}", food.food_id);
-        let query_food = repo.read(&food.food_id).await.unwrap();
-
-        assert_eq!(query_food.food_id, food.food_id);
-        assert_eq!(query_food.food_name, food.food_name);
-        assert_eq!(query_food.exp, food.exp);
-        assert_eq!(query_food.user_id, food.user_id);
+    async fn test_missing_location_is_foreign_key() {
+        for db in TestDb::each().await {
+            let repo = db.foods();
+            let location_id = LocationId::from(Uuid::new_v4().to_string());
+            let food = Food::new(create_food(), db.alice.info(), location_id);
+
+            assert!(matches!(
+                repo.insert(&food).await,
+                Err(FoodsError::ForeignKey(_))
+            ));
+        }
     }
 
     #[tokio::test]
     async fn test_update_food() {
-        let repo = foodsrepo_new(set_up_db().await);
+        for db in TestDb::each().await {
+            let repo = db.foods();
+            let food = test_food(&db).await;
+            repo.insert(&food).await.unwrap();
+
+            let update_food = new_update_food(&food);
+            let updated = repo
+                .update(&update_food.owned_id(), &update_food)
+                .await
+                .unwrap();
+            assert_eq!(updated, update_food);
+            assert_eq!(
+                repo.read(&update_food.owned_id()).await.unwrap(),
+                update_food
+            );
+        }
+    }
 
-        let user = pub_user_info();
-        let food = Food::new(create_food(), user.clone());
-        repo.insert(&food).await.unwrap();
+    #[tokio::test]
+    async fn test_delete_food() {
+        for db in TestDb::each().await {
+            let repo = db.foods();
+            let food = test_food(&db).await;
+            repo.insert(&food).await.unwrap();
+
+            repo.delete(&food.owned_id()).await.unwrap();
+            assert!(matches!(
+                repo.read(&food.owned_id()).await,
+                Err(FoodsError::NotFound)
+            ));
+        }
+    }
 
-        let update_food = new_update_food(&food);
-        repo.update(&update_food.food_id, &update_food)
-            .await
-            .unwrap();
+    #[tokio::test]
+    async fn test_foreign_food_forbidden() {
+        for db in TestDb::each().await {
+            let repo = db.foods();
+            let food = test_food(&db).await;
+            repo.insert(&food).await.unwrap();
+
+            let foreign_id = OwnedFoodId::new(food.food_id.clone(), db.bob.user_id.clone());
+            assert!(matches!(
+                repo.read(&foreign_id).await,
+                Err(FoodsError::Forbidden)
+            ));
+            assert!(matches!(
+                repo.update(&foreign_id, &new_update_food(&food)).await,
+                Err(FoodsError::Forbidden)
+            ));
+            assert!(matches!(
+                repo.delete(&foreign_id).await,
+                Err(FoodsError::Forbidden)
+            ));
+            assert!(repo
+                .read_all(db.bob.user_id.clone())
+                .await
+                .unwrap()
+                .foods
+                .is_empty());
+
+            let missing_id =
+                OwnedFoodId::new(FoodId::from("missing_food_id"), food.user_id.clone());
+            assert!(matches!(
+                repo.read(&missing_id).await,
+                Err(FoodsError::NotFound)
+            ));
+
+            assert_eq!(repo.read(&food.owned_id()).await.unwrap(), food);
+        }
+    }
 
-        let db_food = query_full_data(&update_food.food_id).await.unwrap();
-        assert_eq!(db_food.food_id, update_food.food_id);
-        assert_eq!(db_food.food_name, update_food.food_name);
-        assert_eq!(db_food.exp, update_food.exp);
-        assert_eq!(db_food.user_id, update_food.user_id);
+    #[tokio::test]
+    async fn test_missing_food_not_found() {
+        for db in TestDb::each().await {
+            let repo = db.foods();
+
+            let food = test_food(&db).await;
+            assert!(matches!(
+                repo.update(&food.owned_id(), &food).await,
+                Err(FoodsError::NotFound)
+            ));
+            assert!(matches!(
+                repo.delete(&food.owned_id()).await,
+                Err(FoodsError::NotFound)
+            ));
+        }
     }
 
     #[tokio::test]
-    async fn test_delete_food() {
-        let repo = foodsrepo_new(set_up_db().await);
+    async fn test_read_expiring() {
+        for db in TestDb::each().await {
+            let repo = db.foods();
+            let user = db.alice.info();
+            let food = test_food(&db).await;
+            repo.insert(&food).await.unwrap();
+
+            let day_before = food.exp.pred_opt().unwrap();
+            let res = repo
+                .read_expiring(&user.user_id, day_before, 1)
+                .await
+                .unwrap();
+            assert!(res.expiring.contains(&food));
+            assert!(!res.expired.contains(&food));
+            assert!(res.expiring.windows(2).all(|w| w[0].exp <= w[1].exp));
+
+            let day_after = food.exp.succ_opt().unwrap();
+            let res = repo
+                .read_expiring(&user.user_id, day_after, 0)
+                .await
+                .unwrap();
+            assert!(res.expired.contains(&food));
+
+            let res = repo
+                .read_expiring(&user.user_id, day_before.pred_opt().unwrap(), 0)
+                .await
+                .unwrap();
+            assert!(!res.expiring.contains(&food));
+            assert!(!res.expired.contains(&food));
+        }
+    }
 
-        let user = pub_user_info();
-        let food = Food::new(create_food(), user.clone());
-        repo.insert(&food).await.unwrap();
+    #[tokio::test]
+    async fn test_consume_food() {
+        for db in TestDb::each().await {
+            let repo = db.foods();
+            let food = test_food(&db).await;
+            repo.insert(&food).await.unwrap();
+
+            let remaining = repo.consume(&food.owned_id(), 1.0).await.unwrap().unwrap();
+            assert_eq!(remaining.quantity, 2.0);
+            assert_eq!(repo.read(&food.owned_id()).await.unwrap().quantity, 2.0);
+
+            let used_up = repo.consume(&food.owned_id(), 2.0).await.unwrap();
+            assert!(used_up.is_none());
+            assert!(matches!(
+                repo.read(&food.owned_id()).await,
+                Err(FoodsError::NotFound)
+            ));
+        }
+    }
 
-        repo.delete(&food.food_id).await.unwrap();
+    #[tokio::test]
+    async fn test_relocate_food() {
+        for db in TestDb::each().await {
+            let repo = db.foods();
+            let user = db.alice.info();
+            let food = test_food(&db).await;
+            repo.insert(&food).await.unwrap();
+
+            let home = db.households().default_for(&user.user_id).await.unwrap();
+            let freezer = db
+                .locations()
+                .insert(&StorageLocation::new(
+                    serde_json::from_value(json!({
+                        "location_name": "Garage freezer",
+                        "kind": "freezer",
+                    }))
+                    .unwrap(),
+                    user.clone(),
+                    home.household().household_id().clone(),
+                ))
+                .await
+                .unwrap();
+
+            let moved = repo
+                .relocate(&food.owned_id(), freezer.location_id())
+                .await
+                .unwrap();
+            assert_eq!(&moved.location_id, freezer.location_id());
+
+            let in_freezer = repo.read_all_in(freezer.location_id()).await.unwrap();
+            assert_eq!(in_freezer.foods, vec![moved]);
+        }
+    }
 
-        if let Ok(_) = query_full_data(&food.food_id).await {
-            panic!("food should deleted but exists");
+    #[tokio::test]
+    async fn test_household_shares_foods() {
+        for db in TestDb::each().await {
+            let repo = db.foods();
+            let households = db.households();
+            let guest = db.bob.info();
+
+            let food = test_food(&db).await;
+            repo.insert(&food).await.unwrap();
+
+            let home = households.default_for(&db.alice.user_id).await.unwrap();
+            let (invite, token) = Invite::new(
+                serde_json::from_value::<InvitePayload>(json!({
+                    "mail": String::from(db.bob.mail.clone()),
+                    "role": Role::ReadOnly.as_str(),
+                }))
+                .unwrap(),
+                home.household().household_id().clone(),
+            );
+            households.insert_invite(&invite).await.unwrap();
+
+            let guest_id = OwnedFoodId::new(food.food_id.clone(), guest.user_id.clone());
+            assert!(matches!(
+                repo.read(&guest_id).await,
+                Err(FoodsError::Forbidden)
+            ));
+
+            households
+                .accept_invite(&token, &guest.user_id)
+                .await
+                .unwrap();
+            assert_eq!(repo.read(&guest_id).await.unwrap(), food);
+            assert!(repo
+                .read_all(guest.user_id.clone())
+                .await
+                .unwrap()
+                .foods
+                .contains(&food));
+            assert!(matches!(
+                repo.delete(&guest_id).await,
+                Err(FoodsError::Forbidden)
+            ));
+
+            // Taking the household down takes its foods along.
+            households
+                .delete(home.household().household_id(), &db.alice.user_id)
+                .await
+                .unwrap();
+            assert!(matches!(
+                repo.read(&food.owned_id()).await,
+                Err(FoodsError::NotFound)
+            ));
         }
     }
 }
@@ -0,0 +1,398 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::Type, Row};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{
+    db::{classify, impl_from_row, impl_uuid_type, DbErrorKind},
+    mail::{MailError, OutgoingMail},
+    users::{Mail, UserId, UserName},
+    util::{gen_random_token, hash_token},
+    validation::{trimmed_text, InvalidValue},
+};
+
+pub(crate) mod handler;
+pub(crate) mod memory;
+pub(crate) mod repo;
+
+static HOUSEHOLD_ID_COLUMN: &str = "household_id";
+static HOUSEHOLD_NAME_COLUMN: &str = "household_name";
+static ROLE_COLUMN: &str = "role";
+static USER_ID_COLUMN: &str = "user_id";
+static USER_NAME_COLUMN: &str = "user_name";
+
+static DEFAULT_HOUSEHOLD_NAME: &str = "Home";
+const MAX_HOUSEHOLD_NAME_CHARS: usize = 64;
+static INVITE_SUBJECT: &str = "You are invited to a shared fridge";
+const INVITE_TTL_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HouseholdId(String);
+
+impl_uuid_type!(HouseholdId);
+
+impl From<HouseholdId> for String {
+    fn from(value: HouseholdId) -> Self {
+        value.0
+    }
+}
+
+impl<T> From<T> for HouseholdId
+where
+    T: ToString,
+{
+    fn from(value: T) -> Self {
+        Self(value.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Type)]
+#[serde(try_from = "String")]
+#[sqlx(transparent)]
+pub struct HouseholdName(String);
+
+impl From<HouseholdName> for String {
+    fn from(value: HouseholdName) -> Self {
+        value.0
+    }
+}
+
+impl TryFrom<String> for HouseholdName {
+    type Error = InvalidValue;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        trimmed_text(&value, MAX_HOUSEHOLD_NAME_CHARS).map(Self)
+    }
+}
+
+impl TryFrom<&str> for HouseholdName {
+    type Error = InvalidValue;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::try_from(value.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Type)]
+#[sqlx(transparent)]
+pub struct InviteToken(String);
+
+impl InviteToken {
+    pub(crate) fn hash(&self) -> String {
+        hash_token(&self.0)
+    }
+}
+
+impl From<InviteToken> for String {
+    fn from(value: InviteToken) -> Self {
+        value.0
+    }
+}
+
+impl<T> From<T> for InviteToken
+where
+    T: ToString,
+{
+    fn from(value: T) -> Self {
+        Self(value.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Owner,
+    Member,
+    ReadOnly,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Member => "member",
+            Role::ReadOnly => "read_only",
+        }
+    }
+
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        [Role::Owner, Role::Member, Role::ReadOnly]
+            .into_iter()
+            .find(|role| role.as_str() == value)
+    }
+
+    // Whether the role may add, change or remove foods and locations.
+    pub fn can_write(&self) -> bool {
+        !matches!(self, Role::ReadOnly)
+    }
+
+    pub(crate) fn decode(value: &str, column: &str) -> Result<Self, sqlx::Error> {
+        Role::parse(value).ok_or_else(|| sqlx::Error::ColumnDecode {
+            index: column.to_string(),
+            source: "unknown role".into(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateHouseholdPayload {
+    household_name: HouseholdName,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InvitePayload {
+    mail: Mail,
+    role: Role,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RolePayload {
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Household {
+    household_id: HouseholdId,
+    household_name: HouseholdName,
+}
+
+impl Household {
+    pub fn new(payload: CreateHouseholdPayload) -> Self {
+        Self {
+            household_id: HouseholdId::from(Uuid::new_v4()),
+            household_name: payload.household_name,
+        }
+    }
+
+    // The household users get when they start using the app on their own.
+    pub(crate) fn default_home() -> Self {
+        Self::new(CreateHouseholdPayload {
+            household_name: HouseholdName(DEFAULT_HOUSEHOLD_NAME.to_string()),
+        })
+    }
+
+    pub fn household_id(&self) -> &HouseholdId {
+        &self.household_id
+    }
+}
+
+impl_from_row!(Household, |row| {
+    Ok(Household {
+        household_id: row.try_get(HOUSEHOLD_ID_COLUMN)?,
+        household_name: HouseholdName(row.try_get(HOUSEHOLD_NAME_COLUMN)?),
+    })
+});
+
+// A household together with the role the requesting user has in it.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Membership {
+    #[serde(flatten)]
+    household: Household,
+    role: Role,
+}
+
+impl Membership {
+    pub fn household(&self) -> &Household {
+        &self.household
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+}
+
+impl_from_row!(Membership, |row| {
+    Ok(Membership {
+        household: Household::from_row(row)?,
+        role: Role::decode(row.try_get(ROLE_COLUMN)?, ROLE_COLUMN)?,
+    })
+});
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Member {
+    user_id: UserId,
+    user_name: UserName,
+    role: Role,
+}
+
+impl_from_row!(Member, |row| {
+    Ok(Member {
+        user_id: row.try_get(USER_ID_COLUMN)?,
+        user_name: row.try_get(USER_NAME_COLUMN)?,
+        role: Role::decode(row.try_get(ROLE_COLUMN)?, ROLE_COLUMN)?,
+    })
+});
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HouseholdDetail {
+    #[serde(flatten)]
+    household: Household,
+    members: Vec<Member>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AllHouseholds {
+    households: Vec<Membership>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Invite {
+    #[serde(skip)]
+    token_hash: String,
+    household_id: HouseholdId,
+    #[serde(serialize_with = "serialize_mail")]
+    mail: Mail,
+    role: Role,
+    expires_at: NaiveDateTime,
+}
+
+fn serialize_mail<S: serde::Serializer>(mail: &Mail, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&String::from(mail.clone()))
+}
+
+impl Invite {
+    // An invite and the token that accepts it, which has to go out by mail right away as only its
+    // hash is stored.
+    pub fn new(payload: InvitePayload, household_id: HouseholdId) -> (Self, InviteToken) {
+        let token = InviteToken(gen_random_token());
+        let invite = Self {
+            token_hash: token.hash(),
+            household_id,
+            mail: payload.mail,
+            role: payload.role,
+            expires_at: Utc::now().naive_utc() + Duration::days(INVITE_TTL_DAYS),
+        };
+        (invite, token)
+    }
+
+    pub(crate) fn household_id(&self) -> &HouseholdId {
+        &self.household_id
+    }
+
+    pub(crate) fn mail(
+        &self,
+        token: &InviteToken,
+        inviter: &UserName,
+        household: &Household,
+    ) -> OutgoingMail {
+        let body = format!(
+            "Hi,\n\n{} invited you to share the fridge \"{}\".\n\
+             Log in and accept the invite with this token before {} UTC:\n\n{}\n",
+            String::from(inviter.clone()),
+            String::from(household.household_name.clone()),
+            self.expires_at.format("%Y-%m-%d %H:%M"),
+            token.0,
+        );
+        OutgoingMail {
+            to: self.mail.clone(),
+            subject: INVITE_SUBJECT.to_string(),
+            body,
+        }
+    }
+}
+
+impl_from_row!(Invite, |row| {
+    Ok(Invite {
+        token_hash: row.try_get("token_hash")?,
+        household_id: row.try_get(HOUSEHOLD_ID_COLUMN)?,
+        mail: row.try_get("mail")?,
+        role: Role::decode(row.try_get(ROLE_COLUMN)?, ROLE_COLUMN)?,
+        expires_at: row.try_get("expires_at")?,
+    })
+});
+
+#[derive(Debug, Error)]
+pub enum HouseholdError {
+    #[error("Not found")]
+    NotFound,
+    #[error("Not allowed in this household")]
+    Forbidden,
+    #[error("Invite is addressed to another mail")]
+    InviteMismatch,
+    #[error("Household needs at least one owner")]
+    LastOwner,
+    #[error("Already a member of the household")]
+    Duplicate(#[source] sqlx::Error),
+    #[error("Referenced record does not exist")]
+    ForeignKey(#[source] sqlx::Error),
+    #[error("Invalid household")]
+    Validation(#[source] sqlx::Error),
+    #[error("Database unavailable")]
+    Unavailable(#[source] sqlx::Error),
+    #[error("Database error")]
+    Database(#[source] sqlx::Error),
+    #[error(transparent)]
+    Mail(#[from] MailError),
+}
+
+impl From<sqlx::Error> for HouseholdError {
+    fn from(value: sqlx::Error) -> Self {
+        match classify(&value) {
+            DbErrorKind::NotFound => HouseholdError::NotFound,
+            DbErrorKind::UniqueViolation => HouseholdError::Duplicate(value),
+            DbErrorKind::ForeignKeyViolation => HouseholdError::ForeignKey(value),
+            DbErrorKind::Validation => HouseholdError::Validation(value),
+            DbErrorKind::Unavailable => HouseholdError::Unavailable(value),
+            DbErrorKind::Other => HouseholdError::Database(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{Household, HouseholdName, Invite, InvitePayload, Role};
+    use crate::users::{Mail, UserName};
+
+    #[test]
+    fn test_role_round_trip() {
+        for role in [Role::Owner, Role::Member, Role::ReadOnly] {
+            assert_eq!(Role::parse(role.as_str()), Some(role));
+            assert_eq!(
+                serde_json::to_string(&role).unwrap(),
+                format!("\"{}\"", role.as_str())
+            );
+        }
+        assert_eq!(Role::parse("admin"), None);
+        assert!(!Role::ReadOnly.can_write());
+        assert!(Role::Member.can_write());
+    }
+
+    #[test]
+    fn test_household_name_validation() {
+        let name: HouseholdName = serde_json::from_str(r#"" Flat 3 ""#).unwrap();
+        assert_eq!(name, HouseholdName::try_from("Flat 3").unwrap());
+        assert!(HouseholdName::try_from("   ").is_err());
+        assert!(HouseholdName::try_from("x".repeat(65)).is_err());
+        assert!(serde_json::from_str::<HouseholdName>(r#""""#).is_err());
+    }
+
+    #[test]
+    fn test_invite_mail_carries_token() {
+        let household = Household::new(super::CreateHouseholdPayload {
+            household_name: HouseholdName::try_from("Flat 3").unwrap(),
+        });
+        let (invite, token) = Invite::new(
+            InvitePayload {
+                mail: Mail::try_from("bob@example.com").unwrap(),
+                role: Role::Member,
+            },
+            household.household_id.clone(),
+        );
+
+        let mail = invite.mail(&token, &UserName::try_from("alice").unwrap(), &household);
+        assert_eq!(mail.to, Mail::try_from("bob@example.com").unwrap());
+        assert!(mail.body.contains("alice invited you"));
+        assert!(mail.body.contains("\"Flat 3\""));
+        assert!(mail.body.contains(&token.0));
+        assert_eq!(invite.token_hash, token.hash());
+
+        // The token only ever travels by mail.
+        let value = serde_json::to_value(&invite).unwrap();
+        assert_eq!(value["mail"], json!("bob@example.com"));
+        assert!(value.get("token").is_none());
+        assert!(value.get("token_hash").is_none());
+    }
+}
@@ -0,0 +1,192 @@
+use hyper::{body::Bytes, StatusCode};
+
+use crate::{
+    server::{empty_response, json_response, parse_json, ApiError, AppState, HttpResponse},
+    users::{PubUserInfo, UserError, UserId},
+};
+
+use super::{
+    CreateHouseholdPayload, Household, HouseholdError, HouseholdId, Invite, InvitePayload,
+    InviteToken, RolePayload,
+};
+
+impl From<HouseholdError> for ApiError {
+    fn from(value: HouseholdError) -> Self {
+        match value {
+            HouseholdError::NotFound => ApiError::new(StatusCode::NOT_FOUND, value),
+            HouseholdError::Forbidden | HouseholdError::InviteMismatch => {
+                ApiError::new(StatusCode::FORBIDDEN, value)
+            }
+            HouseholdError::LastOwner | HouseholdError::Duplicate(_) => {
+                ApiError::new(StatusCode::CONFLICT, value)
+            }
+            HouseholdError::ForeignKey(_) | HouseholdError::Validation(_) => {
+                ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, value)
+            }
+            HouseholdError::Unavailable(_) => {
+                ApiError::logged(StatusCode::SERVICE_UNAVAILABLE, &value)
+            }
+            HouseholdError::Mail(_) => ApiError::logged(StatusCode::BAD_GATEWAY, &value),
+            HouseholdError::Database(_) => {
+                ApiError::logged(StatusCode::INTERNAL_SERVER_ERROR, &value)
+            }
+        }
+    }
+}
+
+pub(crate) async fn create(
+    state: &AppState,
+    user: PubUserInfo,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let payload: CreateHouseholdPayload = parse_json(&body)?;
+    let membership = state
+        .households
+        .insert(&Household::new(payload), &user.user_id)
+        .await?;
+    Ok(json_response(StatusCode::CREATED, &membership))
+}
+
+pub(crate) async fn read(
+    state: &AppState,
+    user: PubUserInfo,
+    household_id: &str,
+) -> Result<HttpResponse, ApiError> {
+    let household = state
+        .households
+        .read(&HouseholdId::from(household_id), &user.user_id)
+        .await?;
+    Ok(json_response(StatusCode::OK, &household))
+}
+
+pub(crate) async fn read_all(
+    state: &AppState,
+    user: PubUserInfo,
+) -> Result<HttpResponse, ApiError> {
+    let households = state.households.read_all(user.user_id).await?;
+    Ok(json_response(StatusCode::OK, &households))
+}
+
+pub(crate) async fn delete(
+    state: &AppState,
+    user: PubUserInfo,
+    household_id: &str,
+) -> Result<HttpResponse, ApiError> {
+    state
+        .households
+        .delete(&HouseholdId::from(household_id), &user.user_id)
+        .await?;
+    Ok(empty_response(StatusCode::NO_CONTENT))
+}
+
+pub(crate) async fn invite(
+    state: &AppState,
+    user: PubUserInfo,
+    household_id: &str,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let payload: InvitePayload = parse_json(&body)?;
+    let household_id = HouseholdId::from(household_id);
+    state
+        .households
+        .check_owner(&household_id, &user.user_id)
+        .await?;
+    // Otherwise anybody could have the server mail invites with a made up account.
+    if !state.users.mail_status(&user.user_id).await?.verified {
+        return Err(UserError::MailUnverified.into());
+    }
+    // The token is only handed out by mail, so there is no point in creating it without one.
+    let mailer = state.mailer.as_ref().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "mail is not configured on this server",
+        )
+    })?;
+
+    let household = state.households.read(&household_id, &user.user_id).await?;
+    let (invite, token) = Invite::new(payload, household_id);
+    state.households.insert_invite(&invite).await?;
+    mailer
+        .send(&invite.mail(&token, &user.user_name, &household.household))
+        .await
+        .map_err(HouseholdError::from)?;
+    Ok(json_response(StatusCode::CREATED, &invite))
+}
+
+pub(crate) async fn accept(
+    state: &AppState,
+    user: PubUserInfo,
+    token: &str,
+) -> Result<HttpResponse, ApiError> {
+    let membership = state
+        .households
+        .accept_invite(&InviteToken::from(token), &user.user_id)
+        .await?;
+    Ok(json_response(StatusCode::OK, &membership))
+}
+
+pub(crate) async fn update_member(
+    state: &AppState,
+    user: PubUserInfo,
+    household_id: &str,
+    member_id: &str,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let payload: RolePayload = parse_json(&body)?;
+    let household_id = HouseholdId::from(household_id);
+    state
+        .households
+        .check_owner(&household_id, &user.user_id)
+        .await?;
+    state
+        .households
+        .update_member(&household_id, &UserId::from(member_id), payload.role)
+        .await?;
+
+    let household = state.households.read(&household_id, &user.user_id).await?;
+    Ok(json_response(StatusCode::OK, &household))
+}
+
+// Owners remove members, everybody else may only leave on their own.
+pub(crate) async fn delete_member(
+    state: &AppState,
+    user: PubUserInfo,
+    household_id: &str,
+    member_id: &str,
+) -> Result<HttpResponse, ApiError> {
+    let household_id = HouseholdId::from(household_id);
+    let member_id = UserId::from(member_id);
+    if member_id != user.user_id {
+        state
+            .households
+            .check_owner(&household_id, &user.user_id)
+            .await?;
+    }
+    state
+        .households
+        .delete_member(&household_id, &member_id)
+        .await?;
+    Ok(empty_response(StatusCode::NO_CONTENT))
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::StatusCode;
+
+    use crate::{households::HouseholdError, server::ApiError};
+
+    fn status_of(err: HouseholdError) -> StatusCode {
+        ApiError::from(err).into_response().status()
+    }
+
+    #[test]
+    fn test_household_error_status() {
+        assert_eq!(status_of(HouseholdError::NotFound), StatusCode::NOT_FOUND);
+        assert_eq!(status_of(HouseholdError::Forbidden), StatusCode::FORBIDDEN);
+        assert_eq!(
+            status_of(HouseholdError::InviteMismatch),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(status_of(HouseholdError::LastOwner), StatusCode::CONFLICT);
+    }
+}
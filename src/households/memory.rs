@@ -0,0 +1,294 @@
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::{
+    memory::{ConstraintError, MemoryStore, Tables},
+    users::UserId,
+    RepositoryAllReader,
+};
+
+use super::{
+    repo::{check_not_last_owner, HouseholdStore},
+    AllHouseholds, Household, HouseholdDetail, HouseholdError, HouseholdId, Invite, InviteToken,
+    Member, Membership, Role,
+};
+
+// A row of `household_member_table`.
+#[derive(Debug, Clone)]
+pub(crate) struct StoredMember {
+    pub(crate) household_id: HouseholdId,
+    pub(crate) user_id: UserId,
+    pub(crate) role: Role,
+}
+
+pub struct MemoryHouseholdRepository {
+    store: MemoryStore,
+}
+
+impl MemoryHouseholdRepository {
+    pub(crate) fn new(store: MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+fn insert_member(
+    tables: &mut Tables,
+    household_id: &HouseholdId,
+    user_id: &UserId,
+    role: Role,
+) -> Result<(), HouseholdError> {
+    if tables.household(household_id).is_none() || tables.user(user_id).is_none() {
+        return Err(ConstraintError::foreign_key("member_household_usr_id").into());
+    }
+    if tables.role(household_id, user_id).is_some() {
+        return Err(ConstraintError::unique("member_household_usr_idx").into());
+    }
+    tables.members.push(StoredMember {
+        household_id: household_id.clone(),
+        user_id: user_id.clone(),
+        role,
+    });
+    Ok(())
+}
+
+fn insert_household(
+    tables: &mut Tables,
+    household: &Household,
+    owner: &UserId,
+) -> Result<Membership, HouseholdError> {
+    if tables.household(&household.household_id).is_some() {
+        return Err(ConstraintError::unique("household_id_idx").into());
+    }
+    if tables.user(owner).is_none() {
+        return Err(ConstraintError::foreign_key("member_usr_id").into());
+    }
+    tables.households.push(household.clone());
+    insert_member(tables, &household.household_id, owner, Role::Owner)?;
+    Ok(Membership {
+        household: household.clone(),
+        role: Role::Owner,
+    })
+}
+
+// The role of a member and how many owners their household has, like `MEMBER_ROLE_QUERY`.
+fn member_role(
+    tables: &Tables,
+    household_id: &HouseholdId,
+    member_id: &UserId,
+) -> Result<(Role, i64), HouseholdError> {
+    tables
+        .household(household_id)
+        .ok_or(HouseholdError::NotFound)?;
+    let role = tables
+        .role(household_id, member_id)
+        .ok_or(HouseholdError::NotFound)?;
+    let owners = tables
+        .members
+        .iter()
+        .filter(|member| &member.household_id == household_id && member.role == Role::Owner)
+        .count();
+    Ok((role, owners as i64))
+}
+
+#[async_trait]
+impl HouseholdStore for MemoryHouseholdRepository {
+    async fn insert(
+        &self,
+        household: &Household,
+        owner: &UserId,
+    ) -> Result<Membership, HouseholdError> {
+        insert_household(&mut self.store.lock(), household, owner)
+    }
+
+    async fn default_for(&self, user_id: &UserId) -> Result<Membership, HouseholdError> {
+        let mut tables = self.store.lock();
+        let membership = tables
+            .members
+            .iter()
+            .filter(|member| &member.user_id == user_id && member.role != Role::ReadOnly)
+            .find_map(|member| {
+                Some(Membership {
+                    household: tables.household(&member.household_id)?.clone(),
+                    role: member.role,
+                })
+            });
+
+        match membership {
+            Some(membership) => Ok(membership),
+            None => insert_household(&mut tables, &Household::default_home(), user_id),
+        }
+    }
+
+    async fn role(
+        &self,
+        household_id: &HouseholdId,
+        user_id: &UserId,
+    ) -> Result<Role, HouseholdError> {
+        self.store
+            .lock()
+            .role(household_id, user_id)
+            .ok_or(HouseholdError::NotFound)
+    }
+
+    async fn read(
+        &self,
+        household_id: &HouseholdId,
+        user_id: &UserId,
+    ) -> Result<HouseholdDetail, HouseholdError> {
+        let tables = self.store.lock();
+        tables
+            .role(household_id, user_id)
+            .ok_or(HouseholdError::NotFound)?;
+        let household = tables
+            .household(household_id)
+            .ok_or(HouseholdError::NotFound)?
+            .clone();
+
+        let members = tables
+            .members
+            .iter()
+            .filter(|member| &member.household_id == household_id)
+            .filter_map(|member| {
+                Some(Member {
+                    user_id: member.user_id.clone(),
+                    user_name: tables.user(&member.user_id)?.user_name().clone(),
+                    role: member.role,
+                })
+            })
+            .collect();
+        Ok(HouseholdDetail { household, members })
+    }
+
+    async fn delete(
+        &self,
+        household_id: &HouseholdId,
+        user_id: &UserId,
+    ) -> Result<(), HouseholdError> {
+        let mut tables = self.store.lock();
+        match tables.role(household_id, user_id) {
+            Some(Role::Owner) => {}
+            Some(_) => return Err(HouseholdError::Forbidden),
+            None => return Err(HouseholdError::NotFound),
+        }
+
+        tables.remove_household(household_id);
+        Ok(())
+    }
+
+    async fn insert_invite(&self, invite: &Invite) -> Result<(), HouseholdError> {
+        let mut tables = self.store.lock();
+        let now = Utc::now().naive_utc();
+        tables.invites.retain(|invite| invite.expires_at > now);
+
+        if tables.household(&invite.household_id).is_none() {
+            return Err(ConstraintError::foreign_key("invite_household_id").into());
+        }
+        if tables
+            .invites
+            .iter()
+            .any(|stored| stored.token_hash == invite.token_hash)
+        {
+            return Err(ConstraintError::unique("invite_token_hash_idx").into());
+        }
+        tables.invites.push(invite.clone());
+        Ok(())
+    }
+
+    async fn accept_invite(
+        &self,
+        token: &InviteToken,
+        user_id: &UserId,
+    ) -> Result<Membership, HouseholdError> {
+        let mut tables = self.store.lock();
+        let now = Utc::now().naive_utc();
+        let token_hash = token.hash();
+        let invite = tables
+            .invites
+            .iter()
+            .find(|invite| invite.token_hash == token_hash && invite.expires_at > now)
+            .ok_or(HouseholdError::NotFound)?
+            .clone();
+
+        let user = tables.user(user_id).ok_or(HouseholdError::NotFound)?;
+        if user.mail() != &invite.mail {
+            return Err(HouseholdError::InviteMismatch);
+        }
+
+        insert_member(&mut tables, &invite.household_id, user_id, invite.role)?;
+        tables
+            .invites
+            .retain(|stored| stored.token_hash != token_hash);
+
+        let household = tables
+            .household(&invite.household_id)
+            .ok_or(HouseholdError::NotFound)?
+            .clone();
+        Ok(Membership {
+            household,
+            role: invite.role,
+        })
+    }
+
+    async fn update_member(
+        &self,
+        household_id: &HouseholdId,
+        member_id: &UserId,
+        role: Role,
+    ) -> Result<(), HouseholdError> {
+        let mut tables = self.store.lock();
+        let (current, owners) = member_role(&tables, household_id, member_id)?;
+        if role != Role::Owner {
+            check_not_last_owner(current.as_str(), owners)?;
+        }
+
+        if let Some(member) = tables
+            .members
+            .iter_mut()
+            .find(|member| &member.household_id == household_id && &member.user_id == member_id)
+        {
+            member.role = role;
+        }
+        Ok(())
+    }
+
+    async fn delete_member(
+        &self,
+        household_id: &HouseholdId,
+        member_id: &UserId,
+    ) -> Result<(), HouseholdError> {
+        let mut tables = self.store.lock();
+        let (current, owners) = member_role(&tables, household_id, member_id)?;
+        check_not_last_owner(current.as_str(), owners)?;
+
+        tables.members.retain(|member| {
+            !(&member.household_id == household_id && &member.user_id == member_id)
+        });
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T> RepositoryAllReader<T> for MemoryHouseholdRepository
+where
+    T: Into<UserId> + Clone + Send + Sync + 'static,
+{
+    type QueryRes = AllHouseholds;
+    type QueryErr = HouseholdError;
+
+    async fn read_all(&self, id: T) -> Result<Self::QueryRes, Self::QueryErr> {
+        let user_id: UserId = id.into();
+        let tables = self.store.lock();
+        let households = tables
+            .members
+            .iter()
+            .filter(|member| member.user_id == user_id)
+            .filter_map(|member| {
+                Some(Membership {
+                    household: tables.household(&member.household_id)?.clone(),
+                    role: member.role,
+                })
+            })
+            .collect();
+        Ok(AllHouseholds { households })
+    }
+}
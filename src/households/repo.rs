@@ -0,0 +1,646 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{query, query_as, query_scalar};
+
+use crate::{
+    db::{with_pool, DbPool},
+    users::{Mail, UserId},
+    RepositoryAllReader,
+};
+
+use super::{
+    AllHouseholds, Household, HouseholdDetail, HouseholdError, HouseholdId, Invite, InviteToken,
+    Member, Membership, Role,
+};
+
+// The role of a member and how many owners their household has, in that order.
+static MEMBER_ROLE_QUERY: &str = r#"
+    SELECT m.role, (
+        SELECT COUNT(*)
+        FROM household_member_table o
+        WHERE o.household_id = m.household_id AND o.role = ?
+    )
+    FROM household_member_table m
+    WHERE m.household_id = ? AND m.user_id = ?
+"#;
+
+// What the handlers need from a household backend, either `HouseholdRepository` or
+// `MemoryHouseholdRepository`.
+#[async_trait]
+pub(crate) trait HouseholdStore:
+    RepositoryAllReader<UserId, QueryRes = AllHouseholds, QueryErr = HouseholdError> + Send + Sync
+{
+    // Creates the household with `owner` as its first member.
+    async fn insert(
+        &self,
+        household: &Household,
+        owner: &UserId,
+    ) -> Result<Membership, HouseholdError>;
+
+    // The first household the user joined and may add foods to, creating a home for users who
+    // have none yet.
+    async fn default_for(&self, user_id: &UserId) -> Result<Membership, HouseholdError>;
+
+    // The role of the user in the household. Outsiders cannot tell a foreign household from a
+    // missing one.
+    async fn role(
+        &self,
+        household_id: &HouseholdId,
+        user_id: &UserId,
+    ) -> Result<Role, HouseholdError>;
+
+    async fn check_owner(
+        &self,
+        household_id: &HouseholdId,
+        user_id: &UserId,
+    ) -> Result<(), HouseholdError> {
+        match self.role(household_id, user_id).await? {
+            Role::Owner => Ok(()),
+            _ => Err(HouseholdError::Forbidden),
+        }
+    }
+
+    async fn read(
+        &self,
+        household_id: &HouseholdId,
+        user_id: &UserId,
+    ) -> Result<HouseholdDetail, HouseholdError>;
+
+    // Removes the household along with its locations and the foods stored in them.
+    async fn delete(
+        &self,
+        household_id: &HouseholdId,
+        user_id: &UserId,
+    ) -> Result<(), HouseholdError>;
+
+    async fn insert_invite(&self, invite: &Invite) -> Result<(), HouseholdError>;
+
+    // Turns a pending invite into a membership of the user it was mailed to.
+    async fn accept_invite(
+        &self,
+        token: &InviteToken,
+        user_id: &UserId,
+    ) -> Result<Membership, HouseholdError>;
+
+    async fn update_member(
+        &self,
+        household_id: &HouseholdId,
+        member_id: &UserId,
+        role: Role,
+    ) -> Result<(), HouseholdError>;
+
+    async fn delete_member(
+        &self,
+        household_id: &HouseholdId,
+        member_id: &UserId,
+    ) -> Result<(), HouseholdError>;
+}
+
+pub struct HouseholdRepository {
+    pool: DbPool,
+}
+
+impl HouseholdRepository {
+    pub(crate) fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl HouseholdStore for HouseholdRepository {
+    async fn insert(
+        &self,
+        household: &Household,
+        owner: &UserId,
+    ) -> Result<Membership, HouseholdError> {
+        with_pool!(&self.pool, |pool| {
+            let mut tx = pool.begin().await?;
+            query(
+                r#"
+                    INSERT INTO household_table
+                    (household_id, household_name)
+                    VALUES (?, ?)
+                "#,
+            )
+            .bind(&household.household_id)
+            .bind(&household.household_name)
+            .execute(&mut *tx)
+            .await?;
+
+            query(
+                r#"
+                    INSERT INTO household_member_table
+                    (household_id, user_id, role)
+                    VALUES (?, ?, ?)
+                "#,
+            )
+            .bind(&household.household_id)
+            .bind(owner)
+            .bind(Role::Owner.as_str())
+            .execute(&mut *tx)
+            .await?;
+
+            tx.commit().await?;
+        });
+        Ok(Membership {
+            household: household.clone(),
+            role: Role::Owner,
+        })
+    }
+
+    async fn default_for(&self, user_id: &UserId) -> Result<Membership, HouseholdError> {
+        let membership = with_pool!(&self.pool, |pool| {
+            query_as::<_, Membership>(
+                r#"
+                    SELECT h.household_id, h.household_name, m.role
+                    FROM household_member_table m
+                    INNER JOIN household_table h ON h.household_id = m.household_id
+                    WHERE m.user_id = ? AND m.role <> ?
+                    ORDER BY m.id
+                    LIMIT 1
+                "#,
+            )
+            .bind(user_id)
+            .bind(Role::ReadOnly.as_str())
+            .fetch_optional(pool)
+            .await
+        })?;
+
+        match membership {
+            Some(membership) => Ok(membership),
+            None => self.insert(&Household::default_home(), user_id).await,
+        }
+    }
+
+    async fn role(
+        &self,
+        household_id: &HouseholdId,
+        user_id: &UserId,
+    ) -> Result<Role, HouseholdError> {
+        let role = with_pool!(&self.pool, |pool| {
+            query_scalar::<_, String>(
+                r#"
+                    SELECT role
+                    FROM household_member_table
+                    WHERE household_id = ? AND user_id = ?
+                "#,
+            )
+            .bind(household_id)
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await
+        })?
+        .ok_or(HouseholdError::NotFound)?;
+        Role::parse(&role).ok_or(HouseholdError::NotFound)
+    }
+
+    async fn read(
+        &self,
+        household_id: &HouseholdId,
+        user_id: &UserId,
+    ) -> Result<HouseholdDetail, HouseholdError> {
+        self.role(household_id, user_id).await?;
+        let household = with_pool!(&self.pool, |pool| {
+            query_as::<_, Household>(
+                r#"
+                    SELECT household_id, household_name
+                    FROM household_table
+                    WHERE household_id = ?
+                "#,
+            )
+            .bind(household_id)
+            .fetch_one(pool)
+            .await
+        })?;
+
+        let members = with_pool!(&self.pool, |pool| {
+            query_as::<_, Member>(
+                r#"
+                    SELECT m.user_id, u.user_name, m.role
+                    FROM household_member_table m
+                    INNER JOIN user_table u ON u.user_id = m.user_id
+                    WHERE m.household_id = ?
+                    ORDER BY m.id
+                "#,
+            )
+            .bind(household_id)
+            .fetch_all(pool)
+            .await
+        })?;
+        Ok(HouseholdDetail { household, members })
+    }
+
+    async fn delete(
+        &self,
+        household_id: &HouseholdId,
+        user_id: &UserId,
+    ) -> Result<(), HouseholdError> {
+        self.check_owner(household_id, user_id).await?;
+        with_pool!(&self.pool, |pool| {
+            let mut tx = pool.begin().await?;
+            query(
+                r#"
+                    DELETE FROM food_table
+                    WHERE location_id IN (
+                        SELECT location_id
+                        FROM storage_location_table
+                        WHERE household_id = ?
+                    )
+                "#,
+            )
+            .bind(household_id)
+            .execute(&mut *tx)
+            .await?;
+
+            let rows = query(
+                r#"
+                    DELETE FROM household_table
+                    WHERE household_id = ?
+                "#,
+            )
+            .bind(household_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+            if rows == 0 {
+                return Err(HouseholdError::NotFound);
+            }
+            tx.commit().await?;
+        });
+        Ok(())
+    }
+
+    async fn insert_invite(&self, invite: &Invite) -> Result<(), HouseholdError> {
+        // Expired invites can never be accepted, so drop them while we are here.
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    DELETE FROM household_invite_table
+                    WHERE expires_at <= ?
+                "#,
+            )
+            .bind(Utc::now().naive_utc())
+            .execute(pool)
+            .await?;
+        });
+
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    INSERT INTO household_invite_table
+                    (token_hash, household_id, mail, role, expires_at)
+                    VALUES (?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(&invite.token_hash)
+            .bind(&invite.household_id)
+            .bind(&invite.mail)
+            .bind(invite.role.as_str())
+            .bind(invite.expires_at)
+            .execute(pool)
+            .await?;
+        });
+        Ok(())
+    }
+
+    async fn accept_invite(
+        &self,
+        token: &InviteToken,
+        user_id: &UserId,
+    ) -> Result<Membership, HouseholdError> {
+        let token_hash = token.hash();
+        with_pool!(&self.pool, |pool| {
+            let mut tx = pool.begin().await?;
+
+            // Claiming the invite before reading it locks it, so only one accept can use it.
+            let rows = query(
+                r#"
+                    UPDATE household_invite_table
+                    SET expires_at = expires_at
+                    WHERE token_hash = ? AND expires_at > ?
+                "#,
+            )
+            .bind(&token_hash)
+            .bind(Utc::now().naive_utc())
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            if rows == 0 {
+                return Err(HouseholdError::NotFound);
+            }
+
+            let invite = query_as::<_, Invite>(
+                r#"
+                    SELECT token_hash, household_id, mail, role, expires_at
+                    FROM household_invite_table
+                    WHERE token_hash = ?
+                "#,
+            )
+            .bind(&token_hash)
+            .fetch_one(&mut *tx)
+            .await?;
+
+            let mail = query_scalar::<_, Mail>(
+                r#"
+                    SELECT mail
+                    FROM user_table
+                    WHERE user_id = ?
+                "#,
+            )
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+            if mail != invite.mail {
+                return Err(HouseholdError::InviteMismatch);
+            }
+
+            query(
+                r#"
+                    INSERT INTO household_member_table
+                    (household_id, user_id, role)
+                    VALUES (?, ?, ?)
+                "#,
+            )
+            .bind(&invite.household_id)
+            .bind(user_id)
+            .bind(invite.role.as_str())
+            .execute(&mut *tx)
+            .await?;
+
+            query(
+                r#"
+                    DELETE FROM household_invite_table
+                    WHERE token_hash = ?
+                "#,
+            )
+            .bind(&token_hash)
+            .execute(&mut *tx)
+            .await?;
+
+            let household = query_as::<_, Household>(
+                r#"
+                    SELECT household_id, household_name
+                    FROM household_table
+                    WHERE household_id = ?
+                "#,
+            )
+            .bind(&invite.household_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+            tx.commit().await?;
+            Ok(Membership {
+                household,
+                role: invite.role,
+            })
+        })
+    }
+
+    async fn update_member(
+        &self,
+        household_id: &HouseholdId,
+        member_id: &UserId,
+        role: Role,
+    ) -> Result<(), HouseholdError> {
+        with_pool!(&self.pool, |pool| {
+            let mut tx = pool.begin().await?;
+            // Membership changes of a household take turns, so two owners cannot demote each
+            // other at the same time and leave it without one.
+            let rows = query(
+                r#"
+                    UPDATE household_table
+                    SET household_name = household_name
+                    WHERE household_id = ?
+                "#,
+            )
+            .bind(household_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            if rows == 0 {
+                return Err(HouseholdError::NotFound);
+            }
+            let (current, owners) = query_as::<_, (String, i64)>(MEMBER_ROLE_QUERY)
+                .bind(Role::Owner.as_str())
+                .bind(household_id)
+                .bind(member_id)
+                .fetch_optional(&mut *tx)
+                .await?
+                .ok_or(HouseholdError::NotFound)?;
+            if role != Role::Owner {
+                check_not_last_owner(&current, owners)?;
+            }
+
+            query(
+                r#"
+                    UPDATE household_member_table
+                    SET role = ?
+                    WHERE household_id = ? AND user_id = ?
+                "#,
+            )
+            .bind(role.as_str())
+            .bind(household_id)
+            .bind(member_id)
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+        });
+        Ok(())
+    }
+
+    async fn delete_member(
+        &self,
+        household_id: &HouseholdId,
+        member_id: &UserId,
+    ) -> Result<(), HouseholdError> {
+        with_pool!(&self.pool, |pool| {
+            let mut tx = pool.begin().await?;
+            let rows = query(
+                r#"
+                    UPDATE household_table
+                    SET household_name = household_name
+                    WHERE household_id = ?
+                "#,
+            )
+            .bind(household_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            if rows == 0 {
+                return Err(HouseholdError::NotFound);
+            }
+            let (current, owners) = query_as::<_, (String, i64)>(MEMBER_ROLE_QUERY)
+                .bind(Role::Owner.as_str())
+                .bind(household_id)
+                .bind(member_id)
+                .fetch_optional(&mut *tx)
+                .await?
+                .ok_or(HouseholdError::NotFound)?;
+            check_not_last_owner(&current, owners)?;
+
+            query(
+                r#"
+                    DELETE FROM household_member_table
+                    WHERE household_id = ? AND user_id = ?
+                "#,
+            )
+            .bind(household_id)
+            .bind(member_id)
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+        });
+        Ok(())
+    }
+}
+
+// Refuses to let the only owner of a household step down or leave.
+pub(super) fn check_not_last_owner(role: &str, owners: i64) -> Result<(), HouseholdError> {
+    if Role::parse(role) == Some(Role::Owner) && owners <= 1 {
+        return Err(HouseholdError::LastOwner);
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl<T> RepositoryAllReader<T> for HouseholdRepository
+where
+    T: Into<UserId> + Clone + Send + Sync + 'static,
+{
+    type QueryRes = AllHouseholds;
+    type QueryErr = HouseholdError;
+
+    async fn read_all(&self, id: T) -> Result<Self::QueryRes, Self::QueryErr> {
+        let households = with_pool!(&self.pool, |pool| {
+            query_as::<_, Membership>(
+                r#"
+                    SELECT h.household_id, h.household_name, m.role
+                    FROM household_member_table m
+                    INNER JOIN household_table h ON h.household_id = m.household_id
+                    WHERE m.user_id = ?
+                    ORDER BY m.id
+                "#,
+            )
+            .bind::<UserId>(id.clone().into())
+            .fetch_all(pool)
+            .await
+        })?;
+        Ok(AllHouseholds { households })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        households::{
+            CreateHouseholdPayload, Household, HouseholdError, HouseholdName, Invite,
+            InvitePayload, InviteToken, Role,
+        },
+        testing::TestDb,
+    };
+
+    fn flat() -> Household {
+        Household::new(CreateHouseholdPayload {
+            household_name: HouseholdName::try_from("Flat 3").unwrap(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_default_household_created_once() {
+        for db in TestDb::each().await {
+            let repo = db.households();
+            let user = db.alice.info();
+
+            let home = repo.default_for(&user.user_id).await.unwrap();
+            assert_eq!(home.role, Role::Owner);
+            assert_eq!(repo.default_for(&user.user_id).await.unwrap(), home);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invite_and_accept() {
+        for db in TestDb::each().await {
+            let repo = db.households();
+            let (owner, guest, stranger) = (db.alice.info(), db.bob.info(), db.carol.info());
+
+            let household = flat();
+            repo.insert(&household, &owner.user_id).await.unwrap();
+            let (invite, token) = Invite::new(
+                InvitePayload {
+                    mail: db.bob.mail.clone(),
+                    role: Role::ReadOnly,
+                },
+                household.household_id.clone(),
+            );
+            repo.insert_invite(&invite).await.unwrap();
+
+            assert!(matches!(
+                repo.accept_invite(&token, &stranger.user_id).await,
+                Err(HouseholdError::InviteMismatch)
+            ));
+            let membership = repo.accept_invite(&token, &guest.user_id).await.unwrap();
+            assert_eq!(membership.household, household);
+            assert_eq!(membership.role, Role::ReadOnly);
+            assert!(matches!(
+                repo.accept_invite(&token, &guest.user_id).await,
+                Err(HouseholdError::NotFound)
+            ));
+
+            let all = repo.read_all(guest.user_id.clone()).await.unwrap();
+            assert!(all.households.contains(&membership));
+            let detail = repo
+                .read(&household.household_id, &guest.user_id)
+                .await
+                .unwrap();
+            assert_eq!(detail.members.len(), 2);
+            assert!(matches!(
+                repo.read(&household.household_id, &stranger.user_id).await,
+                Err(HouseholdError::NotFound)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_invite_not_found() {
+        for db in TestDb::each().await {
+            let repo = db.households();
+            let user = db.alice.info();
+
+            assert!(matches!(
+                repo.accept_invite(&InviteToken::from("missing_token"), &user.user_id)
+                    .await,
+                Err(HouseholdError::NotFound)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_last_owner_stays() {
+        for db in TestDb::each().await {
+            let repo = db.households();
+            let owner = db.alice.info();
+            let household = flat();
+            repo.insert(&household, &owner.user_id).await.unwrap();
+
+            assert!(matches!(
+                repo.update_member(&household.household_id, &owner.user_id, Role::Member)
+                    .await,
+                Err(HouseholdError::LastOwner)
+            ));
+            assert!(matches!(
+                repo.delete_member(&household.household_id, &owner.user_id)
+                    .await,
+                Err(HouseholdError::LastOwner)
+            ));
+
+            repo.delete(&household.household_id, &owner.user_id)
+                .await
+                .unwrap();
+            assert!(matches!(
+                repo.role(&household.household_id, &owner.user_id).await,
+                Err(HouseholdError::NotFound)
+            ));
+        }
+    }
+}
@@ -2,16 +2,30 @@ use std::error::Error;
 
 use async_trait::async_trait;
 use serde::Serialize;
-use sqlx::{mysql::MySqlRow, FromRow};
 
+pub mod api_tokens;
 pub mod auth;
+mod db;
 pub mod foods;
+pub mod households;
+pub mod locations;
+pub mod mail;
+mod memory;
+pub mod migrate;
+pub mod notify;
+pub mod server;
+#[cfg(test)]
+mod testing;
+pub mod two_factor;
 pub mod users;
 pub mod util;
+pub mod validation;
+
+pub use db::DbPool;
 
 #[async_trait]
-trait RepositoryWriter<'a, 'r, Payload, Target>: RepositoryTargetReader<'a, Target> {
-    type Output: Serialize + FromRow<'r, MySqlRow>;
+trait RepositoryWriter<'a, Payload, Target>: RepositoryTargetReader<'a, Target> {
+    type Output: Serialize;
     type Error: Error;
 
     async fn insert(&self, payload: &Payload) -> Result<Self::Output, Self::Error>;
@@ -0,0 +1,267 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::Type, Row};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{
+    db::{classify, impl_from_row, impl_uuid_type, DbErrorKind},
+    households::HouseholdId,
+    users::{PubUserInfo, UserId},
+    validation::{trimmed_text, InvalidValue},
+};
+
+pub(crate) mod handler;
+pub(crate) mod memory;
+pub(crate) mod repo;
+
+static LOCATION_ID_COLUMN: &str = "location_id";
+static LOCATION_NAME_COLUMN: &str = "location_name";
+static LOCATION_KIND_COLUMN: &str = "kind";
+static LOCATION_TEMPERATURE_COLUMN: &str = "temperature";
+static USER_ID_COLUMN: &str = "user_id";
+static HOUSEHOLD_ID_COLUMN: &str = "household_id";
+
+static DEFAULT_LOCATION_NAME: &str = "Fridge";
+const MAX_LOCATION_NAME_CHARS: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LocationId(String);
+
+impl_uuid_type!(LocationId);
+
+impl From<LocationId> for String {
+    fn from(value: LocationId) -> Self {
+        value.0
+    }
+}
+
+impl<T> From<T> for LocationId
+where
+    T: ToString,
+{
+    fn from(value: T) -> Self {
+        Self(value.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Type)]
+#[serde(try_from = "String")]
+#[sqlx(transparent)]
+pub struct LocationName(String);
+
+impl From<LocationName> for String {
+    fn from(value: LocationName) -> Self {
+        value.0
+    }
+}
+
+impl TryFrom<String> for LocationName {
+    type Error = InvalidValue;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        trimmed_text(&value, MAX_LOCATION_NAME_CHARS).map(Self)
+    }
+}
+
+impl TryFrom<&str> for LocationName {
+    type Error = InvalidValue;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::try_from(value.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LocationKind {
+    Fridge,
+    Freezer,
+    Pantry,
+}
+
+impl LocationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LocationKind::Fridge => "fridge",
+            LocationKind::Freezer => "freezer",
+            LocationKind::Pantry => "pantry",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        [
+            LocationKind::Fridge,
+            LocationKind::Freezer,
+            LocationKind::Pantry,
+        ]
+        .into_iter()
+        .find(|kind| kind.as_str() == value)
+    }
+}
+
+// A location id scoped to the user who must be a member of the location's household.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedLocationId {
+    pub location_id: LocationId,
+    pub user_id: UserId,
+}
+
+impl OwnedLocationId {
+    pub fn new(location_id: LocationId, user_id: UserId) -> Self {
+        Self {
+            location_id,
+            user_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateLocationPayload {
+    location_name: LocationName,
+    kind: LocationKind,
+    #[serde(default)]
+    temperature: Option<f64>,
+    // Falls back to the user's default household when left out.
+    #[serde(default)]
+    pub(crate) household_id: Option<HouseholdId>,
+}
+
+// `user_id` records who added the location, it belongs to the whole household.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct StorageLocation {
+    location_id: LocationId,
+    location_name: LocationName,
+    kind: LocationKind,
+    temperature: Option<f64>,
+    household_id: HouseholdId,
+    user_id: UserId,
+}
+
+impl StorageLocation {
+    pub fn new(
+        payload: CreateLocationPayload,
+        user: PubUserInfo,
+        household_id: HouseholdId,
+    ) -> Self {
+        Self::with_id(
+            LocationId::from(Uuid::new_v4().to_string()),
+            payload,
+            user.user_id,
+            household_id,
+        )
+    }
+
+    pub(crate) fn with_id(
+        location_id: LocationId,
+        payload: CreateLocationPayload,
+        user_id: UserId,
+        household_id: HouseholdId,
+    ) -> Self {
+        Self {
+            location_id,
+            location_name: payload.location_name,
+            kind: payload.kind,
+            temperature: payload.temperature,
+            household_id,
+            user_id,
+        }
+    }
+
+    // The location foods go to when the user does not pick one.
+    pub(crate) fn default_fridge(user_id: UserId, household_id: HouseholdId) -> Self {
+        let payload = CreateLocationPayload {
+            location_name: LocationName(DEFAULT_LOCATION_NAME.to_string()),
+            kind: LocationKind::Fridge,
+            temperature: None,
+            household_id: None,
+        };
+        Self::with_id(
+            LocationId::from(Uuid::new_v4()),
+            payload,
+            user_id,
+            household_id,
+        )
+    }
+
+    pub fn location_id(&self) -> &LocationId {
+        &self.location_id
+    }
+
+    pub fn household_id(&self) -> &HouseholdId {
+        &self.household_id
+    }
+
+    pub fn user_id(&self) -> &UserId {
+        &self.user_id
+    }
+
+    pub fn owned_id(&self) -> OwnedLocationId {
+        OwnedLocationId::new(self.location_id.clone(), self.user_id.clone())
+    }
+}
+
+impl_from_row!(StorageLocation, |row| {
+    Ok(StorageLocation {
+        location_id: row.try_get(LOCATION_ID_COLUMN)?,
+        location_name: LocationName(row.try_get(LOCATION_NAME_COLUMN)?),
+        kind: LocationKind::parse(row.try_get(LOCATION_KIND_COLUMN)?).ok_or_else(|| {
+            sqlx::Error::ColumnDecode {
+                index: LOCATION_KIND_COLUMN.to_string(),
+                source: "unknown location kind".into(),
+            }
+        })?,
+        temperature: row.try_get(LOCATION_TEMPERATURE_COLUMN)?,
+        household_id: row.try_get(HOUSEHOLD_ID_COLUMN)?,
+        user_id: row.try_get(USER_ID_COLUMN)?,
+    })
+});
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AllLocations {
+    locations: Vec<StorageLocation>,
+}
+
+#[derive(Debug, Error)]
+pub enum LocationError {
+    #[error("Not found")]
+    NotFound,
+    #[error("Location belongs to another household or is read-only")]
+    Forbidden,
+    #[error("Location already exists")]
+    Duplicate(#[source] sqlx::Error),
+    #[error("Location still holds foods")]
+    ForeignKey(#[source] sqlx::Error),
+    #[error("Invalid location")]
+    Validation(#[source] sqlx::Error),
+    #[error("Database unavailable")]
+    Unavailable(#[source] sqlx::Error),
+    #[error("Database error")]
+    Database(#[source] sqlx::Error),
+}
+
+impl From<sqlx::Error> for LocationError {
+    fn from(value: sqlx::Error) -> Self {
+        match classify(&value) {
+            DbErrorKind::NotFound => LocationError::NotFound,
+            DbErrorKind::UniqueViolation => LocationError::Duplicate(value),
+            DbErrorKind::ForeignKeyViolation => LocationError::ForeignKey(value),
+            DbErrorKind::Validation => LocationError::Validation(value),
+            DbErrorKind::Unavailable => LocationError::Unavailable(value),
+            DbErrorKind::Other => LocationError::Database(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LocationName;
+
+    #[test]
+    fn test_location_name_validation() {
+        let name: LocationName = serde_json::from_str(r#"" Garage freezer ""#).unwrap();
+        assert_eq!(name, LocationName::try_from("Garage freezer").unwrap());
+        assert!(LocationName::try_from("   ").is_err());
+        assert!(LocationName::try_from("x".repeat(65)).is_err());
+        assert!(serde_json::from_str::<LocationName>(r#""""#).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use hyper::{body::Bytes, StatusCode};
+
+use crate::{
+    households::{HouseholdError, HouseholdId},
+    server::{empty_response, json_response, parse_json, ApiError, AppState, HttpResponse},
+    users::PubUserInfo,
+};
+
+use super::{CreateLocationPayload, LocationError, LocationId, OwnedLocationId, StorageLocation};
+
+impl From<LocationError> for ApiError {
+    fn from(value: LocationError) -> Self {
+        match value {
+            LocationError::NotFound => ApiError::new(StatusCode::NOT_FOUND, value),
+            LocationError::Forbidden => ApiError::new(StatusCode::FORBIDDEN, value),
+            LocationError::Duplicate(_) | LocationError::ForeignKey(_) => {
+                ApiError::new(StatusCode::CONFLICT, value)
+            }
+            LocationError::Validation(_) => ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, value),
+            LocationError::Unavailable(_) => {
+                ApiError::logged(StatusCode::SERVICE_UNAVAILABLE, &value)
+            }
+            LocationError::Database(_) => {
+                ApiError::logged(StatusCode::INTERNAL_SERVER_ERROR, &value)
+            }
+        }
+    }
+}
+
+// The household a new location goes to: the requested one if the user may add to it, the
+// user's default household otherwise.
+async fn writable_household(
+    state: &AppState,
+    user: &PubUserInfo,
+    household_id: Option<HouseholdId>,
+) -> Result<HouseholdId, ApiError> {
+    match household_id {
+        Some(household_id) => {
+            let role = state.households.role(&household_id, &user.user_id).await?;
+            if !role.can_write() {
+                return Err(HouseholdError::Forbidden.into());
+            }
+            Ok(household_id)
+        }
+        None => {
+            let membership = state.households.default_for(&user.user_id).await?;
+            Ok(membership.household().household_id().clone())
+        }
+    }
+}
+
+pub(crate) async fn create(
+    state: &AppState,
+    user: PubUserInfo,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let mut payload: CreateLocationPayload = parse_json(&body)?;
+    let household_id = writable_household(state, &user, payload.household_id.take()).await?;
+    let location = state
+        .locations
+        .insert(&StorageLocation::new(payload, user, household_id))
+        .await?;
+    Ok(json_response(StatusCode::CREATED, &location))
+}
+
+pub(crate) async fn read(
+    state: &AppState,
+    user: PubUserInfo,
+    location_id: &str,
+) -> Result<HttpResponse, ApiError> {
+    let id = OwnedLocationId::new(LocationId::from(location_id), user.user_id);
+    let location = state.locations.read(&id).await?;
+    Ok(json_response(StatusCode::OK, &location))
+}
+
+pub(crate) async fn read_all(
+    state: &AppState,
+    user: PubUserInfo,
+) -> Result<HttpResponse, ApiError> {
+    let locations = state.locations.read_all(user.user_id).await?;
+    Ok(json_response(StatusCode::OK, &locations))
+}
+
+pub(crate) async fn update(
+    state: &AppState,
+    user: PubUserInfo,
+    location_id: &str,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let payload: CreateLocationPayload = parse_json(&body)?;
+    let id = OwnedLocationId::new(LocationId::from(location_id), user.user_id);
+    let current = state.locations.read(&id).await?;
+    let location = StorageLocation::with_id(
+        id.location_id.clone(),
+        payload,
+        current.user_id,
+        current.household_id,
+    );
+    let location = state.locations.update(&id, &location).await?;
+    Ok(json_response(StatusCode::OK, &location))
+}
+
+pub(crate) async fn delete(
+    state: &AppState,
+    user: PubUserInfo,
+    location_id: &str,
+) -> Result<HttpResponse, ApiError> {
+    let id = OwnedLocationId::new(LocationId::from(location_id), user.user_id);
+    state.locations.delete(&id).await?;
+    Ok(empty_response(StatusCode::NO_CONTENT))
+}
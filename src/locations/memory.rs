@@ -0,0 +1,170 @@
+use async_trait::async_trait;
+
+use crate::{
+    households::{HouseholdId, Role},
+    memory::{ConstraintError, MemoryStore, Tables},
+    users::UserId,
+    RepositoryAllReader, RepositoryTargetReader, RepositoryWriter,
+};
+
+use super::{repo::LocationStore, AllLocations, LocationError, OwnedLocationId, StorageLocation};
+
+pub struct MemoryLocationRepository {
+    store: MemoryStore,
+}
+
+impl MemoryLocationRepository {
+    pub(crate) fn new(store: MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+// The role of the user in the household of the location. Tells a missing location apart from
+// one in a household the user is not a member of.
+fn check_access(tables: &Tables, id: &OwnedLocationId) -> Result<Role, LocationError> {
+    let location = tables
+        .locations
+        .iter()
+        .find(|location| location.location_id == id.location_id)
+        .ok_or(LocationError::NotFound)?;
+    tables
+        .role(&location.household_id, &id.user_id)
+        .ok_or(LocationError::Forbidden)
+}
+
+fn check_writer(tables: &Tables, id: &OwnedLocationId) -> Result<(), LocationError> {
+    if check_access(tables, id)?.can_write() {
+        Ok(())
+    } else {
+        Err(LocationError::Forbidden)
+    }
+}
+
+fn insert_location(
+    tables: &mut Tables,
+    payload: &StorageLocation,
+) -> Result<StorageLocation, LocationError> {
+    if tables
+        .locations
+        .iter()
+        .any(|location| location.location_id == payload.location_id)
+    {
+        return Err(ConstraintError::unique("location_id_idx").into());
+    }
+    if tables.household(&payload.household_id).is_none() {
+        return Err(ConstraintError::foreign_key("location_household_id").into());
+    }
+    tables.locations.push(payload.clone());
+    Ok(payload.clone())
+}
+
+#[async_trait]
+impl LocationStore for MemoryLocationRepository {
+    async fn default_for(
+        &self,
+        household_id: &HouseholdId,
+        user_id: &UserId,
+    ) -> Result<StorageLocation, LocationError> {
+        let mut tables = self.store.lock();
+        let location = tables
+            .locations
+            .iter()
+            .find(|location| &location.household_id == household_id)
+            .cloned();
+
+        match location {
+            Some(location) => Ok(location),
+            None => insert_location(
+                &mut tables,
+                &StorageLocation::default_fridge(user_id.clone(), household_id.clone()),
+            ),
+        }
+    }
+
+    async fn check_writer(&self, id: &OwnedLocationId) -> Result<(), LocationError> {
+        check_writer(&self.store.lock(), id)
+    }
+}
+
+#[async_trait]
+impl<'a> RepositoryWriter<'a, StorageLocation, OwnedLocationId> for MemoryLocationRepository {
+    type Output = StorageLocation;
+    type Error = LocationError;
+
+    async fn insert(&self, payload: &StorageLocation) -> Result<Self::Output, Self::Error> {
+        insert_location(&mut self.store.lock(), payload)
+    }
+
+    async fn update(
+        &self,
+        id: &'a OwnedLocationId,
+        payload: &StorageLocation,
+    ) -> Result<Self::Output, Self::Error> {
+        let mut tables = self.store.lock();
+        check_writer(&tables, id)?;
+        let location = tables
+            .locations
+            .iter_mut()
+            .find(|location| location.location_id == id.location_id)
+            .ok_or(LocationError::NotFound)?;
+        location.location_name = payload.location_name.clone();
+        location.kind = payload.kind;
+        location.temperature = payload.temperature;
+        Ok(location.clone())
+    }
+
+    // Foods keep their location from being removed, as `ON DELETE RESTRICT` does.
+    async fn delete(&self, id: &'a OwnedLocationId) -> Result<(), Self::Error> {
+        let mut tables = self.store.lock();
+        check_writer(&tables, id)?;
+        if tables
+            .foods
+            .iter()
+            .any(|food| food.location_id() == &id.location_id)
+        {
+            return Err(ConstraintError::foreign_key("food_location_id").into());
+        }
+        tables
+            .locations
+            .retain(|location| location.location_id != id.location_id);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'a> RepositoryTargetReader<'a, OwnedLocationId> for MemoryLocationRepository {
+    type QueryRes = StorageLocation;
+    type QueryErr = LocationError;
+
+    async fn read(&self, id: &'a OwnedLocationId) -> Result<Self::QueryRes, Self::QueryErr> {
+        let tables = self.store.lock();
+        check_access(&tables, id)?;
+        tables
+            .locations
+            .iter()
+            .find(|location| location.location_id == id.location_id)
+            .cloned()
+            .ok_or(LocationError::NotFound)
+    }
+}
+
+#[async_trait]
+impl<T> RepositoryAllReader<T> for MemoryLocationRepository
+where
+    T: Into<UserId> + Clone + Send + Sync + 'static,
+{
+    type QueryRes = AllLocations;
+    type QueryErr = LocationError;
+
+    async fn read_all(&self, id: T) -> Result<Self::QueryRes, Self::QueryErr> {
+        let user_id: UserId = id.into();
+        let tables = self.store.lock();
+        let locations = tables
+            .locations
+            .iter()
+            .filter(|location| tables.role(&location.household_id, &user_id).is_some())
+            .cloned()
+            .collect();
+        Ok(AllLocations { locations })
+    }
+}
@@ -0,0 +1,351 @@
+use async_trait::async_trait;
+use sqlx::{query, query_as, query_scalar};
+
+use crate::{
+    db::{with_pool, DbPool},
+    households::{HouseholdId, Role},
+    users::UserId,
+    RepositoryAllReader, RepositoryTargetReader, RepositoryWriter,
+};
+
+use super::{AllLocations, LocationError, OwnedLocationId, StorageLocation};
+
+// What the handlers need from a location backend, either `LocationRepository` or
+// `MemoryLocationRepository`.
+#[async_trait]
+pub(crate) trait LocationStore:
+    for<'a> RepositoryWriter<
+        'a,
+        StorageLocation,
+        OwnedLocationId,
+        Output = StorageLocation,
+        Error = LocationError,
+    > + for<'a> RepositoryTargetReader<
+        'a,
+        OwnedLocationId,
+        QueryRes = StorageLocation,
+        QueryErr = LocationError,
+    > + RepositoryAllReader<UserId, QueryRes = AllLocations, QueryErr = LocationError>
+    + Send
+    + Sync
+{
+    // The oldest location of the household, creating the default fridge if it has none yet.
+    async fn default_for(
+        &self,
+        household_id: &HouseholdId,
+        user_id: &UserId,
+    ) -> Result<StorageLocation, LocationError>;
+
+    async fn check_writer(&self, id: &OwnedLocationId) -> Result<(), LocationError>;
+}
+
+pub struct LocationRepository {
+    pool: DbPool,
+}
+
+impl LocationRepository {
+    pub(crate) fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    // The role of the user in the household of the location. Tells a missing location apart
+    // from one in a household the user is not a member of.
+    async fn check_access(&self, id: &OwnedLocationId) -> Result<Role, LocationError> {
+        let role = with_pool!(&self.pool, |pool| {
+            query_scalar::<_, Option<String>>(
+                r#"
+                    SELECT m.role
+                    FROM storage_location_table l
+                    LEFT JOIN household_member_table m
+                    ON m.household_id = l.household_id AND m.user_id = ?
+                    WHERE l.location_id = ?
+                "#,
+            )
+            .bind(&id.user_id)
+            .bind(&id.location_id)
+            .fetch_optional(pool)
+            .await
+        })?;
+
+        match role {
+            Some(Some(role)) => Role::parse(&role).ok_or(LocationError::Forbidden),
+            Some(None) => Err(LocationError::Forbidden),
+            None => Err(LocationError::NotFound),
+        }
+    }
+}
+
+#[async_trait]
+impl LocationStore for LocationRepository {
+    async fn default_for(
+        &self,
+        household_id: &HouseholdId,
+        user_id: &UserId,
+    ) -> Result<StorageLocation, LocationError> {
+        let location = with_pool!(&self.pool, |pool| {
+            query_as::<_, StorageLocation>(
+                r#"
+                    SELECT location_id, location_name, kind, temperature, household_id, user_id
+                    FROM storage_location_table
+                    WHERE household_id = ?
+                    ORDER BY id
+                    LIMIT 1
+                "#,
+            )
+            .bind(household_id)
+            .fetch_optional(pool)
+            .await
+        })?;
+
+        match location {
+            Some(location) => Ok(location),
+            None => {
+                self.insert(&StorageLocation::default_fridge(
+                    user_id.clone(),
+                    household_id.clone(),
+                ))
+                .await
+            }
+        }
+    }
+
+    async fn check_writer(&self, id: &OwnedLocationId) -> Result<(), LocationError> {
+        if self.check_access(id).await?.can_write() {
+            Ok(())
+        } else {
+            Err(LocationError::Forbidden)
+        }
+    }
+}
+
+#[async_trait]
+impl<'a> RepositoryWriter<'a, StorageLocation, OwnedLocationId> for LocationRepository {
+    type Output = StorageLocation;
+    type Error = LocationError;
+
+    async fn insert(&self, payload: &StorageLocation) -> Result<Self::Output, Self::Error> {
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    INSERT INTO storage_location_table
+                    (location_id, location_name, kind, temperature, household_id, user_id)
+                    VALUES (?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(&payload.location_id)
+            .bind(&payload.location_name)
+            .bind(payload.kind.as_str())
+            .bind(payload.temperature)
+            .bind(&payload.household_id)
+            .bind(&payload.user_id)
+            .execute(pool)
+            .await?;
+        });
+        Ok(payload.clone())
+    }
+
+    async fn update(
+        &self,
+        id: &'a OwnedLocationId,
+        payload: &StorageLocation,
+    ) -> Result<Self::Output, Self::Error> {
+        self.check_writer(id).await?;
+        let rows = with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    UPDATE storage_location_table
+                    SET
+                    location_name = ?, kind = ?, temperature = ?
+                    WHERE location_id = ?
+                "#,
+            )
+            .bind(&payload.location_name)
+            .bind(payload.kind.as_str())
+            .bind(payload.temperature)
+            .bind(&id.location_id)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+
+        if rows == 0 {
+            return Err(LocationError::NotFound);
+        }
+        self.read(id).await
+    }
+
+    async fn delete(&self, id: &'a OwnedLocationId) -> Result<(), Self::Error> {
+        self.check_writer(id).await?;
+        let rows = with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    DELETE FROM storage_location_table
+                    WHERE location_id = ?
+                "#,
+            )
+            .bind(&id.location_id)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+
+        if rows == 0 {
+            return Err(LocationError::NotFound);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'a> RepositoryTargetReader<'a, OwnedLocationId> for LocationRepository {
+    type QueryRes = StorageLocation;
+    type QueryErr = LocationError;
+
+    async fn read(&self, id: &'a OwnedLocationId) -> Result<Self::QueryRes, Self::QueryErr> {
+        let location = with_pool!(&self.pool, |pool| {
+            query_as::<_, StorageLocation>(
+                r#"
+                    SELECT l.location_id, l.location_name, l.kind, l.temperature, l.household_id,
+                    l.user_id
+                    FROM storage_location_table l
+                    INNER JOIN household_member_table m ON m.household_id = l.household_id
+                    WHERE l.location_id = ? AND m.user_id = ?
+                "#,
+            )
+            .bind(&id.location_id)
+            .bind(&id.user_id)
+            .fetch_optional(pool)
+            .await
+        })?;
+
+        match location {
+            Some(location) => Ok(location),
+            None => self
+                .check_access(id)
+                .await
+                .and(Err(LocationError::NotFound)),
+        }
+    }
+}
+
+// Locations of every household the user is a member of.
+#[async_trait]
+impl<T> RepositoryAllReader<T> for LocationRepository
+where
+    T: Into<UserId> + Clone + Send + Sync + 'static,
+{
+    type QueryRes = AllLocations;
+    type QueryErr = LocationError;
+
+    async fn read_all(&self, id: T) -> Result<Self::QueryRes, Self::QueryErr> {
+        let locations = with_pool!(&self.pool, |pool| {
+            query_as::<_, StorageLocation>(
+                r#"
+                    SELECT l.location_id, l.location_name, l.kind, l.temperature, l.household_id,
+                    l.user_id
+                    FROM storage_location_table l
+                    INNER JOIN household_member_table m ON m.household_id = l.household_id
+                    WHERE m.user_id = ?
+                    ORDER BY l.id
+                "#,
+            )
+            .bind::<UserId>(id.clone().into())
+            .fetch_all(pool)
+            .await
+        })?;
+        Ok(AllLocations { locations })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        households::HouseholdId,
+        locations::{
+            CreateLocationPayload, LocationError, LocationKind, LocationName, OwnedLocationId,
+            StorageLocation,
+        },
+        testing::{TestDb, TestUser},
+    };
+
+    async fn home_of(db: &TestDb, user: &TestUser) -> HouseholdId {
+        let home = db.households().default_for(&user.user_id).await.unwrap();
+        home.household().household_id().clone()
+    }
+
+    fn garage_freezer() -> CreateLocationPayload {
+        CreateLocationPayload {
+            location_name: LocationName::try_from("Garage freezer").unwrap(),
+            kind: LocationKind::Freezer,
+            temperature: Some(-18.0),
+            household_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_default_location_created_once() {
+        for db in TestDb::each().await {
+            let repo = db.locations();
+            let (user, home) = (db.alice.info(), home_of(&db, &db.alice).await);
+
+            let default = repo.default_for(&home, &user.user_id).await.unwrap();
+            assert_eq!(default.kind, LocationKind::Fridge);
+            assert_eq!(
+                repo.default_for(&home, &user.user_id).await.unwrap(),
+                default
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_location_crud() {
+        for db in TestDb::each().await {
+            let repo = db.locations();
+            let (user, home) = (db.alice.info(), home_of(&db, &db.alice).await);
+
+            let location = repo
+                .insert(&StorageLocation::new(garage_freezer(), user.clone(), home))
+                .await
+                .unwrap();
+            assert_eq!(repo.read(&location.owned_id()).await.unwrap(), location);
+
+            let renamed = StorageLocation {
+                location_name: LocationName::try_from("Chest freezer").unwrap(),
+                ..location.clone()
+            };
+            let updated = repo.update(&location.owned_id(), &renamed).await.unwrap();
+            assert_eq!(updated, renamed);
+
+            let all = repo.read_all(user.user_id.clone()).await.unwrap();
+            assert_eq!(all.locations, vec![renamed]);
+
+            repo.delete(&location.owned_id()).await.unwrap();
+            assert!(matches!(
+                repo.read(&location.owned_id()).await,
+                Err(LocationError::NotFound)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_foreign_location_forbidden() {
+        for db in TestDb::each().await {
+            let repo = db.locations();
+            let (user, home) = (db.alice.info(), home_of(&db, &db.alice).await);
+            let location = repo
+                .insert(&StorageLocation::new(garage_freezer(), user, home))
+                .await
+                .unwrap();
+
+            let foreign_id = OwnedLocationId::new(location.location_id.clone(), db.bob.user_id);
+            assert!(matches!(
+                repo.read(&foreign_id).await,
+                Err(LocationError::Forbidden)
+            ));
+            assert!(matches!(
+                repo.delete(&foreign_id).await,
+                Err(LocationError::Forbidden)
+            ));
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use async_trait::async_trait;
+use lettre::{
+    address::AddressError,
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use thiserror::Error;
+
+use crate::users::Mail;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutgoingMail {
+    pub to: Mail,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: &OutgoingMail) -> Result<(), MailError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmtpSecurity {
+    None,
+    StartTls,
+    Tls,
+}
+
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub credentials: Option<(String, String)>,
+    pub from: String,
+}
+
+impl SmtpConfig {
+    // Reads `SMTP_*` variables. Mail is disabled when `SMTP_HOST` is not set.
+    pub fn from_env() -> Result<Option<Self>, MailError> {
+        let Ok(host) = dotenvy::var("SMTP_HOST") else {
+            return Ok(None);
+        };
+
+        let security = match dotenvy::var("SMTP_SECURITY").as_deref() {
+            Ok("none") => SmtpSecurity::None,
+            Ok("starttls") | Err(_) => SmtpSecurity::StartTls,
+            Ok("tls") => SmtpSecurity::Tls,
+            Ok(other) => {
+                return Err(MailError::Config(format!(
+                    "unknown SMTP_SECURITY {}",
+                    other
+                )))
+            }
+        };
+        let port = match dotenvy::var("SMTP_PORT") {
+            Ok(port) => port
+                .parse()
+                .map_err(|_e| MailError::Config(format!("invalid SMTP_PORT {}", port)))?,
+            Err(_) => match security {
+                SmtpSecurity::None => 25,
+                SmtpSecurity::StartTls => 587,
+                SmtpSecurity::Tls => 465,
+            },
+        };
+        let credentials = match (dotenvy::var("SMTP_USERNAME"), dotenvy::var("SMTP_PASSWORD")) {
+            (Ok(username), Ok(password)) => Some((username, password)),
+            _ => None,
+        };
+        let from = dotenvy::var("SMTP_FROM")
+            .map_err(|_e| MailError::Config("SMTP_FROM is required".to_string()))?;
+
+        Ok(Some(Self {
+            host,
+            port,
+            security,
+            credentials,
+            from,
+        }))
+    }
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(config: SmtpConfig) -> Result<Self, MailError> {
+        let builder = match config.security {
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+            }
+            SmtpSecurity::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            }
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+        };
+        let mut builder = builder.port(config.port);
+        if let Some((username, password)) = config.credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            from: config.from.parse()?,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: &OutgoingMail) -> Result<(), MailError> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(String::from(mail.to.clone()).parse()?)
+            .subject(&mail.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(mail.body.clone())?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MailError {
+    #[error("Invalid mail configuration: {0}")]
+    Config(String),
+    #[error("Invalid mail address")]
+    Address(#[from] AddressError),
+    #[error("Failed to build mail")]
+    Message(#[from] lettre::error::Error),
+    #[error("Failed to deliver mail")]
+    Transport(#[from] lettre::transport::smtp::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+        sync::oneshot,
+    };
+
+    use super::{Mailer, OutgoingMail, SmtpConfig, SmtpMailer, SmtpSecurity};
+    use crate::users::Mail;
+
+    // Accepts a single SMTP session and hands back the DATA section it received.
+    async fn smtp_stand_in() -> (u16, oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut data = String::new();
+            let mut in_data = false;
+
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                if in_data {
+                    if line == "." {
+                        in_data = false;
+                        writer.write_all(b"250 queued\r\n").await.unwrap();
+                    } else {
+                        data.push_str(&line);
+                        data.push('\n');
+                    }
+                    continue;
+                }
+                let reply: &[u8] = match line.split(' ').next().unwrap_or_default() {
+                    "DATA" => {
+                        in_data = true;
+                        b"354 go ahead\r\n"
+                    }
+                    "QUIT" => {
+                        writer.write_all(b"221 bye\r\n").await.unwrap();
+                        break;
+                    }
+                    _ => b"250 OK\r\n",
+                };
+                writer.write_all(reply).await.unwrap();
+            }
+            let _ = tx.send(data);
+        });
+
+        (port, rx)
+    }
+
+    #[tokio::test]
+    async fn test_smtp_mailer_delivers() {
+        let (port, received) = smtp_stand_in().await;
+        let mailer = SmtpMailer::new(SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            security: SmtpSecurity::None,
+            credentials: None,
+            from: "fridge@example.com".to_string(),
+        })
+        .unwrap();
+
+        mailer
+            .send(&OutgoingMail {
+                to: Mail::try_from("user@example.com").unwrap(),
+                subject: "Foods expiring soon".to_string(),
+                body: "milk".to_string(),
+            })
+            .await
+            .unwrap();
+
+        let data = received.await.unwrap();
+        assert!(data.contains("To: user@example.com"));
+        assert!(data.contains("Subject: Foods expiring soon"));
+        assert!(data.contains("milk"));
+    }
+}
@@ -0,0 +1,156 @@
+use std::{fs, sync::Arc, time::Duration};
+
+use fridge_manage_server::{
+    auth::token::TokenSigner,
+    mail::{Mailer, SmtpConfig, SmtpMailer},
+    migrate,
+    notify::Notifier,
+    server::{serve, App},
+    users::{import::import_users, policy::PasswordPolicy, verify::MailVerifier},
+    util::HashConfig,
+    DbPool,
+};
+use tokio::net::TcpListener;
+
+static DEFAULT_SERVER_ADDR: &str = "0.0.0.0:8080";
+const DEFAULT_NOTIFY_INTERVAL_SECS: u64 = 60;
+
+static USAGE: &str = "usage: fridge-manage-server [--no-migrate]
+       fridge-manage-server --demo
+       fridge-manage-server migrate <status|up|down>
+       fridge-manage-server import-users <users.jsonl>";
+
+enum Command {
+    Serve { auto_migrate: bool },
+    Demo,
+    MigrateStatus,
+    MigrateUp,
+    MigrateDown,
+    ImportUsers { path: String },
+}
+
+impl Command {
+    fn parse(args: &[&str]) -> Option<Self> {
+        match args {
+            [] => Some(Command::Serve { auto_migrate: true }),
+            ["--no-migrate"] => Some(Command::Serve {
+                auto_migrate: false,
+            }),
+            ["--demo"] => Some(Command::Demo),
+            ["migrate", "status"] => Some(Command::MigrateStatus),
+            ["migrate", "up"] => Some(Command::MigrateUp),
+            ["migrate", "down"] => Some(Command::MigrateDown),
+            ["import-users", path] => Some(Command::ImportUsers {
+                path: path.to_string(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let command = Command::parse(&args).ok_or(USAGE)?;
+
+    match command {
+        // The demo needs neither a database nor mail, everything lives in memory until exit.
+        Command::Demo => {
+            println!("demo mode, data is kept in memory and lost on exit");
+            listen(App::in_memory(None)).await?
+        }
+        Command::Serve { auto_migrate } => run_server(connect().await?, auto_migrate).await?,
+        Command::MigrateStatus => {
+            for migration in migrate::status(&connect().await?).await? {
+                println!("{}", migration);
+            }
+        }
+        Command::MigrateUp => {
+            let pool = connect().await?;
+            migrate::up(&pool).await?;
+            println!("database is at version {}", migrate::latest_version(&pool));
+        }
+        Command::MigrateDown => match migrate::down(&connect().await?).await? {
+            Some(version) => println!("reverted migration {}", version),
+            None => println!("no migration to revert"),
+        },
+        // One JSON object per line: user_name, mail, password_hash and optionally mail_verified.
+        Command::ImportUsers { path } => {
+            let input = fs::read_to_string(&path)?;
+            let pool = connect().await?;
+            migrate::check_schema(&pool).await?;
+            let report = import_users(pool, &input).await?;
+            for (line, mail) in report.skipped {
+                println!(
+                    "line {}: {} is registered already, skipped",
+                    line,
+                    String::from(mail)
+                );
+            }
+            println!("imported {} users", report.imported);
+        }
+    }
+    Ok(())
+}
+
+async fn connect() -> Result<DbPool, Box<dyn std::error::Error>> {
+    let db_url = dotenvy::var("DATABASE_URL")?;
+    Ok(DbPool::connect(&db_url).await?)
+}
+
+async fn run_server(pool: DbPool, auto_migrate: bool) -> Result<(), Box<dyn std::error::Error>> {
+    // `AUTO_MIGRATE=false` opts out just like `--no-migrate`, handy in container setups.
+    let auto_migrate =
+        auto_migrate && dotenvy::var("AUTO_MIGRATE").map_or(true, |value| value != "false");
+    if auto_migrate {
+        migrate::up(&pool).await?;
+    } else {
+        migrate::check_schema(&pool).await?;
+    }
+
+    let mailer: Option<Arc<dyn Mailer>> = match SmtpConfig::from_env()? {
+        Some(config) => {
+            let interval = match dotenvy::var("NOTIFY_INTERVAL_SECS") {
+                Ok(secs) => secs.parse()?,
+                Err(_) => DEFAULT_NOTIFY_INTERVAL_SECS,
+            };
+            let mailer = Arc::new(SmtpMailer::new(config)?);
+            Notifier::new(pool.clone(), mailer.clone()).spawn(Duration::from_secs(interval));
+            Some(mailer)
+        }
+        None => {
+            println!("SMTP_HOST is not set, expiry digests and household invites are disabled");
+            None
+        }
+    };
+
+    let verifier = MailVerifier::from_env()?;
+    if mailer.is_some() && verifier.is_ephemeral() {
+        println!("MAIL_VERIFICATION_KEY is not set, verification links stop working on restart");
+    }
+
+    let tokens = TokenSigner::from_env()?;
+    if tokens.is_ephemeral() {
+        println!("JWT_SECRET is not set, access tokens stop working on restart");
+    }
+
+    listen(App::new(
+        pool,
+        mailer,
+        PasswordPolicy::from_env()?,
+        verifier,
+        HashConfig::from_env()?,
+        tokens,
+    ))
+    .await
+}
+
+async fn listen(app: App) -> Result<(), Box<dyn std::error::Error>> {
+    let addr = dotenvy::var("SERVER_ADDR").unwrap_or_else(|_| DEFAULT_SERVER_ADDR.to_string());
+    let listener = TcpListener::bind(&addr).await?;
+    println!("listening on {}", addr);
+
+    serve(listener, app).await?;
+    Ok(())
+}
@@ -0,0 +1,178 @@
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use sqlx::error::{DatabaseError, ErrorKind};
+
+use crate::{
+    api_tokens::ApiToken,
+    auth::{PasswordReset, RefreshGrant, Session},
+    foods::Food,
+    households::{memory::StoredMember, Household, HouseholdId, Invite, Role},
+    locations::StorageLocation,
+    notify::memory::StoredPreference,
+    two_factor::{memory::StoredRecoveryCode, LoginChallenge, TotpEnrolment},
+    users::{User, UserId},
+};
+
+// Rows of every table, kept in insertion order like the auto-increment `id` of the SQL schema.
+#[derive(Debug, Default)]
+pub(crate) struct Tables {
+    pub(crate) users: Vec<User>,
+    pub(crate) sessions: Vec<Session>,
+    pub(crate) password_resets: Vec<PasswordReset>,
+    pub(crate) refresh_grants: Vec<RefreshGrant>,
+    pub(crate) api_tokens: Vec<ApiToken>,
+    pub(crate) enrolments: Vec<TotpEnrolment>,
+    pub(crate) recovery_codes: Vec<StoredRecoveryCode>,
+    pub(crate) login_challenges: Vec<LoginChallenge>,
+    pub(crate) preferences: Vec<StoredPreference>,
+    pub(crate) households: Vec<Household>,
+    pub(crate) members: Vec<StoredMember>,
+    pub(crate) invites: Vec<Invite>,
+    pub(crate) locations: Vec<StorageLocation>,
+    pub(crate) foods: Vec<Food>,
+}
+
+impl Tables {
+    pub(crate) fn user(&self, user_id: &UserId) -> Option<&User> {
+        self.users.iter().find(|user| user.user_id() == user_id)
+    }
+
+    pub(crate) fn household(&self, household_id: &HouseholdId) -> Option<&Household> {
+        self.households
+            .iter()
+            .find(|household| household.household_id() == household_id)
+    }
+
+    pub(crate) fn role(&self, household_id: &HouseholdId, user_id: &UserId) -> Option<Role> {
+        self.members
+            .iter()
+            .find(|member| &member.household_id == household_id && &member.user_id == user_id)
+            .map(|member| member.role)
+    }
+
+    // Drops the household with its members, invites, locations and the foods in them, as the
+    // cascades and the `DELETE` of the foods do in SQL.
+    pub(crate) fn remove_household(&mut self, household_id: &HouseholdId) {
+        let locations: Vec<_> = self
+            .locations
+            .iter()
+            .filter(|location| location.household_id() == household_id)
+            .map(|location| location.location_id().clone())
+            .collect();
+        self.foods
+            .retain(|food| !locations.contains(food.location_id()));
+        self.locations
+            .retain(|location| location.household_id() != household_id);
+        self.invites
+            .retain(|invite| invite.household_id() != household_id);
+        self.members
+            .retain(|member| &member.household_id != household_id);
+        self.households
+            .retain(|household| household.household_id() != household_id);
+    }
+}
+
+// Backs the in-memory repositories. Clones share the same tables, just like clones of a pool
+// share one database.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MemoryStore {
+    tables: Arc<Mutex<Tables>>,
+}
+
+impl MemoryStore {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    // Nothing awaits while holding the lock, so a std mutex does. A panicking test must not
+    // take the tables down with it.
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// A constraint violation reported like the database would, so the `From<sqlx::Error>` impls of
+// the repository errors classify it the same way.
+#[derive(Debug)]
+pub(crate) struct ConstraintError {
+    unique: bool,
+    message: String,
+}
+
+impl ConstraintError {
+    pub(crate) fn unique(constraint: &str) -> sqlx::Error {
+        Self::error(true, constraint)
+    }
+
+    pub(crate) fn foreign_key(constraint: &str) -> sqlx::Error {
+        Self::error(false, constraint)
+    }
+
+    fn error(unique: bool, constraint: &str) -> sqlx::Error {
+        sqlx::Error::Database(Box::new(Self {
+            unique,
+            message: format!("constraint {} failed", constraint),
+        }))
+    }
+}
+
+impl fmt::Display for ConstraintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for ConstraintError {}
+
+impl DatabaseError for ConstraintError {
+    fn message(&self) -> &str {
+        &self.message
+    }
+
+    fn code(&self) -> Option<Cow<'_, str>> {
+        None
+    }
+
+    fn as_error(&self) -> &(dyn Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn Error + Send + Sync + 'static> {
+        self
+    }
+
+    fn kind(&self) -> ErrorKind {
+        if self.unique {
+            ErrorKind::UniqueViolation
+        } else {
+            ErrorKind::ForeignKeyViolation
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ConstraintError;
+    use crate::db::{classify, DbErrorKind};
+
+    #[test]
+    fn test_constraint_errors_classify_like_database_ones() {
+        assert_eq!(
+            classify(&ConstraintError::unique("user_id_idx")),
+            DbErrorKind::UniqueViolation
+        );
+        assert_eq!(
+            classify(&ConstraintError::foreign_key("food_location_id")),
+            DbErrorKind::ForeignKeyViolation
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use std::fmt;
+
+use sqlx::{
+    migrate::{Migrate, MigrateError, Migrator},
+    query, query_scalar,
+};
+use thiserror::Error;
+
+use crate::db::{with_pool, DbPool};
+
+// Every file under `migrations/<backend>/`, baked into the binary at compile time.
+pub static MYSQL_MIGRATOR: Migrator = sqlx::migrate!("migrations/mysql");
+#[cfg(feature = "sqlite")]
+pub static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+#[cfg(feature = "postgres")]
+pub static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("migrations/postgres");
+
+// The migrations written for the backend behind `pool`.
+pub fn migrator(pool: &DbPool) -> &'static Migrator {
+    match pool {
+        DbPool::MySql(_) => &MYSQL_MIGRATOR,
+        #[cfg(feature = "sqlite")]
+        DbPool::Sqlite(_) => &SQLITE_MIGRATOR,
+        #[cfg(feature = "postgres")]
+        DbPool::Postgres(_) => &POSTGRES_MIGRATOR,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+impl fmt::Display for MigrationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.applied { "applied" } else { "pending" };
+        write!(f, "{} {:<8} {}", self.version, state, self.description)
+    }
+}
+
+// The newest migration this binary knows about for the backend behind `pool`.
+pub fn latest_version(pool: &DbPool) -> i64 {
+    migrator(pool)
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration())
+        .map(|m| m.version)
+        .max()
+        .unwrap_or_default()
+}
+
+// Where sqlx records the migrations it ran.
+static MIGRATIONS_TABLE: &str = "_sqlx_migrations";
+// Every schema has it from the first migration on, so it tells a database that was set up by hand
+// from `20241111153301_schema.sql`, before there were migrations, from an empty one.
+static BASELINE_TABLE: &str = "user_table";
+
+async fn table_exists(pool: &DbPool, table: &str) -> Result<bool, SchemaError> {
+    let sql = match pool {
+        DbPool::MySql(_) => {
+            r#"
+                SELECT COUNT(*)
+                FROM information_schema.tables
+                WHERE table_schema = DATABASE() AND table_name = ?
+            "#
+        }
+        #[cfg(feature = "sqlite")]
+        DbPool::Sqlite(_) => {
+            r#"
+                SELECT COUNT(*)
+                FROM sqlite_master
+                WHERE type = 'table' AND name = ?
+            "#
+        }
+        #[cfg(feature = "postgres")]
+        DbPool::Postgres(_) => {
+            r#"
+                SELECT COUNT(*)
+                FROM information_schema.tables
+                WHERE table_schema = current_schema() AND table_name = ?
+            "#
+        }
+    };
+    let count: i64 = with_pool!(pool, |pool| {
+        query_scalar(sql).bind(table).fetch_one(pool).await?
+    });
+    Ok(count > 0)
+}
+
+// The versions recorded as applied. A database without the migrations table has none, and stays
+// without it, so checking the schema writes nothing.
+async fn applied_versions(pool: &DbPool) -> Result<Vec<i64>, SchemaError> {
+    if !table_exists(pool, MIGRATIONS_TABLE).await? {
+        return Ok(Vec::new());
+    }
+    let applied = with_pool!(pool, |pool| {
+        let mut conn = pool.acquire().await?;
+        conn.list_applied_migrations().await?
+    });
+    Ok(applied.into_iter().map(|m| m.version).collect())
+}
+
+// Records the first migration as applied without running it, for a database whose schema was
+// created by hand from it. Running it would fail on the tables that are there already. Returns
+// whether it did.
+//
+// Only MySQL had a schema before there were migrations. The first migration of the other backends
+// is their whole schema, so a stray `user_table` there must not pass for all of it.
+async fn adopt_baseline(pool: &DbPool) -> Result<bool, SchemaError> {
+    #[allow(irrefutable_let_patterns)]
+    let DbPool::MySql(mysql) = pool
+    else {
+        return Ok(false);
+    };
+    let Some(baseline) = MYSQL_MIGRATOR
+        .iter()
+        .find(|m| m.migration_type.is_up_migration())
+    else {
+        return Ok(false);
+    };
+    if applied_versions(pool).await?.contains(&baseline.version)
+        || !table_exists(pool, BASELINE_TABLE).await?
+    {
+        return Ok(false);
+    }
+
+    let mut conn = mysql.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    // The same row sqlx writes for a migration it ran, with the checksum of the file so later
+    // runs do not take it for a changed migration.
+    query(
+        r#"
+            INSERT INTO _sqlx_migrations
+            (version, description, success, checksum, execution_time)
+            VALUES (?, ?, TRUE, ?, -1)
+        "#,
+    )
+    .bind(baseline.version)
+    .bind(baseline.description.as_ref())
+    .bind(baseline.checksum.as_ref())
+    .execute(&mut *conn)
+    .await?;
+    Ok(true)
+}
+
+pub async fn status(pool: &DbPool) -> Result<Vec<MigrationStatus>, SchemaError> {
+    let applied = applied_versions(pool).await?;
+    Ok(migrator(pool)
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration())
+        .map(|m| MigrationStatus {
+            version: m.version,
+            description: m.description.to_string(),
+            applied: applied.contains(&m.version),
+        })
+        .collect())
+}
+
+// Refuses to touch a database that a newer binary has already migrated.
+pub async fn check_schema(pool: &DbPool) -> Result<(), SchemaError> {
+    let binary = latest_version(pool);
+    let database = applied_versions(pool)
+        .await?
+        .into_iter()
+        .max()
+        .unwrap_or_default();
+
+    if database > binary {
+        return Err(SchemaError::TooNew { database, binary });
+    }
+    Ok(())
+}
+
+// Applies every pending migration. A schema that was set up by hand before there were migrations
+// is adopted first, see `adopt_baseline`.
+pub async fn up(pool: &DbPool) -> Result<(), SchemaError> {
+    check_schema(pool).await?;
+    if adopt_baseline(pool).await? {
+        println!("found a schema set up by hand, recorded the baseline migration as applied");
+    }
+    let migrator = migrator(pool);
+    with_pool!(pool, |pool| migrator.run(pool).await?);
+    Ok(())
+}
+
+// Reverts the latest applied migration and returns its version, if there was any.
+pub async fn down(pool: &DbPool) -> Result<Option<i64>, SchemaError> {
+    check_schema(pool).await?;
+    let mut applied = applied_versions(pool).await?;
+    applied.sort_unstable();
+
+    let Some(latest) = applied.pop() else {
+        return Ok(None);
+    };
+    let target = applied.last().copied().unwrap_or_default();
+    let migrator = migrator(pool);
+    with_pool!(pool, |pool| migrator.undo(pool, target).await?);
+    Ok(Some(latest))
+}
+
+#[derive(Debug, Error)]
+pub enum SchemaError {
+    #[error("database schema version {database} is newer than this binary supports ({binary})")]
+    TooNew { database: i64, binary: i64 },
+    #[error("Failed to migrate the database")]
+    Migrate(#[from] MigrateError),
+    #[error("Database error")]
+    Database(#[from] sqlx::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use sqlx::migrate::Migrator;
+
+    use super::{check_schema, status, table_exists, up, MIGRATIONS_TABLE, MYSQL_MIGRATOR};
+    use crate::{
+        db::{with_pool, DbPool},
+        testing::EmptyDb,
+    };
+
+    fn assert_reversible(migrator: &Migrator) {
+        let ups: Vec<_> = migrator
+            .iter()
+            .filter(|m| m.migration_type.is_up_migration())
+            .map(|m| m.version)
+            .collect();
+        let downs: Vec<_> = migrator
+            .iter()
+            .filter(|m| m.migration_type.is_down_migration())
+            .map(|m| m.version)
+            .collect();
+
+        assert!(!ups.is_empty());
+        assert_eq!(ups, downs);
+    }
+
+    #[test]
+    fn test_every_migration_reversible() {
+        assert_reversible(&MYSQL_MIGRATOR);
+        #[cfg(feature = "sqlite")]
+        assert_reversible(&super::SQLITE_MIGRATOR);
+        #[cfg(feature = "postgres")]
+        assert_reversible(&super::POSTGRES_MIGRATOR);
+    }
+
+    #[tokio::test]
+    async fn test_check_schema_writes_nothing() {
+        let Some(db) = EmptyDb::new().await else {
+            return;
+        };
+        check_schema(&db.pool).await.unwrap();
+        assert!(!table_exists(&db.pool, MIGRATIONS_TABLE).await.unwrap());
+    }
+
+    // A database set up from the schema file by hand, before there were migrations.
+    #[tokio::test]
+    async fn test_up_adopts_hand_applied_baseline() {
+        let Some(db) = EmptyDb::new().await else {
+            return;
+        };
+        #[allow(irrefutable_let_patterns)]
+        let DbPool::MySql(pool) = &db.pool
+        else {
+            return;
+        };
+        let baseline = MYSQL_MIGRATOR
+            .iter()
+            .find(|m| m.migration_type.is_up_migration())
+            .unwrap();
+        sqlx::raw_sql(&baseline.sql).execute(pool).await.unwrap();
+
+        up(&db.pool).await.unwrap();
+        assert!(status(&db.pool).await.unwrap().iter().all(|m| m.applied));
+        // And the next run finds nothing to do.
+        up(&db.pool).await.unwrap();
+    }
+
+    // The other backends never had a hand-made schema, their first migration is not adopted just
+    // because a `user_table` is there.
+    #[tokio::test]
+    async fn test_up_adopts_no_baseline_outside_mysql() {
+        let Some(db) = EmptyDb::new().await else {
+            return;
+        };
+        if matches!(db.pool, DbPool::MySql(_)) {
+            return;
+        }
+        with_pool!(&db.pool, |pool| {
+            sqlx::raw_sql("CREATE TABLE user_table (user_id VARCHAR(40) NOT NULL)")
+                .execute(pool)
+                .await
+                .unwrap();
+        });
+
+        assert!(up(&db.pool).await.is_err());
+        assert!(status(&db.pool).await.unwrap().iter().all(|m| !m.applied));
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use chrono::{Local, NaiveDateTime, NaiveTime, TimeDelta};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use thiserror::Error;
+use tokio::task::JoinHandle;
+
+use crate::{
+    db::{classify, impl_from_row, DbErrorKind, DbPool, UnsignedColumn},
+    foods::{
+        repo::{FoodsRepository, FoodsStore},
+        ExpiringFoods, FoodsError,
+    },
+    mail::{MailError, Mailer, OutgoingMail},
+    users::{Mail, UserId, UserName},
+};
+
+pub(crate) mod handler;
+pub(crate) mod memory;
+pub(crate) mod repo;
+
+use repo::{PreferenceRepository, PreferenceStore};
+
+static DIGEST_SUBJECT: &str = "Foods expiring soon";
+// A digest whose mail failed is tried again after this long instead of on every tick.
+const RETRY_DELAY_MINUTES: i64 = 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NotificationPreference {
+    pub enabled: bool,
+    pub send_time: NaiveTime,
+    pub lead_days: u32,
+}
+
+impl Default for NotificationPreference {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            send_time: NaiveTime::from_hms_opt(8, 0, 0).unwrap_or_default(),
+            lead_days: 3,
+        }
+    }
+}
+
+impl_from_row!(NotificationPreference, |row| {
+    Ok(NotificationPreference {
+        enabled: row.try_get("enabled")?,
+        send_time: row.try_get("send_time")?,
+        lead_days: row.try_get_u32("lead_days")?,
+    })
+});
+
+// A user whose digest is due, with what we need to build and address it.
+#[derive(Debug, Clone)]
+pub(crate) struct DigestRecipient {
+    user_id: UserId,
+    user_name: UserName,
+    mail: Mail,
+    lead_days: u32,
+}
+
+impl_from_row!(DigestRecipient, |row| {
+    Ok(DigestRecipient {
+        user_id: row.try_get("user_id")?,
+        user_name: row.try_get("user_name")?,
+        mail: row.try_get("mail")?,
+        lead_days: row.try_get_u32("lead_days")?,
+    })
+});
+
+fn digest_body(user_name: &UserName, lead_days: u32, foods: &ExpiringFoods) -> String {
+    let mut body = format!("Hi {},\n", String::from(user_name.clone()));
+    let sections = [
+        ("Already expired:".to_string(), foods.expired()),
+        (
+            format!("Expiring within {} days:", lead_days),
+            foods.expiring(),
+        ),
+    ];
+
+    for (title, foods) in sections.iter().filter(|(_, foods)| !foods.is_empty()) {
+        let _ = write!(body, "\n{}\n", title);
+        for food in foods.iter() {
+            let _ = writeln!(body, "- {} ({})", food.food_name().as_str(), food.exp());
+        }
+    }
+    body
+}
+
+// Periodically mails each opted-in user with a verified mail a digest of their foods nearing `exp`.
+pub struct Notifier {
+    preferences: Box<dyn PreferenceStore>,
+    foods: Box<dyn FoodsStore>,
+    mailer: Arc<dyn Mailer>,
+    // When the digests whose mail failed may be tried again.
+    retry_at: Mutex<HashMap<UserId, NaiveDateTime>>,
+}
+
+impl Notifier {
+    pub fn new(pool: DbPool, mailer: Arc<dyn Mailer>) -> Self {
+        Self::with_stores(
+            Box::new(PreferenceRepository::new(pool.clone())),
+            Box::new(FoodsRepository::new(pool)),
+            mailer,
+        )
+    }
+
+    fn with_stores(
+        preferences: Box<dyn PreferenceStore>,
+        foods: Box<dyn FoodsStore>,
+        mailer: Arc<dyn Mailer>,
+    ) -> Self {
+        Self {
+            preferences,
+            foods,
+            mailer,
+            retry_at: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn retry_at(&self) -> MutexGuard<'_, HashMap<UserId, NaiveDateTime>> {
+        self.retry_at.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn spawn(self, every: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.run_once(Local::now().naive_local()).await {
+                    eprintln!("failed to send expiry digests: {}", e);
+                }
+            }
+        })
+    }
+
+    // Sends every digest due at `now` and returns how many were sent.
+    pub(crate) async fn run_once(&self, now: NaiveDateTime) -> Result<usize, NotifyError> {
+        let today = now.date();
+        let mut sent = 0;
+
+        for recipient in self.preferences.due(today, now.time()).await? {
+            let waiting = self
+                .retry_at()
+                .get(&recipient.user_id)
+                .is_some_and(|retry_at| *retry_at > now);
+            if waiting {
+                continue;
+            }
+            let foods = self
+                .foods
+                .read_expiring(&recipient.user_id, today, recipient.lead_days)
+                .await?;
+
+            if !foods.is_empty() {
+                let mail = OutgoingMail {
+                    to: recipient.mail.clone(),
+                    subject: DIGEST_SUBJECT.to_string(),
+                    body: digest_body(&recipient.user_name, recipient.lead_days, &foods),
+                };
+                // Leave the digest due, to be retried once the delay is over.
+                if let Err(e) = self.mailer.send(&mail).await {
+                    eprintln!("failed to send expiry digest: {}", e);
+                    let retry_at = now + TimeDelta::minutes(RETRY_DELAY_MINUTES);
+                    self.retry_at().insert(recipient.user_id.clone(), retry_at);
+                    continue;
+                }
+                sent += 1;
+            }
+            self.retry_at().remove(&recipient.user_id);
+            self.preferences
+                .mark_sent(&recipient.user_id, today)
+                .await?;
+        }
+        Ok(sent)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum NotifyError {
+    #[error("Database unavailable")]
+    Unavailable(#[source] sqlx::Error),
+    #[error("Database error")]
+    Database(#[source] sqlx::Error),
+    #[error(transparent)]
+    Foods(#[from] FoodsError),
+    #[error(transparent)]
+    Mail(#[from] MailError),
+}
+
+impl From<sqlx::Error> for NotifyError {
+    fn from(value: sqlx::Error) -> Self {
+        match classify(&value) {
+            DbErrorKind::Unavailable => NotifyError::Unavailable(value),
+            _ => NotifyError::Database(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use chrono::{NaiveDate, NaiveTime};
+    use serde_json::json;
+
+    use super::{digest_body, Notifier};
+    use crate::{
+        foods::{ExpiringFoods, Food},
+        locations::LocationId,
+        notify::NotificationPreference,
+        testing::{RecordingMailer, TestDb},
+        users::{PubUserInfo, UserId, UserName},
+    };
+
+    fn food(name: &str, day: u32) -> Food {
+        let payload = serde_json::from_value(json!({
+            "food_name": name,
+            "exp": format!("2025-04-{:02}", day),
+        }))
+        .unwrap();
+        let user = PubUserInfo {
+            user_id: UserId::from("test_user_id"),
+            user_name: UserName::try_from("alice").unwrap(),
+        };
+        Food::new(payload, user, LocationId::from("test_location_id"))
+    }
+
+    #[test]
+    fn test_digest_body() {
+        let today = NaiveDate::from_ymd_opt(2025, 4, 10).unwrap();
+        let foods = ExpiringFoods::split(vec![food("milk", 9), food("eggs", 12)], today);
+
+        let body = digest_body(&UserName::try_from("alice").unwrap(), 3, &foods);
+        assert_eq!(
+            body,
+            "Hi alice,\n\
+             \nAlready expired:\n- milk (2025-04-09)\n\
+             \nExpiring within 3 days:\n- eggs (2025-04-12)\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_once_mails_due_digests() {
+        let db = TestDb::memory().await;
+        let user = db.alice.info();
+        let home = db.households().default_for(&user.user_id).await.unwrap();
+        let fridge = db
+            .locations()
+            .default_for(home.household().household_id(), &user.user_id)
+            .await
+            .unwrap();
+        let milk = serde_json::from_value(json!({"food_name": "milk", "exp": "2025-04-12"}));
+        let milk = Food::new(milk.unwrap(), user.clone(), fridge.location_id().clone());
+        db.foods().insert(&milk).await.unwrap();
+
+        let preference = NotificationPreference {
+            enabled: true,
+            send_time: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+            lead_days: 3,
+        };
+        db.preferences()
+            .upsert(&user.user_id, &preference)
+            .await
+            .unwrap();
+
+        let mailer = Arc::new(RecordingMailer::default());
+        let notifier = Notifier::with_stores(db.preferences(), db.foods(), mailer.clone());
+
+        let today = NaiveDate::from_ymd_opt(2025, 4, 10).unwrap();
+        let early = today.and_hms_opt(7, 0, 0).unwrap();
+        assert_eq!(notifier.run_once(early).await.unwrap(), 0);
+
+        // A failed digest waits for the retry delay instead of going again on the next tick.
+        mailer.set_failing(true);
+        let now = today.and_hms_opt(9, 0, 0).unwrap();
+        assert_eq!(notifier.run_once(now).await.unwrap(), 0);
+        mailer.set_failing(false);
+        let soon = today.and_hms_opt(9, 30, 0).unwrap();
+        assert_eq!(notifier.run_once(soon).await.unwrap(), 0);
+
+        let later = today.and_hms_opt(10, 0, 0).unwrap();
+        assert_eq!(notifier.run_once(later).await.unwrap(), 1);
+        assert_eq!(notifier.run_once(later).await.unwrap(), 0);
+
+        let sent = mailer.sent();
+        assert_eq!(sent[0].to, db.alice.mail);
+        assert!(sent[0].body.contains("- milk (2025-04-12)"));
+    }
+}
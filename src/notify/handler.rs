@@ -0,0 +1,46 @@
+use hyper::{body::Bytes, StatusCode};
+
+use crate::{
+    server::{json_response, parse_json, ApiError, AppState, HttpResponse},
+    users::PubUserInfo,
+};
+
+use super::{NotificationPreference, NotifyError};
+
+const MAX_LEAD_DAYS: u32 = 30;
+
+impl From<NotifyError> for ApiError {
+    fn from(value: NotifyError) -> Self {
+        match value {
+            NotifyError::Foods(e) => ApiError::from(e),
+            NotifyError::Unavailable(_) => {
+                ApiError::logged(StatusCode::SERVICE_UNAVAILABLE, &value)
+            }
+            NotifyError::Database(_) | NotifyError::Mail(_) => {
+                ApiError::logged(StatusCode::INTERNAL_SERVER_ERROR, &value)
+            }
+        }
+    }
+}
+
+pub(crate) async fn read(state: &AppState, user: PubUserInfo) -> Result<HttpResponse, ApiError> {
+    let preference = state.preferences.read(&user.user_id).await?;
+    Ok(json_response(StatusCode::OK, &preference))
+}
+
+pub(crate) async fn update(
+    state: &AppState,
+    user: PubUserInfo,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let payload: NotificationPreference = parse_json(&body)?;
+    if payload.lead_days > MAX_LEAD_DAYS {
+        return Err(ApiError::invalid_field(
+            "lead_days",
+            format!("must be at most {}", MAX_LEAD_DAYS),
+        ));
+    }
+
+    let preference = state.preferences.upsert(&user.user_id, &payload).await?;
+    Ok(json_response(StatusCode::OK, &preference))
+}
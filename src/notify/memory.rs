@@ -0,0 +1,113 @@
+use async_trait::async_trait;
+use chrono::{NaiveDate, NaiveTime};
+
+use crate::{
+    memory::{ConstraintError, MemoryStore},
+    users::UserId,
+    RepositoryTargetReader,
+};
+
+use super::{repo::PreferenceStore, DigestRecipient, NotificationPreference, NotifyError};
+
+// A row of `notification_preference_table`.
+#[derive(Debug, Clone)]
+pub(crate) struct StoredPreference {
+    pub(crate) user_id: UserId,
+    pub(crate) preference: NotificationPreference,
+    pub(crate) last_sent_on: Option<NaiveDate>,
+}
+
+pub struct MemoryPreferenceRepository {
+    store: MemoryStore,
+}
+
+impl MemoryPreferenceRepository {
+    pub(crate) fn new(store: MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl PreferenceStore for MemoryPreferenceRepository {
+    async fn upsert(
+        &self,
+        user_id: &UserId,
+        preference: &NotificationPreference,
+    ) -> Result<NotificationPreference, NotifyError> {
+        let mut tables = self.store.lock();
+        if tables.user(user_id).is_none() {
+            return Err(ConstraintError::foreign_key("preference_usr_id").into());
+        }
+
+        match tables
+            .preferences
+            .iter_mut()
+            .find(|stored| &stored.user_id == user_id)
+        {
+            Some(stored) => stored.preference = preference.clone(),
+            None => tables.preferences.push(StoredPreference {
+                user_id: user_id.clone(),
+                preference: preference.clone(),
+                last_sent_on: None,
+            }),
+        }
+        Ok(preference.clone())
+    }
+
+    async fn due(
+        &self,
+        today: NaiveDate,
+        now: NaiveTime,
+    ) -> Result<Vec<DigestRecipient>, NotifyError> {
+        let tables = self.store.lock();
+        let recipients = tables
+            .preferences
+            .iter()
+            .filter(|stored| {
+                stored.preference.enabled
+                    && stored.preference.send_time <= now
+                    && stored.last_sent_on.is_none_or(|sent_on| sent_on < today)
+            })
+            .filter_map(|stored| {
+                let user = tables
+                    .user(&stored.user_id)
+                    .filter(|user| user.mail_verified())?;
+                Some(DigestRecipient {
+                    user_id: stored.user_id.clone(),
+                    user_name: user.user_name().clone(),
+                    mail: user.mail().clone(),
+                    lead_days: stored.preference.lead_days,
+                })
+            })
+            .collect();
+        Ok(recipients)
+    }
+
+    async fn mark_sent(&self, user_id: &UserId, today: NaiveDate) -> Result<(), NotifyError> {
+        let mut tables = self.store.lock();
+        if let Some(stored) = tables
+            .preferences
+            .iter_mut()
+            .find(|stored| &stored.user_id == user_id)
+        {
+            stored.last_sent_on = Some(today);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'a> RepositoryTargetReader<'a, UserId> for MemoryPreferenceRepository {
+    type QueryRes = NotificationPreference;
+    type QueryErr = NotifyError;
+
+    async fn read(&self, id: &'a UserId) -> Result<Self::QueryRes, Self::QueryErr> {
+        let tables = self.store.lock();
+        let preference = tables
+            .preferences
+            .iter()
+            .find(|stored| &stored.user_id == id)
+            .map(|stored| stored.preference.clone());
+        Ok(preference.unwrap_or_default())
+    }
+}
@@ -0,0 +1,233 @@
+use async_trait::async_trait;
+use chrono::{NaiveDate, NaiveTime};
+use sqlx::{query, query_as};
+
+use crate::{
+    db::{with_pool, DbPool},
+    users::UserId,
+    RepositoryTargetReader,
+};
+
+use super::{DigestRecipient, NotificationPreference, NotifyError};
+
+// What the handlers and the notifier need from a preference backend, either
+// `PreferenceRepository` or `MemoryPreferenceRepository`.
+#[async_trait]
+pub(crate) trait PreferenceStore:
+    for<'a> RepositoryTargetReader<
+        'a,
+        UserId,
+        QueryRes = NotificationPreference,
+        QueryErr = NotifyError,
+    > + Send
+    + Sync
+{
+    async fn upsert(
+        &self,
+        user_id: &UserId,
+        preference: &NotificationPreference,
+    ) -> Result<NotificationPreference, NotifyError>;
+
+    // Opted-in users whose send time has passed today and who have not been mailed yet today.
+    // Mails that are not verified yet get nothing.
+    async fn due(
+        &self,
+        today: NaiveDate,
+        now: NaiveTime,
+    ) -> Result<Vec<DigestRecipient>, NotifyError>;
+
+    async fn mark_sent(&self, user_id: &UserId, today: NaiveDate) -> Result<(), NotifyError>;
+}
+
+pub struct PreferenceRepository {
+    pool: DbPool,
+}
+
+impl PreferenceRepository {
+    pub(crate) fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl PreferenceStore for PreferenceRepository {
+    async fn upsert(
+        &self,
+        user_id: &UserId,
+        preference: &NotificationPreference,
+    ) -> Result<NotificationPreference, NotifyError> {
+        // MySQL has an upsert of its own, SQLite and Postgres share the standard one.
+        let upsert = match self.pool {
+            DbPool::MySql(_) => {
+                r#"
+                    INSERT INTO notification_preference_table
+                    (user_id, enabled, send_time, lead_days)
+                    VALUES (?, ?, ?, ?)
+                    ON DUPLICATE KEY UPDATE
+                    enabled = VALUES(enabled),
+                    send_time = VALUES(send_time),
+                    lead_days = VALUES(lead_days)
+                "#
+            }
+            #[allow(unreachable_patterns)]
+            _ => {
+                r#"
+                    INSERT INTO notification_preference_table
+                    (user_id, enabled, send_time, lead_days)
+                    VALUES (?, ?, ?, ?)
+                    ON CONFLICT (user_id) DO UPDATE SET
+                    enabled = excluded.enabled,
+                    send_time = excluded.send_time,
+                    lead_days = excluded.lead_days
+                "#
+            }
+        };
+        with_pool!(&self.pool, |pool| {
+            query(upsert)
+                .bind(user_id)
+                .bind(preference.enabled)
+                .bind(preference.send_time)
+                .bind(i64::from(preference.lead_days))
+                .execute(pool)
+                .await?;
+        });
+        Ok(preference.clone())
+    }
+
+    async fn due(
+        &self,
+        today: NaiveDate,
+        now: NaiveTime,
+    ) -> Result<Vec<DigestRecipient>, NotifyError> {
+        let recipients = with_pool!(&self.pool, |pool| {
+            query_as::<_, DigestRecipient>(
+                r#"
+                    SELECT p.user_id, u.user_name, u.mail, p.lead_days
+                    FROM notification_preference_table p
+                    INNER JOIN user_table u ON u.user_id = p.user_id
+                    WHERE p.enabled = TRUE
+                    AND u.mail_verified = TRUE
+                    AND p.send_time <= ?
+                    AND (p.last_sent_on IS NULL OR p.last_sent_on < ?)
+                "#,
+            )
+            .bind(now)
+            .bind(today)
+            .fetch_all(pool)
+            .await
+        })?;
+        Ok(recipients)
+    }
+
+    async fn mark_sent(&self, user_id: &UserId, today: NaiveDate) -> Result<(), NotifyError> {
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    UPDATE notification_preference_table
+                    SET last_sent_on = ?
+                    WHERE user_id = ?
+                "#,
+            )
+            .bind(today)
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+        });
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'a> RepositoryTargetReader<'a, UserId> for PreferenceRepository {
+    type QueryRes = NotificationPreference;
+    type QueryErr = NotifyError;
+
+    // Users who never saved their preferences get the defaults, i.e. no digests.
+    async fn read(&self, id: &'a UserId) -> Result<Self::QueryRes, Self::QueryErr> {
+        let preference = with_pool!(&self.pool, |pool| {
+            query_as::<_, NotificationPreference>(
+                r#"
+                    SELECT enabled, send_time, lead_days
+                    FROM notification_preference_table
+                    WHERE user_id = ?
+                "#,
+            )
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+        })?;
+        Ok(preference.unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{NaiveDate, NaiveTime};
+
+    use crate::{notify::NotificationPreference, testing::TestDb};
+
+    #[tokio::test]
+    async fn test_default_preference() {
+        for db in TestDb::each().await {
+            let repo = db.preferences();
+            let user = db.alice.info();
+
+            let preference = repo.read(&user.user_id).await.unwrap();
+            assert_eq!(preference, NotificationPreference::default());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_due_until_marked_sent() {
+        for db in TestDb::each().await {
+            let repo = db.preferences();
+            let user = db.alice.info();
+            let preference = NotificationPreference {
+                enabled: true,
+                send_time: NaiveTime::from_hms_opt(7, 30, 0).unwrap(),
+                lead_days: 2,
+            };
+            repo.upsert(&user.user_id, &preference).await.unwrap();
+            assert_eq!(repo.read(&user.user_id).await.unwrap(), preference);
+
+            let today = NaiveDate::from_ymd_opt(2025, 4, 10).unwrap();
+            let is_due = |recipients: Vec<super::DigestRecipient>| {
+                recipients.iter().any(|r| r.user_id == user.user_id)
+            };
+
+            let early = NaiveTime::from_hms_opt(7, 0, 0).unwrap();
+            assert!(!is_due(repo.due(today, early).await.unwrap()));
+
+            let late = NaiveTime::from_hms_opt(8, 0, 0).unwrap();
+            assert!(is_due(repo.due(today, late).await.unwrap()));
+
+            repo.mark_sent(&user.user_id, today).await.unwrap();
+            assert!(!is_due(repo.due(today, late).await.unwrap()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unverified_mail_never_due() {
+        for db in TestDb::each().await {
+            let repo = db.preferences();
+            let user = db.carol.info();
+            let preference = NotificationPreference {
+                enabled: true,
+                ..NotificationPreference::default()
+            };
+            repo.upsert(&user.user_id, &preference).await.unwrap();
+
+            let today = NaiveDate::from_ymd_opt(2025, 4, 10).unwrap();
+            let late = NaiveTime::from_hms_opt(23, 0, 0).unwrap();
+            let recipients = repo.due(today, late).await.unwrap();
+            assert!(recipients.iter().all(|r| r.user_id != user.user_id));
+
+            db.users()
+                .mark_verified(&user.user_id, &db.carol.mail)
+                .await
+                .unwrap();
+            let recipients = repo.due(today, late).await.unwrap();
+            assert!(recipients.iter().any(|r| r.user_id == user.user_id));
+        }
+    }
+}
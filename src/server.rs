@@ -0,0 +1,1202 @@
+use std::{convert::Infallible, future::Future, pin::Pin, sync::Arc, task::Poll, time::Duration};
+
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{
+    body::{Body, Bytes},
+    header::{self, HeaderValue},
+    http::request::Parts,
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::net::TcpListener;
+use tower::Service;
+
+use crate::{
+    api_tokens::{
+        self,
+        memory::MemoryApiTokenRepository,
+        repo::{ApiTokenRepository, ApiTokenStore},
+    },
+    auth::{
+        self,
+        memory::MemorySessionRepository,
+        repo::{SessionRepository, SessionStore},
+        token::TokenSigner,
+    },
+    foods::{
+        self,
+        memory::MemoryFoodsRepository,
+        repo::{FoodsRepository, FoodsStore},
+    },
+    households::{
+        self,
+        memory::MemoryHouseholdRepository,
+        repo::{HouseholdRepository, HouseholdStore},
+    },
+    locations::{
+        self,
+        memory::MemoryLocationRepository,
+        repo::{LocationRepository, LocationStore},
+    },
+    mail::Mailer,
+    memory::MemoryStore,
+    notify::{
+        self,
+        memory::MemoryPreferenceRepository,
+        repo::{PreferenceRepository, PreferenceStore},
+    },
+    two_factor::{
+        self,
+        memory::MemoryTwoFactorRepository,
+        repo::{TwoFactorRepository, TwoFactorStore},
+    },
+    users::{
+        self,
+        memory::MemoryUserRepository,
+        policy::PasswordPolicy,
+        repo::{UserRepository, UserStore},
+        verify::MailVerifier,
+        Mail, PubUserInfo,
+    },
+    util::{HashConfig, RateLimiter},
+    DbPool,
+};
+
+static PROBLEM_JSON: &str = "application/problem+json";
+
+// Upper bound for request bodies. Every payload we accept is a small JSON document.
+const BODY_LIMIT: usize = 64 * 1024;
+
+// How often one address may ask for another verification mail.
+const RESEND_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+pub(crate) type HttpResponse = Response<Full<Bytes>>;
+
+pub(crate) struct AppState {
+    pub(crate) foods: Box<dyn FoodsStore>,
+    pub(crate) users: Box<dyn UserStore>,
+    pub(crate) sessions: Box<dyn SessionStore>,
+    pub(crate) preferences: Box<dyn PreferenceStore>,
+    pub(crate) locations: Box<dyn LocationStore>,
+    pub(crate) households: Box<dyn HouseholdStore>,
+    pub(crate) api_tokens: Box<dyn ApiTokenStore>,
+    pub(crate) two_factor: Box<dyn TwoFactorStore>,
+    pub(crate) mailer: Option<Arc<dyn Mailer>>,
+    pub(crate) password_policy: PasswordPolicy,
+    pub(crate) verifier: MailVerifier,
+    pub(crate) hasher: HashConfig,
+    pub(crate) tokens: TokenSigner,
+    pub(crate) resend_limiter: RateLimiter<Mail>,
+}
+
+#[derive(Clone)]
+pub struct App {
+    state: Arc<AppState>,
+}
+
+impl App {
+    pub fn new(
+        pool: DbPool,
+        mailer: Option<Arc<dyn Mailer>>,
+        password_policy: PasswordPolicy,
+        verifier: MailVerifier,
+        hasher: HashConfig,
+        tokens: TokenSigner,
+    ) -> Self {
+        Self {
+            state: Arc::new(AppState {
+                foods: Box::new(FoodsRepository::new(pool.clone())),
+                users: Box::new(UserRepository::new(pool.clone())),
+                sessions: Box::new(SessionRepository::new(pool.clone())),
+                preferences: Box::new(PreferenceRepository::new(pool.clone())),
+                locations: Box::new(LocationRepository::new(pool.clone())),
+                households: Box::new(HouseholdRepository::new(pool.clone())),
+                api_tokens: Box::new(ApiTokenRepository::new(pool.clone())),
+                two_factor: Box::new(TwoFactorRepository::new(pool)),
+                mailer,
+                password_policy,
+                verifier,
+                hasher,
+                tokens,
+                resend_limiter: RateLimiter::new(RESEND_INTERVAL),
+            }),
+        }
+    }
+
+    // Keeps everything in process memory instead of a database, for demos and for tests of the
+    // HTTP layer. Nothing survives a restart.
+    pub fn in_memory(mailer: Option<Arc<dyn Mailer>>) -> Self {
+        let store = MemoryStore::new();
+        Self {
+            state: Arc::new(AppState {
+                foods: Box::new(MemoryFoodsRepository::new(store.clone())),
+                users: Box::new(MemoryUserRepository::new(store.clone())),
+                sessions: Box::new(MemorySessionRepository::new(store.clone())),
+                preferences: Box::new(MemoryPreferenceRepository::new(store.clone())),
+                locations: Box::new(MemoryLocationRepository::new(store.clone())),
+                households: Box::new(MemoryHouseholdRepository::new(store.clone())),
+                api_tokens: Box::new(MemoryApiTokenRepository::new(store.clone())),
+                two_factor: Box::new(MemoryTwoFactorRepository::new(store)),
+                mailer,
+                password_policy: PasswordPolicy::default(),
+                verifier: MailVerifier::default(),
+                hasher: HashConfig::default(),
+                tokens: TokenSigner::default(),
+                resend_limiter: RateLimiter::new(RESEND_INTERVAL),
+            }),
+        }
+    }
+}
+
+impl<B> Service<Request<B>> for App
+where
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Response = HttpResponse;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let state = self.state.clone();
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = match Limited::new(body, BODY_LIMIT).collect().await {
+                Ok(collected) => collected.to_bytes(),
+                Err(_e) => {
+                    return Ok(ApiError::new(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        "failed to read request body",
+                    )
+                    .into_response())
+                }
+            };
+            let res = route(&state, &parts, body).await;
+            Ok(res.unwrap_or_else(ApiError::into_response))
+        })
+    }
+}
+
+async fn route(state: &AppState, parts: &Parts, body: Bytes) -> Result<HttpResponse, ApiError> {
+    let segments: Vec<&str> = parts
+        .uri
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    match (&parts.method, segments.as_slice()) {
+        (&Method::POST, ["auth", "login"]) => auth::handler::login(state, body).await,
+        (&Method::POST, ["auth", "login", "2fa"]) => {
+            auth::handler::login_second_factor(state, body).await
+        }
+        (&Method::POST, ["auth", "logout"]) => auth::handler::logout(state, &parts.headers).await,
+        (&Method::POST, ["auth", "token"]) => auth::handler::token(state, body).await,
+        (&Method::POST, ["auth", "token", "2fa"]) => {
+            auth::handler::token_second_factor(state, body).await
+        }
+        (&Method::POST, ["auth", "token", "refresh"]) => auth::handler::refresh(state, body).await,
+        (&Method::POST, ["auth", "token", "revoke"]) => auth::handler::revoke(state, body).await,
+        (&Method::POST, ["auth", "password-reset"]) => {
+            auth::handler::request_reset(state, body).await
+        }
+        (&Method::POST, ["auth", "password-reset", "confirm"]) => {
+            auth::handler::reset_password(state, body).await
+        }
+        (&Method::POST, ["users"]) => users::handler::create(state, body).await,
+        (&Method::GET, ["users", "verify"]) => {
+            users::handler::verify(state, parts.uri.query()).await
+        }
+        (&Method::POST, ["users", "verify", "resend"]) => {
+            users::handler::resend_verification(state, body).await
+        }
+        _ => {
+            let (user, access) = auth::handler::authenticate(state, &parts.headers).await?;
+            access.check(&parts.method, &segments)?;
+            authenticated_route(state, parts, &segments, user, body).await
+        }
+    }
+}
+
+async fn authenticated_route(
+    state: &AppState,
+    parts: &Parts,
+    segments: &[&str],
+    user: PubUserInfo,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let query = parts.uri.query();
+
+    match (&parts.method, segments) {
+        (&Method::GET, ["users", "me"]) => Ok(json_response(StatusCode::OK, &user)),
+        (&Method::PUT, ["users", "me"]) => users::handler::update(state, user, body).await,
+        (&Method::DELETE, ["users", "me"]) => users::handler::delete(state, user).await,
+        (&Method::PUT, ["users", "me", "password"]) => {
+            users::handler::change_password(state, &parts.headers, user, body).await
+        }
+        (&Method::GET, ["users", "me", "notifications"]) => {
+            notify::handler::read(state, user).await
+        }
+        (&Method::PUT, ["users", "me", "notifications"]) => {
+            notify::handler::update(state, user, body).await
+        }
+        (&Method::POST, ["users", "me", "2fa"]) => two_factor::handler::setup(state, user).await,
+        (&Method::POST, ["users", "me", "2fa", "confirm"]) => {
+            two_factor::handler::confirm(state, user, body).await
+        }
+        (&Method::POST, ["users", "me", "2fa", "disable"]) => {
+            two_factor::handler::disable(state, user, body).await
+        }
+        (&Method::GET, ["users", "me", "tokens"]) => {
+            api_tokens::handler::read_all(state, user).await
+        }
+        (&Method::POST, ["users", "me", "tokens"]) => {
+            api_tokens::handler::create(state, user, body).await
+        }
+        (&Method::DELETE, ["users", "me", "tokens", id]) => {
+            api_tokens::handler::delete(state, user, id).await
+        }
+        (&Method::GET, ["users", id]) => users::handler::read(state, id).await,
+        (&Method::POST, ["foods"]) => foods::handler::create(state, user, body).await,
+        (&Method::GET, ["foods"]) => foods::handler::read_all(state, user, query).await,
+        (&Method::GET, ["foods", "expiring"]) => {
+            foods::handler::read_expiring(state, user, query).await
+        }
+        (&Method::GET, ["foods", id]) => foods::handler::read(state, user, id).await,
+        (&Method::PUT, ["foods", id]) => foods::handler::update(state, user, id, body).await,
+        (&Method::POST, ["foods", id, "move"]) => {
+            foods::handler::relocate(state, user, id, body).await
+        }
+        (&Method::POST, ["foods", id, "consume"]) => {
+            foods::handler::consume(state, user, id, body).await
+        }
+        (&Method::DELETE, ["foods", id]) => foods::handler::delete(state, user, id).await,
+        (&Method::POST, ["locations"]) => locations::handler::create(state, user, body).await,
+        (&Method::GET, ["locations"]) => locations::handler::read_all(state, user).await,
+        (&Method::GET, ["locations", id]) => locations::handler::read(state, user, id).await,
+        (&Method::PUT, ["locations", id]) => {
+            locations::handler::update(state, user, id, body).await
+        }
+        (&Method::DELETE, ["locations", id]) => locations::handler::delete(state, user, id).await,
+        (&Method::POST, ["households"]) => households::handler::create(state, user, body).await,
+        (&Method::GET, ["households"]) => households::handler::read_all(state, user).await,
+        (&Method::POST, ["households", "invites", token, "accept"]) => {
+            households::handler::accept(state, user, token).await
+        }
+        (&Method::GET, ["households", id]) => households::handler::read(state, user, id).await,
+        (&Method::DELETE, ["households", id]) => households::handler::delete(state, user, id).await,
+        (&Method::POST, ["households", id, "invites"]) => {
+            households::handler::invite(state, user, id, body).await
+        }
+        (&Method::PUT, ["households", id, "members", member_id]) => {
+            households::handler::update_member(state, user, id, member_id, body).await
+        }
+        (&Method::DELETE, ["households", id, "members", member_id]) => {
+            households::handler::delete_member(state, user, id, member_id).await
+        }
+        _ => Err(ApiError::new(StatusCode::NOT_FOUND, "no such route")),
+    }
+}
+
+pub async fn serve(listener: TcpListener, app: App) -> std::io::Result<()> {
+    loop {
+        let (stream, _addr) = listener.accept().await?;
+        let service = TowerToHyperService::new(app.clone());
+
+        tokio::spawn(async move {
+            if let Err(e) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                eprintln!("failed to serve connection: {}", e);
+            }
+        });
+    }
+}
+
+pub(crate) fn json_response<T: Serialize>(status: StatusCode, value: &T) -> HttpResponse {
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Full::new(Bytes::from(body)))
+            .unwrap_or_default(),
+        Err(e) => ApiError::logged(StatusCode::INTERNAL_SERVER_ERROR, &e).into_response(),
+    }
+}
+
+pub(crate) fn empty_response(status: StatusCode) -> HttpResponse {
+    Response::builder()
+        .status(status)
+        .body(Full::default())
+        .unwrap_or_default()
+}
+
+pub(crate) fn query_param<'q>(query: Option<&'q str>, name: &str) -> Option<&'q str> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+// A body that is not JSON at all is a bad request. JSON that does not fit the payload is
+// unprocessable, and the error names the field at fault so clients can show it next to the input.
+pub(crate) fn parse_json<T: DeserializeOwned>(body: &Bytes) -> Result<T, ApiError> {
+    let value: serde_json::Value = serde_json::from_slice(body)
+        .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.to_string()))?;
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        let field = e.path().to_string();
+        let message = e.into_inner().to_string();
+        if field == "." {
+            ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, message)
+        } else {
+            ApiError::invalid_field(field, message)
+        }
+    })
+}
+
+// Error response rendered as an RFC 7807 problem document.
+#[derive(Debug, Clone)]
+pub(crate) struct ApiError {
+    status: StatusCode,
+    detail: String,
+    errors: Vec<FieldError>,
+}
+
+// One rejected field of a request body, `field` being its path like `mail` or `items[2].name`.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct FieldError {
+    field: String,
+    message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Problem<'a> {
+    #[serde(rename = "type")]
+    problem_type: &'a str,
+    title: &'a str,
+    status: u16,
+    detail: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    errors: &'a [FieldError],
+}
+
+impl ApiError {
+    pub(crate) fn new(status: StatusCode, detail: impl ToString) -> Self {
+        Self {
+            status,
+            detail: detail.to_string(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub(crate) fn invalid_field(field: impl ToString, message: impl ToString) -> Self {
+        let error = FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        };
+        Self {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            detail: format!("{} {}", error.field, error.message),
+            errors: vec![error],
+        }
+    }
+
+    // For failures on our side: the whole source chain goes to the log, the client only sees
+    // the top level message.
+    pub(crate) fn logged(status: StatusCode, err: &dyn std::error::Error) -> Self {
+        let mut chain = err.to_string();
+        let mut source = err.source();
+        while let Some(e) = source {
+            chain.push_str(": ");
+            chain.push_str(&e.to_string());
+            source = e.source();
+        }
+        eprintln!("{} {}", status, chain);
+        Self::new(status, err)
+    }
+
+    pub(crate) fn into_response(self) -> HttpResponse {
+        let problem = Problem {
+            problem_type: "about:blank",
+            title: self.status.canonical_reason().unwrap_or_default(),
+            status: self.status.as_u16(),
+            detail: &self.detail,
+            errors: &self.errors,
+        };
+        let mut res = json_response(self.status, &problem);
+        res.headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use http_body_util::{BodyExt, Full};
+    use hyper::{body::Bytes, header, Method, Request, StatusCode};
+    use serde_json::{json, Value};
+    use tower::Service;
+
+    use super::{empty_response, json_response, parse_json, query_param, ApiError, App};
+    use crate::{
+        foods::CreateFoodPayload,
+        testing::RecordingMailer,
+        two_factor::totp::TotpSecret,
+        users::{import::import, Mail},
+        util::HashConfig,
+    };
+
+    // Sends one request through the whole service and returns the status, the session cookie
+    // if one was set and the JSON body if there is one.
+    async fn send(
+        app: &mut App,
+        method: Method,
+        path: &str,
+        cookie: Option<&str>,
+        body: Value,
+    ) -> (StatusCode, Option<String>, Value) {
+        let mut req = Request::builder().method(method).uri(path);
+        if let Some(cookie) = cookie {
+            req = req.header(header::COOKIE, cookie);
+        }
+        let req = req.body(Full::new(Bytes::from(body.to_string()))).unwrap();
+
+        let res = app.call(req).await.unwrap();
+        let status = res.status();
+        let cookie = res
+            .headers()
+            .get(header::SET_COOKIE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(str::to_string);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        (status, cookie, body)
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_food_round_trip() {
+        let mut app = App::in_memory(None);
+        let user = json!({
+            "user_name": "alice",
+            "mail": "alice@mail.com",
+            "password": "alice_pass",
+        });
+        let (status, _, _) = send(&mut app, Method::POST, "/users", None, user).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let login = json!({"mail": "alice@mail.com", "password": "alice_pass"});
+        let (status, cookie, _) = send(&mut app, Method::POST, "/auth/login", None, login).await;
+        assert_eq!(status, StatusCode::OK);
+        let cookie = cookie.unwrap();
+
+        let (status, _, _) = send(&mut app, Method::GET, "/foods", None, Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let food = json!({"food_name": "milk", "exp": "2025-04-08", "quantity": 2.0});
+        let (status, _, food) = send(&mut app, Method::POST, "/foods", Some(&cookie), food).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let food_path = format!("/foods/{}", food["food_id"].as_str().unwrap());
+        let location_path = format!("/locations/{}", food["location_id"].as_str().unwrap());
+
+        let (status, _, read) = send(
+            &mut app,
+            Method::GET,
+            &food_path,
+            Some(&cookie),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(read, food);
+
+        // The fridge still holds the milk.
+        let (status, _, _) = send(
+            &mut app,
+            Method::DELETE,
+            &location_path,
+            Some(&cookie),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let consume_path = format!("{}/consume", food_path);
+        let amount = json!({"amount": 2.0});
+        let (status, _, _) =
+            send(&mut app, Method::POST, &consume_path, Some(&cookie), amount).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, _, _) = send(
+            &mut app,
+            Method::GET,
+            &food_path,
+            Some(&cookie),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _, _) = send(
+            &mut app,
+            Method::DELETE,
+            &location_path,
+            Some(&cookie),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_fields_are_named() {
+        let mut app = App::in_memory(None);
+        let user = json!({
+            "user_name": "alice",
+            "mail": "not-an-email",
+            "password": "alice_pass",
+        });
+        let (status, _, body) = send(&mut app, Method::POST, "/users", None, user).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body["errors"],
+            json!([{"field": "mail", "message": "must be a valid mail address"}])
+        );
+
+        let user = json!({
+            "user_name": "alice",
+            "mail": "alice@mail.com",
+            "password": "short",
+        });
+        let (status, _, body) = send(&mut app, Method::POST, "/users", None, user).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["field"], "password");
+
+        let user = json!({
+            "user_name": "alice",
+            "mail": "alice@mail.com",
+            "password": "alice_pass",
+        });
+        send(&mut app, Method::POST, "/users", None, user).await;
+        let login = json!({"mail": "alice@mail.com", "password": "alice_pass"});
+        let (_, cookie, _) = send(&mut app, Method::POST, "/auth/login", None, login).await;
+        let cookie = cookie.unwrap();
+
+        let preference = json!({"enabled": true, "send_time": "08:00:00", "lead_days": 31});
+        let (status, _, body) = send(
+            &mut app,
+            Method::PUT,
+            "/users/me/notifications",
+            Some(&cookie),
+            preference,
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body["errors"],
+            json!([{"field": "lead_days", "message": "must be at most 30"}])
+        );
+
+        let (status, _, body) = send(
+            &mut app,
+            Method::GET,
+            "/foods/expiring?days=many",
+            Some(&cookie),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["field"], "days");
+    }
+
+    #[tokio::test]
+    async fn test_change_and_reset_password() {
+        let mailer = Arc::new(RecordingMailer::default());
+        let mut app = App::in_memory(Some(mailer.clone()));
+        let user = json!({
+            "user_name": "alice",
+            "mail": "alice@mail.com",
+            "password": "alice_pass",
+        });
+        send(&mut app, Method::POST, "/users", None, user).await;
+        let login = json!({"mail": "alice@mail.com", "password": "alice_pass"});
+        let (_, cookie, _) = send(&mut app, Method::POST, "/auth/login", None, login.clone()).await;
+        let cookie = cookie.unwrap();
+        let (_, other_cookie, _) =
+            send(&mut app, Method::POST, "/auth/login", None, login.clone()).await;
+        let other_cookie = other_cookie.unwrap();
+        let (_, _, tokens) = send(&mut app, Method::POST, "/auth/token", None, login).await;
+
+        let change = json!({"current_password": "wrong_pass", "new_password": "changed_pass"});
+        let (status, _, body) = send(
+            &mut app,
+            Method::PUT,
+            "/users/me/password",
+            Some(&cookie),
+            change,
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["field"], "current_password");
+
+        let change = json!({"current_password": "alice_pass", "new_password": "changed_pass"});
+        let (status, _, _) = send(
+            &mut app,
+            Method::PUT,
+            "/users/me/password",
+            Some(&cookie),
+            change,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        // The change signed the other sessions out, the one that made it stays.
+        let (status, _, _) = send(
+            &mut app,
+            Method::GET,
+            "/users/me",
+            Some(&other_cookie),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let refresh = json!({"refresh_token": tokens["refresh_token"]});
+        let (status, _, _) =
+            send(&mut app, Method::POST, "/auth/token/refresh", None, refresh).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _, _) = send(
+            &mut app,
+            Method::GET,
+            "/users/me",
+            Some(&cookie),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let payload = json!({"name": "script", "scopes": ["account"]});
+        let (_, _, issued) = send(
+            &mut app,
+            Method::POST,
+            "/users/me/tokens",
+            Some(&cookie),
+            payload,
+        )
+        .await;
+        let api_token = issued["token"].as_str().unwrap();
+        assert_eq!(
+            send_bearer(&mut app, Method::GET, "/users/me", api_token).await,
+            StatusCode::OK
+        );
+
+        // Nobody learns whether a mail is registered.
+        let request = json!({"mail": "nobody@mail.com"});
+        let (status, _, _) = send(
+            &mut app,
+            Method::POST,
+            "/auth/password-reset",
+            None,
+            request,
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        // Only the verification mail from signing up went out.
+        assert_eq!(mailer.sent().len(), 1);
+
+        let request = json!({"mail": "alice@mail.com"});
+        let (status, _, _) = send(
+            &mut app,
+            Method::POST,
+            "/auth/password-reset",
+            None,
+            request,
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let sent = mailer.sent();
+        let token = sent[1].body.split("\n\n").nth(2).unwrap();
+
+        let reset = json!({"token": token, "new_password": "reset_pass"});
+        let path = "/auth/password-reset/confirm";
+        let (status, _, _) = send(&mut app, Method::POST, path, None, reset.clone()).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _, _) = send(&mut app, Method::POST, path, None, reset).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        // The reset signed every session out and revoked the API tokens.
+        let (status, _, _) = send(
+            &mut app,
+            Method::GET,
+            "/users/me",
+            Some(&cookie),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            send_bearer(&mut app, Method::GET, "/users/me", api_token).await,
+            StatusCode::UNAUTHORIZED
+        );
+        let login = json!({"mail": "alice@mail.com", "password": "reset_pass"});
+        let (status, _, _) = send(&mut app, Method::POST, "/auth/login", None, login).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_mail_verification() {
+        let mailer = Arc::new(RecordingMailer::default());
+        let mut app = App::in_memory(Some(mailer.clone()));
+        let user = json!({
+            "user_name": "alice",
+            "mail": "alice@mail.com",
+            "password": "alice_pass",
+        });
+        send(&mut app, Method::POST, "/users", None, user).await;
+        let login = json!({"mail": "alice@mail.com", "password": "alice_pass"});
+        let (_, cookie, _) = send(&mut app, Method::POST, "/auth/login", None, login).await;
+        let cookie = cookie.unwrap();
+
+        let household = json!({"household_name": "  "});
+        let (status, _, body) = send(
+            &mut app,
+            Method::POST,
+            "/households",
+            Some(&cookie),
+            household,
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["field"], "household_name");
+
+        let household = json!({"household_name": "Shared"});
+        let (_, _, household) = send(
+            &mut app,
+            Method::POST,
+            "/households",
+            Some(&cookie),
+            household,
+        )
+        .await;
+        let invites = format!(
+            "/households/{}/invites",
+            household["household_id"].as_str().unwrap()
+        );
+        let invite = json!({"mail": "bob@mail.com", "role": "member"});
+        let (status, _, _) = send(
+            &mut app,
+            Method::POST,
+            &invites,
+            Some(&cookie),
+            invite.clone(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let resend = json!({"mail": "alice@mail.com"});
+        let path = "/users/verify/resend";
+        let (status, _, _) = send(&mut app, Method::POST, path, None, resend.clone()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let (status, _, _) = send(&mut app, Method::POST, path, None, resend).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+        let sent = mailer.sent();
+        assert_eq!(sent.len(), 2);
+        let link = sent[1]
+            .body
+            .lines()
+            .find(|line| line.starts_with("http://"))
+            .unwrap();
+        let path = link.trim_start_matches("http://localhost:8080");
+        let (status, _, _) = send(&mut app, Method::GET, path, None, Value::Null).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _, _) = send(
+            &mut app,
+            Method::GET,
+            "/users/verify?token=forged",
+            None,
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let (status, _, _) = send(&mut app, Method::POST, &invites, Some(&cookie), invite).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_login_rehashes_outdated_password() {
+        let mut app = App::in_memory(None);
+        let user = json!({
+            "user_name": "alice",
+            "mail": "alice@mail.com",
+            "password": "alice_pass",
+        });
+        send(&mut app, Method::POST, "/users", None, user).await;
+
+        // The server restarts with stronger settings.
+        Arc::get_mut(&mut app.state).unwrap().hasher = HashConfig::new(32 * 1024, 3, 1).unwrap();
+        let mail = Mail::try_from("alice@mail.com").unwrap();
+        let user_id = app.state.users.read_by_mail(&mail).await.unwrap().user_id;
+        let stored = app.state.users.password(&user_id).await.unwrap();
+        assert!(stored.is_outdated(&app.state.hasher));
+
+        let login = json!({"mail": "alice@mail.com", "password": "alice_pass"});
+        let (status, _, _) = send(&mut app, Method::POST, "/auth/login", None, login.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        let rehashed = app.state.users.password(&user_id).await.unwrap();
+        assert!(!rehashed.is_outdated(&app.state.hasher));
+        assert_ne!(rehashed, stored);
+
+        let (status, _, _) = send(&mut app, Method::POST, "/auth/login", None, login).await;
+        assert_eq!(status, StatusCode::OK);
+        let wrong = json!({"mail": "alice@mail.com", "password": "wrong_pass"});
+        let (status, _, _) = send(&mut app, Method::POST, "/auth/login", None, wrong).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_login_upgrades_imported_hash() {
+        let mut app = App::in_memory(None);
+        let user = json!({
+            "user_name": "dave",
+            "mail": "dave@mail.com",
+            "password_hash": bcrypt::hash("dave_pass", 4).unwrap(),
+        });
+        import(app.state.users.as_ref(), &user.to_string())
+            .await
+            .unwrap();
+
+        let login = json!({"mail": "dave@mail.com", "password": "dave_pass"});
+        let (status, _, _) = send(&mut app, Method::POST, "/auth/login", None, login.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        let mail = Mail::try_from("dave@mail.com").unwrap();
+        let user_id = app.state.users.read_by_mail(&mail).await.unwrap().user_id;
+        let stored = app.state.users.password(&user_id).await.unwrap();
+        assert!(String::from(stored).starts_with("$argon2id$"));
+
+        let (status, _, _) = send(&mut app, Method::POST, "/auth/login", None, login).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    // A GET with an access token instead of a cookie.
+    async fn send_bearer(app: &mut App, method: Method, path: &str, token: &str) -> StatusCode {
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        app.call(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_access_and_refresh_tokens() {
+        let mut app = App::in_memory(None);
+        let user = json!({
+            "user_name": "alice",
+            "mail": "alice@mail.com",
+            "password": "alice_pass",
+        });
+        send(&mut app, Method::POST, "/users", None, user).await;
+
+        let login = json!({"mail": "alice@mail.com", "password": "wrong_pass"});
+        let (status, _, _) = send(&mut app, Method::POST, "/auth/token", None, login).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let login = json!({"mail": "alice@mail.com", "password": "alice_pass"});
+        let (status, cookie, tokens) =
+            send(&mut app, Method::POST, "/auth/token", None, login).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(cookie, None);
+        assert_eq!(tokens["token_type"], "Bearer");
+        assert_eq!(tokens["expires_in"], 900);
+
+        let access = tokens["access_token"].as_str().unwrap();
+        assert_eq!(
+            send_bearer(&mut app, Method::GET, "/users/me", access).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send_bearer(&mut app, Method::GET, "/users/me", "forged").await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let path = "/auth/token/refresh";
+        let first = json!({"refresh_token": tokens["refresh_token"]});
+        let (status, _, rotated) = send(&mut app, Method::POST, path, None, first.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_ne!(rotated["refresh_token"], tokens["refresh_token"]);
+        let access = rotated["access_token"].as_str().unwrap();
+        assert_eq!(
+            send_bearer(&mut app, Method::GET, "/foods", access).await,
+            StatusCode::OK
+        );
+
+        // Replaying the first refresh token revokes the one it was rotated into as well.
+        let (status, _, _) = send(&mut app, Method::POST, path, None, first).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let second = json!({"refresh_token": rotated["refresh_token"]});
+        let (status, _, _) = send(&mut app, Method::POST, path, None, second).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let login = json!({"mail": "alice@mail.com", "password": "alice_pass"});
+        let (_, _, tokens) = send(&mut app, Method::POST, "/auth/token", None, login).await;
+        let refresh = json!({"refresh_token": tokens["refresh_token"]});
+        let (status, _, _) = send(
+            &mut app,
+            Method::POST,
+            "/auth/token/revoke",
+            None,
+            refresh.clone(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _, _) = send(&mut app, Method::POST, path, None, refresh).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_api_tokens() {
+        let mut app = App::in_memory(None);
+        let user = json!({
+            "user_name": "alice",
+            "mail": "alice@mail.com",
+            "password": "alice_pass",
+        });
+        send(&mut app, Method::POST, "/users", None, user).await;
+        let login = json!({"mail": "alice@mail.com", "password": "alice_pass"});
+        let (_, cookie, _) = send(&mut app, Method::POST, "/auth/login", None, login).await;
+        let cookie = cookie.unwrap();
+
+        let path = "/users/me/tokens";
+        let empty = json!({"name": "fridge door", "scopes": []});
+        let (status, _, body) = send(&mut app, Method::POST, path, Some(&cookie), empty).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["field"], "scopes");
+        let past = json!({
+            "name": "fridge door",
+            "scopes": ["foods:read"],
+            "expires_at": "2020-01-01T00:00:00Z",
+        });
+        let (status, _, body) = send(&mut app, Method::POST, path, Some(&cookie), past).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["field"], "expires_at");
+
+        let payload = json!({"name": "fridge door", "scopes": ["foods:read", "foods:read"]});
+        let (status, _, issued) = send(&mut app, Method::POST, path, Some(&cookie), payload).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(issued["scopes"], json!(["foods:read"]));
+        assert_eq!(issued["expires_at"], Value::Null);
+        let token = issued["token"].as_str().unwrap();
+        assert!(token.starts_with("fridge_"));
+
+        // The secret is shown once only.
+        let (status, _, listed) = send(&mut app, Method::GET, path, Some(&cookie), json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(listed["tokens"][0]["token_id"], issued["token_id"]);
+        assert_eq!(listed["tokens"][0].get("token"), None);
+
+        assert_eq!(
+            send_bearer(&mut app, Method::GET, "/foods", token).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send_bearer(&mut app, Method::POST, "/foods", token).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            send_bearer(&mut app, Method::GET, "/users/me", token).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            send_bearer(&mut app, Method::GET, path, token).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            send_bearer(&mut app, Method::GET, "/foods", "fridge_forged").await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let token_path = format!("{}/{}", path, issued["token_id"].as_str().unwrap());
+        let (status, _, _) = send(
+            &mut app,
+            Method::DELETE,
+            &token_path,
+            Some(&cookie),
+            json!({}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(
+            send_bearer(&mut app, Method::GET, "/foods", token).await,
+            StatusCode::UNAUTHORIZED
+        );
+        let (status, _, _) = send(
+            &mut app,
+            Method::DELETE,
+            &token_path,
+            Some(&cookie),
+            json!({}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_two_factor_login() {
+        let mut app = App::in_memory(None);
+        let user = json!({
+            "user_name": "alice",
+            "mail": "alice@mail.com",
+            "password": "alice_pass",
+        });
+        send(&mut app, Method::POST, "/users", None, user).await;
+        let login = json!({"mail": "alice@mail.com", "password": "alice_pass"});
+        let (_, cookie, _) = send(&mut app, Method::POST, "/auth/login", None, login.clone()).await;
+        let cookie = cookie.unwrap();
+
+        let (status, _, setup) = send(
+            &mut app,
+            Method::POST,
+            "/users/me/2fa",
+            Some(&cookie),
+            json!({}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(setup["otpauth_uri"]
+            .as_str()
+            .unwrap()
+            .starts_with("otpauth://totp/Fridge:alice%40mail.com?"));
+        assert!(setup["qr_svg"].as_str().unwrap().contains("<svg"));
+        let secret = TotpSecret::from_base32(setup["secret"].as_str().unwrap()).unwrap();
+        let step = chrono::Utc::now().timestamp() / 30;
+
+        let path = "/users/me/2fa/confirm";
+        let wrong = json!({"code": "abcdef"});
+        let (status, _, body) = send(&mut app, Method::POST, path, Some(&cookie), wrong).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["field"], "code");
+        let code = json!({"code": secret.code_at(step)});
+        let (status, _, codes) = send(&mut app, Method::POST, path, Some(&cookie), code).await;
+        assert_eq!(status, StatusCode::OK);
+        let recovery_codes = codes["recovery_codes"].as_array().unwrap();
+        assert_eq!(recovery_codes.len(), 10);
+        let (status, _, _) = send(
+            &mut app,
+            Method::POST,
+            "/users/me/2fa",
+            Some(&cookie),
+            json!({}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // The password alone only gets a challenge now.
+        let (status, cookie, challenge) =
+            send(&mut app, Method::POST, "/auth/login", None, login.clone()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(cookie, None);
+        assert_eq!(challenge["expires_in"], 300);
+        let path = "/auth/login/2fa";
+        let second = json!({
+            "challenge": challenge["challenge"],
+            "code": secret.code_at(step + 1),
+        });
+        let (status, cookie, user) = send(&mut app, Method::POST, path, None, second.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(user["user_name"], "alice");
+        let cookie = cookie.unwrap();
+        // Neither the challenge nor the code works twice.
+        let (status, _, _) = send(&mut app, Method::POST, path, None, second).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (_, _, challenge) =
+            send(&mut app, Method::POST, "/auth/login", None, login.clone()).await;
+        let replay = json!({
+            "challenge": challenge["challenge"],
+            "code": secret.code_at(step + 1),
+        });
+        let (status, _, _) = send(&mut app, Method::POST, path, None, replay).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _, challenge) =
+            send(&mut app, Method::POST, "/auth/token", None, login.clone()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let recovery = json!({
+            "challenge": challenge["challenge"],
+            "recovery_code": recovery_codes[0],
+        });
+        let (status, _, tokens) =
+            send(&mut app, Method::POST, "/auth/token/2fa", None, recovery).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(tokens["token_type"], "Bearer");
+        let (_, _, challenge) =
+            send(&mut app, Method::POST, "/auth/token", None, login.clone()).await;
+        let recovery = json!({
+            "challenge": challenge["challenge"],
+            "recovery_code": recovery_codes[0],
+        });
+        let (status, _, _) = send(&mut app, Method::POST, "/auth/token/2fa", None, recovery).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let path = "/users/me/2fa/disable";
+        let wrong = json!({"current_password": "wrong_pass"});
+        let (status, _, _) = send(&mut app, Method::POST, path, Some(&cookie), wrong).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let right = json!({"current_password": "alice_pass"});
+        let (status, _, _) = send(&mut app, Method::POST, path, Some(&cookie), right).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, cookie, _) = send(&mut app, Method::POST, "/auth/login", None, login).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(cookie.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_api_error_response() {
+        let res = ApiError::new(StatusCode::NOT_FOUND, "no such route").into_response();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.headers()["content-type"], "application/problem+json");
+
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            &body[..],
+            br#"{"type":"about:blank","title":"Not Found","status":404,"detail":"no such route"}"#
+        );
+    }
+
+    #[test]
+    fn test_json_response_content_type() {
+        let res = json_response(StatusCode::OK, &vec![1, 2, 3]);
+        assert_eq!(res.headers()["content-type"], "application/json");
+        assert_eq!(
+            empty_response(StatusCode::NO_CONTENT).status(),
+            StatusCode::NO_CONTENT
+        );
+    }
+
+    #[test]
+    fn test_parse_json_rejects_malformed_body() {
+        let body = Bytes::from_static(b"{\"food_name\": 1");
+        let err = parse_json::<CreateFoodPayload>(&body).unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_query_param() {
+        assert_eq!(query_param(Some("days=3&sort=exp"), "days"), Some("3"));
+        assert_eq!(query_param(Some("sort=exp"), "days"), None);
+        assert_eq!(query_param(None, "days"), None);
+    }
+}
@@ -0,0 +1,385 @@
+// Test harness: every test gets a database of its own, migrated and seeded with the fixture users,
+// and nothing is left behind afterwards.
+//
+// Set `TEST_DATABASE_URL` to run the repository tests against a MySQL or Postgres server. A scratch
+// database is created next to the one the URL names, so the user needs `CREATE DATABASE`
+// privileges. Without it the tests run on a throwaway SQLite file when the `sqlite` feature is
+// enabled and on the in-memory backend otherwise, so `cargo test` needs no setup at all. The
+// repository tests go through `TestDb::each`, which adds the in-memory backend to a SQL one.
+
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+    thread,
+};
+
+#[cfg(feature = "sqlite")]
+use std::{env, fs, path::PathBuf};
+
+use async_trait::async_trait;
+use sqlx::mysql::{MySqlConnectOptions, MySqlPoolOptions};
+#[cfg(feature = "postgres")]
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use uuid::Uuid;
+
+use crate::{
+    api_tokens::{memory::MemoryApiTokenRepository, repo::ApiTokenRepository, repo::ApiTokenStore},
+    auth::{memory::MemorySessionRepository, repo::SessionRepository, repo::SessionStore},
+    db::{with_pool, DbPool},
+    foods::{memory::MemoryFoodsRepository, repo::FoodsRepository, repo::FoodsStore},
+    households::{
+        memory::MemoryHouseholdRepository, repo::HouseholdRepository, repo::HouseholdStore,
+    },
+    locations::{memory::MemoryLocationRepository, repo::LocationRepository, repo::LocationStore},
+    mail::{MailError, Mailer, OutgoingMail},
+    memory::MemoryStore,
+    migrate,
+    notify::{
+        memory::MemoryPreferenceRepository, repo::PreferenceRepository, repo::PreferenceStore,
+    },
+    two_factor::{
+        memory::MemoryTwoFactorRepository, repo::TwoFactorRepository, repo::TwoFactorStore,
+    },
+    users::{
+        memory::MemoryUserRepository, repo::UserRepository, repo::UserStore, CreateUserPayload,
+        Mail, Password, PubUserInfo, User, UserId, UserName,
+    },
+    util::HashConfig,
+};
+
+// Every fixture user logs in with this password.
+pub(crate) static FIXTURE_PASSWORD: &str = "fixture_pass";
+
+// Hashing is slow in debug builds, so it is done once and the hash shared by all fixture users.
+fn fixture_hash() -> String {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| HashConfig::default().hash(FIXTURE_PASSWORD).unwrap())
+        .clone()
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TestUser {
+    pub(crate) user_id: UserId,
+    pub(crate) user_name: UserName,
+    pub(crate) mail: Mail,
+    mail_verified: bool,
+}
+
+impl TestUser {
+    fn new(name: &str, mail_verified: bool) -> Self {
+        Self {
+            user_id: UserId::from(Uuid::new_v4().to_string()),
+            user_name: UserName::try_from(name).unwrap(),
+            mail: Mail::try_from(format!("{}@mail.com", name)).unwrap(),
+            mail_verified,
+        }
+    }
+
+    pub(crate) fn info(&self) -> PubUserInfo {
+        PubUserInfo {
+            user_id: self.user_id.clone(),
+            user_name: self.user_name.clone(),
+        }
+    }
+
+    fn user(&self) -> User {
+        let payload = CreateUserPayload {
+            user_name: self.user_name.clone(),
+            mail: self.mail.clone(),
+            password: Password::try_from(FIXTURE_PASSWORD).unwrap(),
+        };
+        let hash = fixture_hash();
+        let user = User::with_id(
+            self.user_id.clone(),
+            payload,
+            Box::new(move |_: &str| Ok(hash.clone())),
+        )
+        .unwrap();
+        if self.mail_verified {
+            user.verified()
+        } else {
+            user
+        }
+    }
+}
+
+// Keeps the mails instead of sending them, or fails like a server that is down.
+#[derive(Default)]
+pub(crate) struct RecordingMailer {
+    sent: Mutex<Vec<OutgoingMail>>,
+    failing: AtomicBool,
+}
+
+impl RecordingMailer {
+    pub(crate) fn sent(&self) -> Vec<OutgoingMail> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_failing(&self, failing: bool) {
+        self.failing.store(failing, Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl Mailer for RecordingMailer {
+    async fn send(&self, mail: &OutgoingMail) -> Result<(), MailError> {
+        if self.failing.load(Ordering::SeqCst) {
+            return Err(MailError::Config("mail server is down".to_string()));
+        }
+        self.sent.lock().unwrap().push(mail.clone());
+        Ok(())
+    }
+}
+
+enum Backend {
+    Sql(DbPool),
+    Memory(MemoryStore),
+}
+
+pub(crate) struct TestDb {
+    backend: Backend,
+    // Declared after the backend so the pool goes first.
+    _scratch: Option<Scratch>,
+    pub(crate) alice: TestUser,
+    pub(crate) bob: TestUser,
+    pub(crate) carol: TestUser,
+}
+
+impl TestDb {
+    pub(crate) async fn new() -> Self {
+        let (backend, scratch) = match dotenvy::var("TEST_DATABASE_URL") {
+            Ok(url) => {
+                let (pool, scratch) = Scratch::server_database(&url).await;
+                (Backend::Sql(pool), Some(scratch))
+            }
+            Err(_) => Self::local_backend().await,
+        };
+        if let Backend::Sql(pool) = &backend {
+            migrate::up(pool).await.unwrap();
+        }
+        Self::seeded(backend, scratch).await
+    }
+
+    // Always the in-memory backend, whatever the other tests run on.
+    pub(crate) async fn memory() -> Self {
+        Self::seeded(Backend::Memory(MemoryStore::new()), None).await
+    }
+
+    // The backend `new` picks and, when that is a SQL one, the in-memory backend too. The
+    // repository tests run on each, as the in-memory repositories have to behave the same.
+    pub(crate) async fn each() -> Vec<Self> {
+        let db = Self::new().await;
+        match db.backend {
+            Backend::Sql(_) => vec![db, Self::memory().await],
+            Backend::Memory(_) => vec![db],
+        }
+    }
+
+    async fn seeded(backend: Backend, scratch: Option<Scratch>) -> Self {
+        let db = Self {
+            backend,
+            _scratch: scratch,
+            alice: TestUser::new("alice", true),
+            bob: TestUser::new("bob", true),
+            // Has not verified the mail yet.
+            carol: TestUser::new("carol", false),
+        };
+        let users = db.users();
+        for fixture in [&db.alice, &db.bob, &db.carol] {
+            users.insert(&fixture.user()).await.unwrap();
+        }
+        db
+    }
+
+    #[cfg(feature = "sqlite")]
+    async fn local_backend() -> (Backend, Option<Scratch>) {
+        let (pool, scratch) = Scratch::sqlite_file().await;
+        (Backend::Sql(pool), Some(scratch))
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    async fn local_backend() -> (Backend, Option<Scratch>) {
+        (Backend::Memory(MemoryStore::new()), None)
+    }
+
+    pub(crate) fn users(&self) -> Box<dyn UserStore> {
+        match &self.backend {
+            Backend::Sql(pool) => Box::new(UserRepository::new(pool.clone())),
+            Backend::Memory(store) => Box::new(MemoryUserRepository::new(store.clone())),
+        }
+    }
+
+    pub(crate) fn sessions(&self) -> Box<dyn SessionStore> {
+        match &self.backend {
+            Backend::Sql(pool) => Box::new(SessionRepository::new(pool.clone())),
+            Backend::Memory(store) => Box::new(MemorySessionRepository::new(store.clone())),
+        }
+    }
+
+    pub(crate) fn preferences(&self) -> Box<dyn PreferenceStore> {
+        match &self.backend {
+            Backend::Sql(pool) => Box::new(PreferenceRepository::new(pool.clone())),
+            Backend::Memory(store) => Box::new(MemoryPreferenceRepository::new(store.clone())),
+        }
+    }
+
+    pub(crate) fn households(&self) -> Box<dyn HouseholdStore> {
+        match &self.backend {
+            Backend::Sql(pool) => Box::new(HouseholdRepository::new(pool.clone())),
+            Backend::Memory(store) => Box::new(MemoryHouseholdRepository::new(store.clone())),
+        }
+    }
+
+    pub(crate) fn locations(&self) -> Box<dyn LocationStore> {
+        match &self.backend {
+            Backend::Sql(pool) => Box::new(LocationRepository::new(pool.clone())),
+            Backend::Memory(store) => Box::new(MemoryLocationRepository::new(store.clone())),
+        }
+    }
+
+    pub(crate) fn foods(&self) -> Box<dyn FoodsStore> {
+        match &self.backend {
+            Backend::Sql(pool) => Box::new(FoodsRepository::new(pool.clone())),
+            Backend::Memory(store) => Box::new(MemoryFoodsRepository::new(store.clone())),
+        }
+    }
+
+    pub(crate) fn api_tokens(&self) -> Box<dyn ApiTokenStore> {
+        match &self.backend {
+            Backend::Sql(pool) => Box::new(ApiTokenRepository::new(pool.clone())),
+            Backend::Memory(store) => Box::new(MemoryApiTokenRepository::new(store.clone())),
+        }
+    }
+
+    pub(crate) fn two_factor(&self) -> Box<dyn TwoFactorStore> {
+        match &self.backend {
+            Backend::Sql(pool) => Box::new(TwoFactorRepository::new(pool.clone())),
+            Backend::Memory(store) => Box::new(MemoryTwoFactorRepository::new(store.clone())),
+        }
+    }
+}
+
+// A database with nothing in it, not even the migrations, for the tests of `migrate`. There is
+// none on the in-memory backend.
+pub(crate) struct EmptyDb {
+    pub(crate) pool: DbPool,
+    _scratch: Scratch,
+}
+
+impl EmptyDb {
+    pub(crate) async fn new() -> Option<Self> {
+        let (pool, scratch) = match dotenvy::var("TEST_DATABASE_URL") {
+            Ok(url) => Scratch::server_database(&url).await,
+            Err(_) => Self::local().await?,
+        };
+        Some(Self {
+            pool,
+            _scratch: scratch,
+        })
+    }
+
+    #[cfg(feature = "sqlite")]
+    async fn local() -> Option<(DbPool, Scratch)> {
+        Some(Scratch::sqlite_file().await)
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    async fn local() -> Option<(DbPool, Scratch)> {
+        None
+    }
+}
+
+// Where the database of one test lives, removed again when the test is done with it.
+enum Scratch {
+    #[cfg(feature = "sqlite")]
+    File(PathBuf),
+    Database {
+        url: String,
+        name: String,
+    },
+}
+
+impl Scratch {
+    #[cfg(feature = "sqlite")]
+    async fn sqlite_file() -> (DbPool, Self) {
+        let path = env::temp_dir().join(format!("fridge_test_{}.db", Uuid::new_v4().simple()));
+        let pool = DbPool::connect(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+        (pool, Scratch::File(path))
+    }
+
+    // Creates a fresh database on the server `url` points at and connects to it.
+    async fn server_database(url: &str) -> (DbPool, Self) {
+        let name = format!("fridge_test_{}", Uuid::new_v4().simple());
+        let admin = DbPool::connect(url).await.unwrap();
+        let create = format!("CREATE DATABASE {}", name);
+        with_pool!(&admin, |admin| {
+            sqlx::raw_sql(&create).execute(admin).await.unwrap();
+            admin.close().await;
+        });
+
+        let pool = match admin {
+            DbPool::MySql(_) => {
+                let options = MySqlConnectOptions::from_str(url).unwrap().database(&name);
+                DbPool::from(MySqlPoolOptions::new().connect_with(options).await.unwrap())
+            }
+            #[cfg(feature = "sqlite")]
+            DbPool::Sqlite(_) => panic!("TEST_DATABASE_URL must point at a database server"),
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(_) => {
+                let options = PgConnectOptions::from_str(url).unwrap().database(&name);
+                DbPool::from(PgPoolOptions::new().connect_with(options).await.unwrap())
+            }
+        };
+        let scratch = Scratch::Database {
+            url: url.to_string(),
+            name,
+        };
+        (pool, scratch)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        match self {
+            #[cfg(feature = "sqlite")]
+            Scratch::File(path) => {
+                for suffix in ["", "-wal", "-shm"] {
+                    let mut file = path.clone().into_os_string();
+                    file.push(suffix);
+                    let _ = fs::remove_file(file);
+                }
+            }
+            Scratch::Database { url, name } => {
+                let url = url.clone();
+                // Postgres refuses to drop a database that still has sessions, and the pool of the
+                // test may not have closed them yet.
+                let drop = match url.split_once(':') {
+                    Some(("postgres" | "postgresql", _)) => {
+                        format!("DROP DATABASE IF EXISTS {} WITH (FORCE)", name)
+                    }
+                    _ => format!("DROP DATABASE IF EXISTS {}", name),
+                };
+                // The runtime of the test is on its way out, so this one gets its own.
+                let _ = thread::spawn(move || {
+                    let runtime = tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .unwrap();
+                    runtime.block_on(async {
+                        let admin = DbPool::connect(&url).await.unwrap();
+                        with_pool!(&admin, |admin| {
+                            sqlx::raw_sql(&drop).execute(admin).await.unwrap();
+                            admin.close().await;
+                        });
+                    });
+                })
+                .join();
+            }
+        }
+    }
+}
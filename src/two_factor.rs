@@ -0,0 +1,241 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use data_encoding::BASE32_NOPAD;
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use thiserror::Error;
+
+use crate::{
+    db::{classify, impl_from_row, DbErrorKind},
+    users::{Password, UserId},
+    util::{gen_random_token, hash_token},
+};
+
+use totp::TotpSecret;
+
+pub(crate) mod handler;
+pub(crate) mod memory;
+pub(crate) mod repo;
+pub(crate) mod totp;
+
+const CHALLENGE_TTL_MINUTES: i64 = 5;
+const RECOVERY_CODE_COUNT: usize = 10;
+// 80 random bits, so a plain hash keeps them safe like it does for the other tokens.
+const RECOVERY_CODE_BYTES: usize = 10;
+
+// The TOTP secret of a user. It is pending until the user proved with a first code that the app
+// has it, and only then does login ask for codes.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TotpEnrolment {
+    user_id: UserId,
+    secret: TotpSecret,
+    enabled: bool,
+    // The time step of the last code accepted, as no code works twice.
+    last_step: i64,
+}
+
+impl TotpEnrolment {
+    pub(crate) fn new(user_id: UserId) -> Self {
+        Self {
+            user_id,
+            secret: TotpSecret::generate(),
+            enabled: false,
+            last_step: 0,
+        }
+    }
+
+    pub(crate) fn user_id(&self) -> &UserId {
+        &self.user_id
+    }
+
+    pub(crate) fn secret(&self) -> &TotpSecret {
+        &self.secret
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+impl_from_row!(TotpEnrolment, |row| {
+    let secret: String = row.try_get("secret")?;
+    Ok(TotpEnrolment {
+        user_id: row.try_get("user_id")?,
+        secret: TotpSecret::from_base32(&secret).ok_or_else(|| sqlx::Error::ColumnDecode {
+            index: "secret".to_string(),
+            source: "secret is not base32".into(),
+        })?,
+        enabled: row.try_get("enabled")?,
+        last_step: row.try_get("last_step")?,
+    })
+});
+
+// Signs in when the authenticator app is gone, once each. Shown once when 2FA is turned on, only
+// their hashes are stored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecoveryCode(String);
+
+impl RecoveryCode {
+    fn generate() -> Self {
+        let mut bytes = [0u8; RECOVERY_CODE_BYTES];
+        OsRng.fill_bytes(&mut bytes);
+        let code = BASE32_NOPAD.encode(&bytes).to_lowercase();
+        // Groups of four are easier to copy down.
+        let groups: Vec<&str> = (0..code.len())
+            .step_by(4)
+            .map(|start| &code[start..(start + 4).min(code.len())])
+            .collect();
+        Self(groups.join("-"))
+    }
+
+    // The hash of the code however it was typed, with or without dashes, in any case.
+    pub(crate) fn hash(&self) -> String {
+        let code: String = self
+            .0
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect();
+        hash_token(&code.to_lowercase())
+    }
+}
+
+// A fresh set of recovery codes and their hashes, which replace whatever codes the user had.
+pub(crate) fn recovery_codes() -> (Vec<RecoveryCode>, Vec<String>) {
+    let codes: Vec<RecoveryCode> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| RecoveryCode::generate())
+        .collect();
+    let hashes = codes.iter().map(RecoveryCode::hash).collect();
+    (codes, hashes)
+}
+
+// Stands for a login whose password was right, until the second factor completes it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChallengeToken(String);
+
+impl ChallengeToken {
+    pub(crate) fn hash(&self) -> String {
+        hash_token(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoginChallenge {
+    token_hash: String,
+    user_id: UserId,
+    expires_at: NaiveDateTime,
+}
+
+impl LoginChallenge {
+    pub(crate) fn new(user_id: UserId) -> (Self, ChallengeToken) {
+        let token = ChallengeToken(gen_random_token());
+        let challenge = Self {
+            token_hash: token.hash(),
+            user_id,
+            expires_at: Utc::now().naive_utc() + Duration::minutes(CHALLENGE_TTL_MINUTES),
+        };
+        (challenge, token)
+    }
+
+    pub(crate) fn user_id(&self) -> &UserId {
+        &self.user_id
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConfirmTotpPayload {
+    code: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DisableTotpPayload {
+    current_password: Password,
+}
+
+// The second login step, with a code of the app or else a recovery code.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SecondFactorPayload {
+    challenge: ChallengeToken,
+    #[serde(default)]
+    code: Option<String>,
+    #[serde(default)]
+    recovery_code: Option<RecoveryCode>,
+}
+
+// What a new enrolment shows the user, for the app to scan or to type in.
+#[derive(Debug, Clone, Serialize)]
+pub struct TotpSetup {
+    secret: String,
+    otpauth_uri: String,
+    qr_svg: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecoveryCodes {
+    recovery_codes: Vec<RecoveryCode>,
+}
+
+// The answer to a password login of a user with 2FA, instead of a session or tokens.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChallengeResponse {
+    challenge: ChallengeToken,
+    expires_in: i64,
+}
+
+impl ChallengeResponse {
+    pub(crate) fn new(challenge: ChallengeToken) -> Self {
+        Self {
+            challenge,
+            expires_in: Duration::minutes(CHALLENGE_TTL_MINUTES).num_seconds(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TwoFactorError {
+    #[error("Two-factor authentication is not set up")]
+    NotFound,
+    #[error("Two-factor authentication is on already")]
+    AlreadyEnabled,
+    #[error("Invalid or used code")]
+    InvalidCode,
+    #[error("Invalid or expired login challenge")]
+    InvalidChallenge,
+    #[error("Two-factor authentication was set up meanwhile")]
+    Duplicate(#[source] sqlx::Error),
+    #[error("Referenced record does not exist")]
+    ForeignKey(#[source] sqlx::Error),
+    #[error("Database unavailable")]
+    Unavailable(#[source] sqlx::Error),
+    #[error("Database error")]
+    Database(#[source] sqlx::Error),
+}
+
+impl From<sqlx::Error> for TwoFactorError {
+    fn from(value: sqlx::Error) -> Self {
+        match classify(&value) {
+            DbErrorKind::NotFound => TwoFactorError::NotFound,
+            DbErrorKind::UniqueViolation => TwoFactorError::Duplicate(value),
+            DbErrorKind::ForeignKeyViolation => TwoFactorError::ForeignKey(value),
+            DbErrorKind::Unavailable => TwoFactorError::Unavailable(value),
+            DbErrorKind::Validation | DbErrorKind::Other => TwoFactorError::Database(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{recovery_codes, RecoveryCode};
+
+    #[test]
+    fn test_recovery_codes() {
+        let (codes, hashes) = recovery_codes();
+        assert_eq!(codes.len(), 10);
+        assert_eq!(hashes.len(), 10);
+        assert_eq!(codes[0].0.len(), 19);
+        assert_ne!(codes[0], codes[1]);
+
+        // Typed in without the dashes and in upper case it is still the same code.
+        let typed = RecoveryCode(codes[0].0.replace('-', "").to_uppercase());
+        assert_eq!(typed.hash(), hashes[0]);
+    }
+}
@@ -0,0 +1,142 @@
+use chrono::Utc;
+use hyper::{body::Bytes, StatusCode};
+
+use crate::{
+    server::{empty_response, json_response, parse_json, ApiError, AppState, HttpResponse},
+    users::{handler::confirm_password, PubUserInfo, UserId},
+};
+
+use super::{
+    recovery_codes, totp::qr_svg, ChallengeResponse, ConfirmTotpPayload, DisableTotpPayload,
+    LoginChallenge, RecoveryCodes, SecondFactorPayload, TotpEnrolment, TotpSetup, TwoFactorError,
+};
+
+impl From<TwoFactorError> for ApiError {
+    fn from(value: TwoFactorError) -> Self {
+        match value {
+            TwoFactorError::NotFound => ApiError::new(StatusCode::NOT_FOUND, value),
+            TwoFactorError::InvalidCode | TwoFactorError::InvalidChallenge => {
+                ApiError::new(StatusCode::UNAUTHORIZED, value)
+            }
+            TwoFactorError::AlreadyEnabled
+            | TwoFactorError::Duplicate(_)
+            | TwoFactorError::ForeignKey(_) => ApiError::new(StatusCode::CONFLICT, value),
+            TwoFactorError::Unavailable(_) => {
+                ApiError::logged(StatusCode::SERVICE_UNAVAILABLE, &value)
+            }
+            TwoFactorError::Database(_) => {
+                ApiError::logged(StatusCode::INTERNAL_SERVER_ERROR, &value)
+            }
+        }
+    }
+}
+
+// The answer to a right password when the user has 2FA on: a challenge to send back with a code,
+// instead of signing in right away.
+pub(crate) async fn challenge(
+    state: &AppState,
+    user_id: &UserId,
+) -> Result<Option<HttpResponse>, ApiError> {
+    match state.two_factor.read(user_id).await {
+        Ok(enrolment) if enrolment.is_enabled() => {}
+        Ok(_) | Err(TwoFactorError::NotFound) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let (challenge, token) = LoginChallenge::new(user_id.clone());
+    state.two_factor.insert_challenge(&challenge).await?;
+    Ok(Some(json_response(
+        StatusCode::ACCEPTED,
+        &ChallengeResponse::new(token),
+    )))
+}
+
+// The user a login challenge completes with, if the code goes with it. A challenge is good for one
+// try, a wrong code means starting over with the password.
+pub(crate) async fn second_factor(
+    state: &AppState,
+    payload: SecondFactorPayload,
+) -> Result<UserId, ApiError> {
+    if payload.code.is_none() && payload.recovery_code.is_none() {
+        return Err(ApiError::invalid_field("code", "is missing"));
+    }
+    let user_id = state.two_factor.take_challenge(&payload.challenge).await?;
+    if let Some(code) = payload.code {
+        let enrolment = match state.two_factor.read(&user_id).await {
+            Ok(enrolment) => enrolment,
+            Err(TwoFactorError::NotFound) => return Err(TwoFactorError::InvalidCode.into()),
+            Err(e) => return Err(e.into()),
+        };
+        let step = enrolment
+            .secret()
+            .check(&code, Utc::now())
+            .ok_or(TwoFactorError::InvalidCode)?;
+        state.two_factor.use_step(&user_id, step).await?;
+    } else if let Some(recovery_code) = payload.recovery_code {
+        state
+            .two_factor
+            .use_recovery_code(&user_id, &recovery_code.hash())
+            .await?;
+    }
+    Ok(user_id)
+}
+
+// Starts an enrolment with a new secret. Nothing changes for logins until `confirm`.
+pub(crate) async fn setup(state: &AppState, user: PubUserInfo) -> Result<HttpResponse, ApiError> {
+    match state.two_factor.read(&user.user_id).await {
+        Ok(enrolment) if enrolment.is_enabled() => {
+            return Err(TwoFactorError::AlreadyEnabled.into())
+        }
+        Ok(_) | Err(TwoFactorError::NotFound) => {}
+        Err(e) => return Err(e.into()),
+    }
+    let mail = state.users.mail_status(&user.user_id).await?.mail;
+    let enrolment = TotpEnrolment::new(user.user_id);
+    state.two_factor.enrol(&enrolment).await?;
+
+    let otpauth_uri = enrolment.secret().otpauth_uri(&String::from(mail));
+    let setup = TotpSetup {
+        secret: enrolment.secret().to_base32(),
+        qr_svg: qr_svg(&otpauth_uri),
+        otpauth_uri,
+    };
+    Ok(json_response(StatusCode::CREATED, &setup))
+}
+
+// Turns 2FA on with a first code from the app, and hands out the recovery codes.
+pub(crate) async fn confirm(
+    state: &AppState,
+    user: PubUserInfo,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let payload: ConfirmTotpPayload = parse_json(&body)?;
+    let enrolment = state.two_factor.read(&user.user_id).await?;
+    if enrolment.is_enabled() {
+        return Err(TwoFactorError::AlreadyEnabled.into());
+    }
+    let step = enrolment
+        .secret()
+        .check(&payload.code, Utc::now())
+        .ok_or_else(|| ApiError::invalid_field("code", "is incorrect"))?;
+
+    let (recovery_codes, hashes) = recovery_codes();
+    state
+        .two_factor
+        .enable(&user.user_id, step, &hashes)
+        .await?;
+    Ok(json_response(
+        StatusCode::OK,
+        &RecoveryCodes { recovery_codes },
+    ))
+}
+
+// Turns 2FA off, which takes the password again so an unattended session cannot do it.
+pub(crate) async fn disable(
+    state: &AppState,
+    user: PubUserInfo,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let payload: DisableTotpPayload = parse_json(&body)?;
+    confirm_password(state, &user.user_id, &payload.current_password).await?;
+    state.two_factor.disable(&user.user_id).await?;
+    Ok(empty_response(StatusCode::NO_CONTENT))
+}
@@ -0,0 +1,164 @@
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::{
+    memory::{ConstraintError, MemoryStore},
+    users::UserId,
+};
+
+use super::{repo::TwoFactorStore, ChallengeToken, LoginChallenge, TotpEnrolment, TwoFactorError};
+
+// A row of `recovery_code_table`.
+#[derive(Debug, Clone)]
+pub(crate) struct StoredRecoveryCode {
+    pub(crate) user_id: UserId,
+    pub(crate) code_hash: String,
+}
+
+pub struct MemoryTwoFactorRepository {
+    store: MemoryStore,
+}
+
+impl MemoryTwoFactorRepository {
+    pub(crate) fn new(store: MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl TwoFactorStore for MemoryTwoFactorRepository {
+    async fn read(&self, user_id: &UserId) -> Result<TotpEnrolment, TwoFactorError> {
+        self.store
+            .lock()
+            .enrolments
+            .iter()
+            .find(|enrolment| &enrolment.user_id == user_id)
+            .cloned()
+            .ok_or(TwoFactorError::NotFound)
+    }
+
+    async fn enrol(&self, enrolment: &TotpEnrolment) -> Result<(), TwoFactorError> {
+        let mut tables = self.store.lock();
+        if tables.user(&enrolment.user_id).is_none() {
+            return Err(ConstraintError::foreign_key("totp_usr_id").into());
+        }
+        tables
+            .enrolments
+            .retain(|stored| stored.user_id != enrolment.user_id || stored.enabled);
+        if tables
+            .enrolments
+            .iter()
+            .any(|stored| stored.user_id == enrolment.user_id)
+        {
+            return Err(ConstraintError::unique("totp_usr_idx").into());
+        }
+        tables.enrolments.push(enrolment.clone());
+        Ok(())
+    }
+
+    async fn enable(
+        &self,
+        user_id: &UserId,
+        step: i64,
+        recovery_hashes: &[String],
+    ) -> Result<(), TwoFactorError> {
+        let mut tables = self.store.lock();
+        let enrolment = tables
+            .enrolments
+            .iter_mut()
+            .find(|enrolment| &enrolment.user_id == user_id && !enrolment.enabled)
+            .ok_or(TwoFactorError::NotFound)?;
+        enrolment.enabled = true;
+        enrolment.last_step = step;
+
+        tables
+            .recovery_codes
+            .retain(|code| &code.user_id != user_id);
+        tables
+            .recovery_codes
+            .extend(recovery_hashes.iter().map(|code_hash| StoredRecoveryCode {
+                user_id: user_id.clone(),
+                code_hash: code_hash.clone(),
+            }));
+        Ok(())
+    }
+
+    async fn disable(&self, user_id: &UserId) -> Result<(), TwoFactorError> {
+        let mut tables = self.store.lock();
+        let before = tables.enrolments.len();
+        tables
+            .enrolments
+            .retain(|enrolment| &enrolment.user_id != user_id);
+        if tables.enrolments.len() == before {
+            return Err(TwoFactorError::NotFound);
+        }
+        tables
+            .recovery_codes
+            .retain(|code| &code.user_id != user_id);
+        Ok(())
+    }
+
+    async fn use_step(&self, user_id: &UserId, step: i64) -> Result<(), TwoFactorError> {
+        let mut tables = self.store.lock();
+        let enrolment = tables
+            .enrolments
+            .iter_mut()
+            .find(|enrolment| {
+                &enrolment.user_id == user_id && enrolment.enabled && enrolment.last_step < step
+            })
+            .ok_or(TwoFactorError::InvalidCode)?;
+        enrolment.last_step = step;
+        Ok(())
+    }
+
+    async fn use_recovery_code(
+        &self,
+        user_id: &UserId,
+        code_hash: &str,
+    ) -> Result<(), TwoFactorError> {
+        let mut tables = self.store.lock();
+        let index = tables
+            .recovery_codes
+            .iter()
+            .position(|code| &code.user_id == user_id && code.code_hash == code_hash)
+            .ok_or(TwoFactorError::InvalidCode)?;
+        tables.recovery_codes.remove(index);
+        Ok(())
+    }
+
+    async fn insert_challenge(&self, challenge: &LoginChallenge) -> Result<(), TwoFactorError> {
+        let mut tables = self.store.lock();
+        let now = Utc::now().naive_utc();
+        tables
+            .login_challenges
+            .retain(|challenge| challenge.expires_at > now);
+
+        if tables.user(&challenge.user_id).is_none() {
+            return Err(ConstraintError::foreign_key("challenge_usr_id").into());
+        }
+        if tables
+            .login_challenges
+            .iter()
+            .any(|stored| stored.token_hash == challenge.token_hash)
+        {
+            return Err(ConstraintError::unique("challenge_token_hash_idx").into());
+        }
+        tables.login_challenges.push(challenge.clone());
+        Ok(())
+    }
+
+    async fn take_challenge(&self, token: &ChallengeToken) -> Result<UserId, TwoFactorError> {
+        let mut tables = self.store.lock();
+        let token_hash = token.hash();
+        let index = tables
+            .login_challenges
+            .iter()
+            .position(|challenge| challenge.token_hash == token_hash)
+            .ok_or(TwoFactorError::InvalidChallenge)?;
+        let challenge = tables.login_challenges.remove(index);
+        if challenge.expires_at <= Utc::now().naive_utc() {
+            return Err(TwoFactorError::InvalidChallenge);
+        }
+        Ok(challenge.user_id)
+    }
+}
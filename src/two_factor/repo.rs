@@ -0,0 +1,406 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{query, query_as, query_scalar};
+
+use crate::{
+    db::{with_pool, DbPool},
+    users::UserId,
+};
+
+use super::{ChallengeToken, LoginChallenge, TotpEnrolment, TwoFactorError};
+
+// What the handlers need from a 2FA backend, either `TwoFactorRepository` or
+// `MemoryTwoFactorRepository`.
+#[async_trait]
+pub(crate) trait TwoFactorStore: Send + Sync {
+    async fn read(&self, user_id: &UserId) -> Result<TotpEnrolment, TwoFactorError>;
+    // Stores a pending enrolment, in place of the pending one the user may have had.
+    async fn enrol(&self, enrolment: &TotpEnrolment) -> Result<(), TwoFactorError>;
+    // Turns the pending enrolment on, with `step` as its first code used, and replaces the
+    // recovery codes with the ones hashed in `recovery_hashes`.
+    async fn enable(
+        &self,
+        user_id: &UserId,
+        step: i64,
+        recovery_hashes: &[String],
+    ) -> Result<(), TwoFactorError>;
+    // Turns 2FA off, the recovery codes go too.
+    async fn disable(&self, user_id: &UserId) -> Result<(), TwoFactorError>;
+    // Takes a code of the time step `step`, unless a code of that step or a later one was used.
+    async fn use_step(&self, user_id: &UserId, step: i64) -> Result<(), TwoFactorError>;
+    // Takes a recovery code, which works once.
+    async fn use_recovery_code(
+        &self,
+        user_id: &UserId,
+        code_hash: &str,
+    ) -> Result<(), TwoFactorError>;
+    async fn insert_challenge(&self, challenge: &LoginChallenge) -> Result<(), TwoFactorError>;
+    // Redeems a login challenge: the user whose password was right, if the challenge is still
+    // valid, and it is gone afterwards either way so it works only once.
+    async fn take_challenge(&self, token: &ChallengeToken) -> Result<UserId, TwoFactorError>;
+}
+
+pub struct TwoFactorRepository {
+    pool: DbPool,
+}
+
+impl TwoFactorRepository {
+    pub(crate) fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl TwoFactorStore for TwoFactorRepository {
+    async fn read(&self, user_id: &UserId) -> Result<TotpEnrolment, TwoFactorError> {
+        with_pool!(&self.pool, |pool| {
+            query_as::<_, TotpEnrolment>(
+                r#"
+                    SELECT user_id, secret, enabled, last_step
+                    FROM totp_table
+                    WHERE user_id = ?
+                "#,
+            )
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?
+        })
+        .ok_or(TwoFactorError::NotFound)
+    }
+
+    async fn enrol(&self, enrolment: &TotpEnrolment) -> Result<(), TwoFactorError> {
+        with_pool!(&self.pool, |pool| {
+            let mut tx = pool.begin().await?;
+            query(
+                r#"
+                    DELETE FROM totp_table
+                    WHERE user_id = ? AND enabled = FALSE
+                "#,
+            )
+            .bind(&enrolment.user_id)
+            .execute(&mut *tx)
+            .await?;
+
+            query(
+                r#"
+                    INSERT INTO totp_table
+                    (user_id, secret, enabled, last_step)
+                    VALUES (?, ?, ?, ?)
+                "#,
+            )
+            .bind(&enrolment.user_id)
+            .bind(enrolment.secret.to_base32())
+            .bind(enrolment.enabled)
+            .bind(enrolment.last_step)
+            .execute(&mut *tx)
+            .await?;
+
+            tx.commit().await?;
+        });
+        Ok(())
+    }
+
+    async fn enable(
+        &self,
+        user_id: &UserId,
+        step: i64,
+        recovery_hashes: &[String],
+    ) -> Result<(), TwoFactorError> {
+        with_pool!(&self.pool, |pool| {
+            let mut tx = pool.begin().await?;
+            let rows = query(
+                r#"
+                    UPDATE totp_table
+                    SET enabled = TRUE, last_step = ?
+                    WHERE user_id = ? AND enabled = FALSE
+                "#,
+            )
+            .bind(step)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            if rows == 0 {
+                return Err(TwoFactorError::NotFound);
+            }
+
+            query(
+                r#"
+                    DELETE FROM recovery_code_table
+                    WHERE user_id = ?
+                "#,
+            )
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+            for code_hash in recovery_hashes {
+                query(
+                    r#"
+                        INSERT INTO recovery_code_table
+                        (user_id, code_hash)
+                        VALUES (?, ?)
+                    "#,
+                )
+                .bind(user_id)
+                .bind(code_hash)
+                .execute(&mut *tx)
+                .await?;
+            }
+
+            tx.commit().await?;
+        });
+        Ok(())
+    }
+
+    async fn disable(&self, user_id: &UserId) -> Result<(), TwoFactorError> {
+        with_pool!(&self.pool, |pool| {
+            let mut tx = pool.begin().await?;
+            query(
+                r#"
+                    DELETE FROM recovery_code_table
+                    WHERE user_id = ?
+                "#,
+            )
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+            let rows = query(
+                r#"
+                    DELETE FROM totp_table
+                    WHERE user_id = ?
+                "#,
+            )
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            if rows == 0 {
+                return Err(TwoFactorError::NotFound);
+            }
+
+            tx.commit().await?;
+        });
+        Ok(())
+    }
+
+    async fn use_step(&self, user_id: &UserId, step: i64) -> Result<(), TwoFactorError> {
+        // The condition on `last_step` keeps two requests with the same code from both getting
+        // through.
+        let rows = with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    UPDATE totp_table
+                    SET last_step = ?
+                    WHERE user_id = ? AND enabled = TRUE AND last_step < ?
+                "#,
+            )
+            .bind(step)
+            .bind(user_id)
+            .bind(step)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        if rows == 0 {
+            return Err(TwoFactorError::InvalidCode);
+        }
+        Ok(())
+    }
+
+    async fn use_recovery_code(
+        &self,
+        user_id: &UserId,
+        code_hash: &str,
+    ) -> Result<(), TwoFactorError> {
+        let rows = with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    DELETE FROM recovery_code_table
+                    WHERE user_id = ? AND code_hash = ?
+                "#,
+            )
+            .bind(user_id)
+            .bind(code_hash)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        if rows == 0 {
+            return Err(TwoFactorError::InvalidCode);
+        }
+        Ok(())
+    }
+
+    async fn insert_challenge(&self, challenge: &LoginChallenge) -> Result<(), TwoFactorError> {
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    DELETE FROM login_challenge_table
+                    WHERE expires_at <= ?
+                "#,
+            )
+            .bind(Utc::now().naive_utc())
+            .execute(pool)
+            .await?;
+        });
+
+        with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    INSERT INTO login_challenge_table
+                    (token_hash, user_id, expires_at)
+                    VALUES (?, ?, ?)
+                "#,
+            )
+            .bind(&challenge.token_hash)
+            .bind(&challenge.user_id)
+            .bind(challenge.expires_at)
+            .execute(pool)
+            .await?;
+        });
+        Ok(())
+    }
+
+    async fn take_challenge(&self, token: &ChallengeToken) -> Result<UserId, TwoFactorError> {
+        let token_hash = token.hash();
+        let user_id = with_pool!(&self.pool, |pool| {
+            query_scalar::<_, UserId>(
+                r#"
+                    SELECT user_id
+                    FROM login_challenge_table
+                    WHERE token_hash = ? AND expires_at > ?
+                "#,
+            )
+            .bind(&token_hash)
+            .bind(Utc::now().naive_utc())
+            .fetch_optional(pool)
+            .await?
+        });
+
+        // Only the request that gets to delete the row may use it, two racing ones do not both
+        // get through.
+        let rows = with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    DELETE FROM login_challenge_table
+                    WHERE token_hash = ?
+                "#,
+            )
+            .bind(&token_hash)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        match user_id {
+            Some(user_id) if rows > 0 => Ok(user_id),
+            _ => Err(TwoFactorError::InvalidChallenge),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+
+    use crate::{
+        testing::TestDb,
+        two_factor::{recovery_codes, LoginChallenge, TotpEnrolment, TwoFactorError},
+        users::UserId,
+    };
+
+    #[tokio::test]
+    async fn test_enrolment_lifecycle() {
+        for db in TestDb::each().await {
+            let repo = db.two_factor();
+            let user_id = &db.alice.user_id;
+
+            assert!(matches!(
+                repo.read(user_id).await,
+                Err(TwoFactorError::NotFound)
+            ));
+            // A second enrolment replaces the first while neither is confirmed.
+            repo.enrol(&TotpEnrolment::new(user_id.clone()))
+                .await
+                .unwrap();
+            let enrolment = TotpEnrolment::new(user_id.clone());
+            repo.enrol(&enrolment).await.unwrap();
+            assert_eq!(repo.read(user_id).await.unwrap(), enrolment);
+            assert!(matches!(
+                repo.use_step(user_id, 10).await,
+                Err(TwoFactorError::InvalidCode)
+            ));
+
+            let (_, hashes) = recovery_codes();
+            repo.enable(user_id, 10, &hashes).await.unwrap();
+            let enabled = repo.read(user_id).await.unwrap();
+            assert!(enabled.is_enabled());
+            assert_eq!(enabled.secret(), enrolment.secret());
+            assert!(matches!(
+                repo.enable(user_id, 11, &hashes).await,
+                Err(TwoFactorError::NotFound)
+            ));
+            assert!(matches!(
+                repo.enrol(&TotpEnrolment::new(user_id.clone())).await,
+                Err(TwoFactorError::Duplicate(_))
+            ));
+
+            // No code works twice, nor does one older than the last.
+            assert!(matches!(
+                repo.use_step(user_id, 10).await,
+                Err(TwoFactorError::InvalidCode)
+            ));
+            repo.use_step(user_id, 11).await.unwrap();
+            assert!(matches!(
+                repo.use_step(user_id, 9).await,
+                Err(TwoFactorError::InvalidCode)
+            ));
+
+            repo.use_recovery_code(user_id, &hashes[0]).await.unwrap();
+            assert!(matches!(
+                repo.use_recovery_code(user_id, &hashes[0]).await,
+                Err(TwoFactorError::InvalidCode)
+            ));
+            assert!(matches!(
+                repo.use_recovery_code(&db.bob.user_id, &hashes[1]).await,
+                Err(TwoFactorError::InvalidCode)
+            ));
+
+            repo.disable(user_id).await.unwrap();
+            assert!(matches!(
+                repo.read(user_id).await,
+                Err(TwoFactorError::NotFound)
+            ));
+            assert!(matches!(
+                repo.use_recovery_code(user_id, &hashes[1]).await,
+                Err(TwoFactorError::InvalidCode)
+            ));
+            assert!(matches!(
+                repo.disable(user_id).await,
+                Err(TwoFactorError::NotFound)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_challenge_works_once() {
+        for db in TestDb::each().await {
+            let repo = db.two_factor();
+
+            let (challenge, token) = LoginChallenge::new(db.alice.user_id.clone());
+            repo.insert_challenge(&challenge).await.unwrap();
+            assert_eq!(repo.take_challenge(&token).await.unwrap(), db.alice.user_id);
+            assert!(matches!(
+                repo.take_challenge(&token).await,
+                Err(TwoFactorError::InvalidChallenge)
+            ));
+
+            let (challenge, _) = LoginChallenge::new(UserId::from(Uuid::new_v4().to_string()));
+            assert!(matches!(
+                repo.insert_challenge(&challenge).await,
+                Err(TwoFactorError::ForeignKey(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use chrono::{DateTime, Utc};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use qrcode::{render::svg, QrCode};
+use rand::{rngs::OsRng, RngCore};
+use sha1::Sha1;
+
+type HmacSha1 = Hmac<Sha1>;
+
+// What every authenticator app assumes when the URI leaves it out: HMAC-SHA1, six digits and a
+// new code every 30 seconds.
+const DIGITS: u32 = 6;
+const PERIOD_SECS: i64 = 30;
+// Codes of the steps right before and after the current one are taken too, for clocks that are a
+// little off and users that are a little slow.
+const SKEW_STEPS: i64 = 1;
+const SECRET_BYTES: usize = 20;
+pub(crate) static ISSUER: &str = "Fridge";
+
+// The key shared with the authenticator app, as RFC 6238 describes it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TotpSecret(Vec<u8>);
+
+impl TotpSecret {
+    pub(crate) fn generate() -> Self {
+        let mut secret = vec![0u8; SECRET_BYTES];
+        OsRng.fill_bytes(&mut secret);
+        Self(secret)
+    }
+
+    pub(crate) fn from_base32(value: &str) -> Option<Self> {
+        BASE32_NOPAD.decode(value.as_bytes()).ok().map(Self)
+    }
+
+    // How apps take the secret when it is typed in instead of scanned.
+    pub(crate) fn to_base32(&self) -> String {
+        BASE32_NOPAD.encode(&self.0)
+    }
+
+    // The HOTP value of RFC 4226 for the counter `step`.
+    pub(crate) fn code_at(&self, step: i64) -> String {
+        let mut mac = HmacSha1::new_from_slice(&self.0).expect("HMAC takes keys of any size");
+        mac.update(&step.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let value = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+        format!(
+            "{:0width$}",
+            value % 10u32.pow(DIGITS),
+            width = DIGITS as usize
+        )
+    }
+
+    // The time step `code` belongs to, if it is a code for `now`. Spaces are ignored, as apps
+    // show the code in two halves.
+    pub(crate) fn check(&self, code: &str, now: DateTime<Utc>) -> Option<i64> {
+        let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+        let current = now.timestamp().div_euclid(PERIOD_SECS);
+        (current - SKEW_STEPS..=current + SKEW_STEPS).find(|step| self.code_at(*step) == code)
+    }
+
+    // What the QR code holds, in the Key Uri Format of Google Authenticator that the other apps
+    // understand as well.
+    pub(crate) fn otpauth_uri(&self, account: &str) -> String {
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            encode_uri_component(ISSUER),
+            encode_uri_component(account),
+            self.to_base32(),
+            encode_uri_component(ISSUER),
+            DIGITS,
+            PERIOD_SECS
+        )
+    }
+}
+
+fn encode_uri_component(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+// `data` as a QR code in an SVG document, ready to go into an `<img>` of the client.
+pub(crate) fn qr_svg(data: &str) -> String {
+    // An otpauth URI is far below the capacity of a QR code.
+    QrCode::new(data.as_bytes())
+        .expect("otpauth URI fits a QR code")
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .build()
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, Utc};
+
+    use super::{qr_svg, TotpSecret};
+
+    fn at(timestamp: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(timestamp, 0).unwrap()
+    }
+
+    // The SHA1 test vectors of RFC 6238, cut to six digits.
+    #[test]
+    fn test_rfc6238_vectors() {
+        let secret = TotpSecret(b"12345678901234567890".to_vec());
+        for (timestamp, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ] {
+            assert_eq!(secret.code_at(timestamp / 30), code);
+            assert_eq!(secret.check(code, at(timestamp)), Some(timestamp / 30));
+        }
+    }
+
+    #[test]
+    fn test_check_window() {
+        let secret = TotpSecret::generate();
+        let now = at(1_800_000_000);
+        let step = now.timestamp() / 30;
+
+        assert_eq!(secret.check(&secret.code_at(step - 1), now), Some(step - 1));
+        assert_eq!(secret.check(&secret.code_at(step + 1), now), Some(step + 1));
+        assert_eq!(secret.check(&secret.code_at(step - 2), now), None);
+        let code = secret.code_at(step);
+        let spaced = format!("{} {}", &code[..3], &code[3..]);
+        assert_eq!(secret.check(&spaced, now), Some(step));
+        assert_eq!(secret.check("", now), None);
+    }
+
+    #[test]
+    fn test_otpauth_uri() {
+        let secret = TotpSecret::generate();
+        assert_eq!(
+            TotpSecret::from_base32(&secret.to_base32()),
+            Some(secret.clone())
+        );
+
+        let uri = secret.otpauth_uri("alice+fridge@mail.com");
+        assert!(uri.starts_with("otpauth://totp/Fridge:alice%2Bfridge%40mail.com?secret="));
+        assert!(uri.contains(&format!("secret={}&", secret.to_base32())));
+        assert!(qr_svg(&uri).contains("<svg"));
+    }
+}
@@ -1,17 +1,34 @@
+use lettre::Address;
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::Type;
-use sqlx::{mysql::MySqlRow, prelude::FromRow, Row};
+use sqlx::{prelude::FromRow, Row};
 use thiserror::Error;
 use uuid::Uuid;
 
-use crate::util::HashFunc;
+use crate::{
+    db::{classify, impl_from_row, impl_uuid_type, DbErrorKind},
+    households::HouseholdError,
+    util::{HashConfig, HashError, HashFunc},
+    validation::{trimmed_text, InvalidValue},
+};
 
+pub(crate) mod handler;
+pub mod import;
+pub(crate) mod memory;
+pub mod policy;
 pub mod repo;
+pub mod verify;
 
-#[derive(Debug, Clone, Serialize, FromRow, PartialEq, Type)]
-#[sqlx(transparent)]
+const MAX_USER_NAME_CHARS: usize = 64;
+// The longest address SMTP can deliver to.
+const MAX_MAIL_CHARS: usize = 254;
+const MAX_PASSWORD_BYTES: usize = 1024;
+
+#[derive(Debug, Clone, Serialize, FromRow, PartialEq, Eq, Hash)]
 pub struct UserId(pub(crate) String);
 
+impl_uuid_type!(UserId);
+
 impl<T: ToString> From<T> for UserId {
     fn from(value: T) -> Self {
         Self(value.to_string())
@@ -25,12 +42,23 @@ impl From<UserId> for String {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq, Type)]
+#[serde(try_from = "String")]
 #[sqlx(transparent)]
 pub struct UserName(String);
 
-impl<T: ToString> From<T> for UserName {
-    fn from(value: T) -> Self {
-        Self(value.to_string())
+impl TryFrom<String> for UserName {
+    type Error = InvalidValue;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        trimmed_text(&value, MAX_USER_NAME_CHARS).map(Self)
+    }
+}
+
+impl TryFrom<&str> for UserName {
+    type Error = InvalidValue;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::try_from(value.to_string())
     }
 }
 
@@ -40,13 +68,35 @@ impl From<UserName> for String {
     }
 }
 
-#[derive(Debug, Clone, FromRow, Deserialize, PartialEq, Type)]
+// Mails are told apart case-insensitively, so they are lowercased and trimmed as soon as they
+// enter the program and stored that way.
+#[derive(Debug, Clone, FromRow, Deserialize, PartialEq, Eq, Hash, Type)]
+#[serde(try_from = "String")]
 #[sqlx(transparent)]
 pub struct Mail(String);
 
-impl<T: ToString> From<T> for Mail {
-    fn from(value: T) -> Self {
-        Self(value.to_string())
+impl TryFrom<String> for Mail {
+    type Error = InvalidValue;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let mail = value.trim().to_lowercase();
+        if mail.chars().count() > MAX_MAIL_CHARS {
+            return Err(InvalidValue::new(format!(
+                "must be at most {} characters",
+                MAX_MAIL_CHARS
+            )));
+        }
+        mail.parse::<Address>()
+            .map_err(|_e| InvalidValue::new("must be a valid mail address"))?;
+        Ok(Self(mail))
+    }
+}
+
+impl TryFrom<&str> for Mail {
+    type Error = InvalidValue;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::try_from(value.to_string())
     }
 }
 
@@ -56,13 +106,66 @@ impl From<Mail> for String {
     }
 }
 
+// A password as the user typed it until `User` replaces it with its hash. Only the checks every
+// password has to pass live here, `PasswordPolicy` decides what is good enough for a new one.
 #[derive(Debug, Clone, Deserialize, FromRow, PartialEq, Type)]
+#[serde(try_from = "String")]
 #[sqlx(transparent)]
 pub struct Password(String);
 
-impl<T: ToString> From<T> for Password {
-    fn from(value: T) -> Self {
-        Self(value.to_string())
+impl TryFrom<String> for Password {
+    type Error = InvalidValue;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value.is_empty() {
+            return Err(InvalidValue::new("must not be empty"));
+        }
+        // Hashing takes longer the longer the input, so there has to be a cap.
+        if value.len() > MAX_PASSWORD_BYTES {
+            return Err(InvalidValue::new(format!(
+                "must be at most {} bytes",
+                MAX_PASSWORD_BYTES
+            )));
+        }
+        Ok(Self(value))
+    }
+}
+
+impl TryFrom<&str> for Password {
+    type Error = InvalidValue;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::try_from(value.to_string())
+    }
+}
+
+impl Password {
+    // The hash to store in place of this password.
+    pub(crate) fn hashed(&self, hasher: &dyn HashFunc) -> Result<Self, HashError> {
+        Ok(Self(hasher.call(&self.0)?))
+    }
+
+    // Whether this password is the one `hash` was made from. A hash that cannot be checked at all
+    // is a problem of the server, so it is logged.
+    pub(crate) fn matches(&self, hash: &Password, hasher: &HashConfig) -> bool {
+        match hasher.verify(&self.0, &hash.0) {
+            Ok(()) => true,
+            Err(HashError::Mismatch) => false,
+            Err(e) => {
+                eprintln!("cannot check password: {}", e);
+                false
+            }
+        }
+    }
+
+    // Stands in for the hash of a mail nobody registered, see `HashConfig::dummy_hash`.
+    pub(crate) fn dummy(hasher: &HashConfig) -> Self {
+        Self(hasher.dummy_hash().to_string())
+    }
+
+    // Whether this hash was made with other settings than `hasher` uses now.
+    pub(crate) fn is_outdated(&self, hasher: &HashConfig) -> bool {
+        hasher.is_outdated(&self.0)
     }
 }
 
@@ -79,56 +182,213 @@ pub struct CreateUserPayload {
     pub password: Password,
 }
 
+// Profile changes have to be confirmed with the current password, as the mail is where password
+// resets go to.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct UpdateUserPayload {
+    pub user_name: UserName,
+    pub mail: Mail,
+    pub current_password: Password,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ResendVerificationPayload {
+    pub mail: Mail,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ChangePasswordPayload {
+    pub current_password: Password,
+    pub new_password: Password,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct User {
     user_id: UserId,
     user_name: UserName,
     mail: Mail,
     password: Password,
+    mail_verified: bool,
 }
 
 impl User {
     pub(crate) fn new(
         payload: CreateUserPayload,
         hasher: Box<dyn HashFunc>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_id(UserId::from(Uuid::new_v4().to_string()), payload, hasher)
+    }
+
+    pub(crate) fn with_id(
+        user_id: UserId,
+        payload: CreateUserPayload,
+        hasher: Box<dyn HashFunc>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         Ok(Self {
-            user_id: UserId::from(Uuid::new_v4().to_string()),
+            user_id,
             user_name: payload.user_name,
             mail: payload.mail,
-            password: Password::from(hasher.call(&payload.password.0)?),
+            password: payload.password.hashed(hasher.as_ref())?,
+            mail_verified: false,
         })
     }
-}
 
-impl FromRow<'_, MySqlRow> for User {
-    fn from_row(row: &'_ MySqlRow) -> Result<Self, sqlx::Error> {
-        Ok(User {
-            user_id: UserId(row.try_get("user_id")?),
-            user_name: UserName(row.try_get("user_name")?),
-            mail: Mail(row.try_get("mail")?),
-            password: Password(row.try_get("password")?),
-        })
+    #[cfg(test)]
+    pub(crate) fn verified(self) -> Self {
+        Self {
+            mail_verified: true,
+            ..self
+        }
+    }
+
+    // A user whose password is already hashed, `password` goes to the store as it is.
+    pub(crate) fn with_hash(
+        user_id: UserId,
+        payload: UpdateUserPayload,
+        password: Password,
+    ) -> Self {
+        Self {
+            user_id,
+            user_name: payload.user_name,
+            mail: payload.mail,
+            password,
+            mail_verified: false,
+        }
+    }
+
+    pub(crate) fn user_id(&self) -> &UserId {
+        &self.user_id
+    }
+
+    pub(crate) fn user_name(&self) -> &UserName {
+        &self.user_name
+    }
+
+    pub(crate) fn mail(&self) -> &Mail {
+        &self.mail
+    }
+
+    pub(crate) fn password(&self) -> &Password {
+        &self.password
+    }
+
+    pub(crate) fn mail_verified(&self) -> bool {
+        self.mail_verified
     }
 }
 
+impl_from_row!(User, |row| {
+    Ok(User {
+        user_id: row.try_get("user_id")?,
+        user_name: UserName(row.try_get("user_name")?),
+        mail: Mail(row.try_get("mail")?),
+        password: Password(row.try_get("password")?),
+        mail_verified: row.try_get("mail_verified")?,
+    })
+});
+
+// Where the user gets mail and whether they confirmed it is theirs.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MailStatus {
+    pub(crate) mail: Mail,
+    pub(crate) verified: bool,
+}
+
+impl_from_row!(MailStatus, |row| {
+    Ok(MailStatus {
+        mail: row.try_get("mail")?,
+        verified: row.try_get("mail_verified")?,
+    })
+});
+
 #[derive(Debug, Clone, Serialize)]
 pub struct PubUserInfo {
     pub user_id: UserId,
     pub user_name: UserName,
 }
 
-impl FromRow<'_, MySqlRow> for PubUserInfo {
-    fn from_row(row: &'_ MySqlRow) -> Result<Self, sqlx::Error> {
-        Ok(PubUserInfo {
-            user_id: UserId(row.try_get("user_id")?),
-            user_name: UserName(row.try_get("user_name")?),
-        })
+impl From<User> for PubUserInfo {
+    fn from(value: User) -> Self {
+        PubUserInfo {
+            user_id: value.user_id,
+            user_name: value.user_name,
+        }
     }
 }
 
-#[derive(Debug, Clone, Error)]
+impl_from_row!(PubUserInfo, |row| {
+    Ok(PubUserInfo {
+        user_id: row.try_get("user_id")?,
+        user_name: UserName(row.try_get("user_name")?),
+    })
+});
+
+#[derive(Debug, Error)]
 pub enum UserError {
-    #[error("error")]
+    #[error("Not found")]
     NotFound,
+    #[error("User already exists")]
+    Duplicate(#[source] sqlx::Error),
+    #[error("Mail already registered")]
+    MailTaken,
+    #[error("Mail address is not verified yet")]
+    MailUnverified,
+    #[error("Referenced record does not exist")]
+    ForeignKey(#[source] sqlx::Error),
+    #[error("Invalid user")]
+    Validation(#[source] sqlx::Error),
+    #[error("Database unavailable")]
+    Unavailable(#[source] sqlx::Error),
+    #[error("Database error")]
+    Database(#[source] sqlx::Error),
+    #[error(transparent)]
+    Household(#[from] HouseholdError),
+}
+
+impl From<sqlx::Error> for UserError {
+    fn from(value: sqlx::Error) -> Self {
+        match classify(&value) {
+            DbErrorKind::NotFound => UserError::NotFound,
+            DbErrorKind::UniqueViolation => UserError::Duplicate(value),
+            DbErrorKind::ForeignKeyViolation => UserError::ForeignKey(value),
+            DbErrorKind::Validation => UserError::Validation(value),
+            DbErrorKind::Unavailable => UserError::Unavailable(value),
+            DbErrorKind::Other => UserError::Database(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Mail, Password, UserName};
+
+    #[test]
+    fn test_user_name_validation() {
+        assert_eq!(
+            String::from(UserName::try_from(" alice ").unwrap()),
+            "alice"
+        );
+        assert!(UserName::try_from("").is_err());
+        assert!(UserName::try_from("a".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn test_mail_validation() {
+        assert_eq!(
+            Mail::try_from(" Alice@Mail.com ").unwrap(),
+            Mail::try_from("alice@mail.com").unwrap()
+        );
+        assert!(Mail::try_from("not-an-email").is_err());
+        assert!(Mail::try_from("alice@").is_err());
+        assert!(Mail::try_from(format!("{}@mail.com", "a".repeat(250))).is_err());
+        assert!(serde_json::from_str::<Mail>(r#""not-an-email""#).is_err());
+    }
+
+    #[test]
+    fn test_password_validation() {
+        // Spaces are kept, they may well be part of the password.
+        assert!(Password::try_from(" pass phrase ").is_ok());
+        assert!(Password::try_from("").is_err());
+        assert!(Password::try_from("p".repeat(1025)).is_err());
+    }
 }
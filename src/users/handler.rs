@@ -0,0 +1,196 @@
+use chrono::Utc;
+use hyper::{body::Bytes, HeaderMap, StatusCode};
+
+use crate::{
+    auth,
+    server::{
+        empty_response, json_response, parse_json, query_param, ApiError, AppState, HttpResponse,
+    },
+};
+
+use super::{
+    verify::VerificationError, ChangePasswordPayload, CreateUserPayload, Mail, Password,
+    PubUserInfo, ResendVerificationPayload, UpdateUserPayload, User, UserError, UserId,
+};
+
+impl From<UserError> for ApiError {
+    fn from(value: UserError) -> Self {
+        match value {
+            UserError::NotFound => ApiError::new(StatusCode::NOT_FOUND, value),
+            UserError::MailUnverified => ApiError::new(StatusCode::FORBIDDEN, value),
+            UserError::Duplicate(_) | UserError::MailTaken => {
+                ApiError::new(StatusCode::CONFLICT, value)
+            }
+            UserError::ForeignKey(_) | UserError::Validation(_) => {
+                ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, value)
+            }
+            UserError::Unavailable(_) => ApiError::logged(StatusCode::SERVICE_UNAVAILABLE, &value),
+            UserError::Database(_) => ApiError::logged(StatusCode::INTERNAL_SERVER_ERROR, &value),
+            UserError::Household(e) => e.into(),
+        }
+    }
+}
+
+impl From<VerificationError> for ApiError {
+    fn from(value: VerificationError) -> Self {
+        match value {
+            VerificationError::Invalid | VerificationError::Expired | VerificationError::Stale => {
+                ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, value)
+            }
+            VerificationError::Config(_) => {
+                ApiError::logged(StatusCode::INTERNAL_SERVER_ERROR, &value)
+            }
+        }
+    }
+}
+
+fn hash_error(e: Box<dyn std::error::Error>) -> ApiError {
+    ApiError::logged(StatusCode::INTERNAL_SERVER_ERROR, e.as_ref())
+}
+
+// Holds a password that is about to be set against the policy of the server.
+pub(crate) fn check_new_password(
+    state: &AppState,
+    field: &str,
+    password: &Password,
+) -> Result<(), ApiError> {
+    state
+        .password_policy
+        .check(password)
+        .map_err(|e| ApiError::invalid_field(field, e))
+}
+
+// Asks the user to confirm a mail they just gave us. The change is stored already whether or not
+// the mail goes out, so a failure is only logged, they can ask for another link. Without mail
+// there is no way to confirm an address, and nothing that needs one works, so it is taken as is.
+async fn confirm_mail(state: &AppState, user_id: &UserId, mail: &Mail) -> Result<(), ApiError> {
+    let Some(mailer) = &state.mailer else {
+        return Ok(state.users.mark_verified(user_id, mail).await?);
+    };
+    if let Err(e) = mailer.send(&state.verifier.mail(user_id, mail)).await {
+        eprintln!("failed to send verification mail: {}", e);
+    }
+    Ok(())
+}
+
+// The stored hash of the user's password, once `password` turned out to match it.
+pub(crate) async fn confirm_password(
+    state: &AppState,
+    user_id: &UserId,
+    password: &Password,
+) -> Result<Password, ApiError> {
+    let stored = state.users.password(user_id).await?;
+    if !password.matches(&stored, &state.hasher) {
+        return Err(ApiError::invalid_field("current_password", "is incorrect"));
+    }
+    Ok(stored)
+}
+
+pub(crate) async fn create(state: &AppState, body: Bytes) -> Result<HttpResponse, ApiError> {
+    let payload: CreateUserPayload = parse_json(&body)?;
+    check_new_password(state, "password", &payload.password)?;
+    let user = User::new(payload, Box::new(state.hasher.clone())).map_err(hash_error)?;
+    let user_info = state.users.insert(&user).await?;
+    confirm_mail(state, user.user_id(), user.mail()).await?;
+    Ok(json_response(StatusCode::CREATED, &user_info))
+}
+
+// Where the link in the verification mail leads.
+pub(crate) async fn verify(
+    state: &AppState,
+    query: Option<&str>,
+) -> Result<HttpResponse, ApiError> {
+    let token = query_param(query, "token")
+        .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "token is missing"))?;
+    let (user_id, mail) = state.verifier.check(token, Utc::now().naive_utc())?;
+    match state.users.mark_verified(&user_id, &mail).await {
+        Ok(()) => Ok(empty_response(StatusCode::NO_CONTENT)),
+        Err(UserError::NotFound) => Err(VerificationError::Stale.into()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Sends another verification link, at most once per `RESEND_INTERVAL` and address. Like the
+// password reset it answers the same whether the mail belongs to anybody.
+pub(crate) async fn resend_verification(
+    state: &AppState,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let payload: ResendVerificationPayload = parse_json(&body)?;
+    let mailer = state.mailer.as_ref().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "mail is not configured on this server",
+        )
+    })?;
+    state.resend_limiter.check(&payload.mail).map_err(|wait| {
+        ApiError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            format!("try again in {} seconds", wait.as_secs() + 1),
+        )
+    })?;
+
+    let user = match state.users.read_by_mail(&payload.mail).await {
+        Ok(user) => user,
+        Err(UserError::NotFound) => return Ok(empty_response(StatusCode::ACCEPTED)),
+        Err(e) => return Err(e.into()),
+    };
+    if !state.users.mail_status(&user.user_id).await?.verified {
+        mailer
+            .send(&state.verifier.mail(&user.user_id, &payload.mail))
+            .await
+            .map_err(|e| ApiError::logged(StatusCode::BAD_GATEWAY, &e))?;
+    }
+    Ok(empty_response(StatusCode::ACCEPTED))
+}
+
+pub(crate) async fn read(state: &AppState, user_id: &str) -> Result<HttpResponse, ApiError> {
+    let user = state.users.read(&UserId::from(user_id)).await?;
+    Ok(json_response(StatusCode::OK, &user))
+}
+
+pub(crate) async fn update(
+    state: &AppState,
+    user: PubUserInfo,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let payload: UpdateUserPayload = parse_json(&body)?;
+    let password = confirm_password(state, &user.user_id, &payload.current_password).await?;
+    let previous = state.users.mail_status(&user.user_id).await?;
+    let user = User::with_hash(user.user_id, payload, password);
+    let user_info = state.users.update(&user.user_id, &user).await?;
+    if previous.mail != *user.mail() {
+        confirm_mail(state, user.user_id(), user.mail()).await?;
+    }
+    Ok(json_response(StatusCode::OK, &user_info))
+}
+
+// Sets a new password and signs the user out of every other session, as whoever knew the old
+// password may still be signed in.
+pub(crate) async fn change_password(
+    state: &AppState,
+    headers: &HeaderMap,
+    user: PubUserInfo,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let payload: ChangePasswordPayload = parse_json(&body)?;
+    check_new_password(state, "new_password", &payload.new_password)?;
+    confirm_password(state, &user.user_id, &payload.current_password).await?;
+
+    let password = payload
+        .new_password
+        .hashed(&state.hasher)
+        .map_err(|e| ApiError::logged(StatusCode::INTERNAL_SERVER_ERROR, &e))?;
+    state.users.set_password(&user.user_id, &password).await?;
+    let current = auth::handler::session_id(headers);
+    state
+        .sessions
+        .revoke_others(&user.user_id, current.as_ref())
+        .await?;
+    Ok(empty_response(StatusCode::NO_CONTENT))
+}
+
+pub(crate) async fn delete(state: &AppState, user: PubUserInfo) -> Result<HttpResponse, ApiError> {
+    state.users.delete(&user.user_id).await?;
+    Ok(empty_response(StatusCode::NO_CONTENT))
+}
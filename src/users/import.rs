@@ -0,0 +1,174 @@
+use serde::Deserialize;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{util::is_known_hash, DbPool};
+
+use super::{
+    repo::{UserRepository, UserStore},
+    Mail, Password, User, UserError, UserId, UserName,
+};
+
+// One user brought over from another app, a line of the JSON Lines file `import-users` reads. The
+// password comes hashed already, in any format `HashConfig::verify` knows, and is rehashed with argon2
+// the first time the user logs in here.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportUserPayload {
+    pub user_name: UserName,
+    pub mail: Mail,
+    pub password_hash: String,
+    // Whether the other app had confirmed the mail already.
+    #[serde(default)]
+    pub mail_verified: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportReport {
+    pub imported: usize,
+    // Lines whose mail belongs to a user already, with their line numbers. Running the same file
+    // again skips everyone imported the first time.
+    pub skipped: Vec<(usize, Mail)>,
+}
+
+#[derive(Debug, Error)]
+pub enum ImportError {
+    #[error("line {line}: {reason}")]
+    Invalid { line: usize, reason: String },
+    #[error("line {line}: {source}")]
+    User { line: usize, source: UserError },
+}
+
+pub async fn import_users(pool: DbPool, input: &str) -> Result<ImportReport, ImportError> {
+    import(&UserRepository::new(pool), input).await
+}
+
+// Every line is checked before the first user is stored, so a bad file imports nobody.
+pub(crate) async fn import(
+    store: &dyn UserStore,
+    input: &str,
+) -> Result<ImportReport, ImportError> {
+    let users = parse(input)?;
+    let mut report = ImportReport::default();
+    for (line, user) in users {
+        match store.insert(&user).await {
+            Ok(_) => report.imported += 1,
+            Err(UserError::MailTaken) => report.skipped.push((line, user.mail)),
+            Err(source) => return Err(ImportError::User { line, source }),
+        }
+    }
+    Ok(report)
+}
+
+fn parse(input: &str) -> Result<Vec<(usize, User)>, ImportError> {
+    let mut users = Vec::new();
+    for (index, text) in input.lines().enumerate() {
+        let line = index + 1;
+        if text.trim().is_empty() {
+            continue;
+        }
+        let payload: ImportUserPayload =
+            serde_json::from_str(text).map_err(|e| ImportError::Invalid {
+                line,
+                reason: e.to_string(),
+            })?;
+        if !is_known_hash(&payload.password_hash) {
+            return Err(ImportError::Invalid {
+                line,
+                reason: "password_hash is in no known format".to_string(),
+            });
+        }
+        let user = User {
+            user_id: UserId::from(Uuid::new_v4().to_string()),
+            user_name: payload.user_name,
+            mail: payload.mail,
+            password: Password(payload.password_hash),
+            mail_verified: payload.mail_verified,
+        };
+        users.push((line, user));
+    }
+    Ok(users)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{import, ImportError, ImportReport};
+    use crate::{
+        testing::TestDb,
+        users::{Mail, Password},
+        util::HashConfig,
+    };
+
+    #[tokio::test]
+    async fn test_import() {
+        for db in TestDb::each().await {
+            let users = db.users();
+            let bcrypt_hash = bcrypt::hash("dave_pass", 4).unwrap();
+            let dave = json!({
+                "user_name": "dave",
+                "mail": "Dave@Mail.com",
+                "password_hash": bcrypt_hash,
+                "mail_verified": true,
+            });
+            let alice = json!({
+                "user_name": "alice",
+                "mail": "alice@mail.com",
+                "password_hash": bcrypt_hash,
+            });
+            // Blank lines are passed over but still counted.
+            let input = format!("{}\n\n{}\n", dave, alice);
+
+            let report = import(users.as_ref(), &input).await.unwrap();
+            assert_eq!(
+                report,
+                ImportReport {
+                    imported: 1,
+                    skipped: vec![(3, db.alice.mail.clone())],
+                }
+            );
+
+            let mail = Mail::try_from("dave@mail.com").unwrap();
+            let dave = users.read_by_mail(&mail).await.unwrap();
+            assert!(users.mail_status(&dave.user_id).await.unwrap().verified);
+            let stored = users.password(&dave.user_id).await.unwrap();
+            assert!(Password::try_from("dave_pass")
+                .unwrap()
+                .matches(&stored, &HashConfig::default()));
+
+            // Running it again changes nothing.
+            let report = import(users.as_ref(), &input).await.unwrap();
+            assert_eq!(report.imported, 0);
+            assert_eq!(report.skipped.len(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bad_line_imports_nobody() {
+        for db in TestDb::each().await {
+            let users = db.users();
+            let dave = json!({
+                "user_name": "dave",
+                "mail": "dave@mail.com",
+                "password_hash": bcrypt::hash("dave_pass", 4).unwrap(),
+            });
+            // An unsalted MD5, which nothing here can check.
+            let erin = json!({
+                "user_name": "erin",
+                "mail": "erin@mail.com",
+                "password_hash": "5f4dcc3b5aa765d61d8327deb882cf99",
+            });
+            let input = format!("{}\n{}", dave, erin);
+
+            let err = import(users.as_ref(), &input).await.unwrap_err();
+            assert!(matches!(err, ImportError::Invalid { line: 2, .. }));
+            let mail = Mail::try_from("dave@mail.com").unwrap();
+            assert!(users.read_by_mail(&mail).await.is_err());
+
+            let err = import(users.as_ref(), r#"{"user_name": "dave"}"#)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, ImportError::Invalid { line: 1, .. }));
+        }
+    }
+}
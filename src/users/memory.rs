@@ -0,0 +1,168 @@
+use async_trait::async_trait;
+
+use crate::{
+    households::{HouseholdError, Role},
+    memory::{ConstraintError, MemoryStore},
+    RepositoryTargetReader, RepositoryWriter,
+};
+
+use super::{repo::UserStore, Mail, MailStatus, Password, PubUserInfo, User, UserError, UserId};
+
+pub struct MemoryUserRepository {
+    store: MemoryStore,
+}
+
+impl MemoryUserRepository {
+    pub(crate) fn new(store: MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl<'a> RepositoryTargetReader<'a, UserId> for MemoryUserRepository {
+    type QueryRes = PubUserInfo;
+    type QueryErr = UserError;
+
+    async fn read(&self, id: &'a UserId) -> Result<Self::QueryRes, Self::QueryErr> {
+        let tables = self.store.lock();
+        let user = tables.user(id).ok_or(UserError::NotFound)?;
+        Ok(PubUserInfo::from(user.clone()))
+    }
+}
+
+#[async_trait]
+impl UserStore for MemoryUserRepository {
+    async fn read_by_mail(&self, mail: &Mail) -> Result<PubUserInfo, UserError> {
+        let tables = self.store.lock();
+        let user = tables
+            .users
+            .iter()
+            .find(|user| &user.mail == mail)
+            .ok_or(UserError::NotFound)?;
+        Ok(PubUserInfo::from(user.clone()))
+    }
+
+    async fn password(&self, id: &UserId) -> Result<Password, UserError> {
+        let tables = self.store.lock();
+        let user = tables.user(id).ok_or(UserError::NotFound)?;
+        Ok(user.password.clone())
+    }
+
+    async fn set_password(&self, id: &UserId, password: &Password) -> Result<(), UserError> {
+        let mut tables = self.store.lock();
+        let user = tables
+            .users
+            .iter_mut()
+            .find(|user| &user.user_id == id)
+            .ok_or(UserError::NotFound)?;
+        user.password = password.clone();
+        Ok(())
+    }
+
+    async fn mail_status(&self, id: &UserId) -> Result<MailStatus, UserError> {
+        let tables = self.store.lock();
+        let user = tables.user(id).ok_or(UserError::NotFound)?;
+        Ok(MailStatus {
+            mail: user.mail.clone(),
+            verified: user.mail_verified,
+        })
+    }
+
+    async fn mark_verified(&self, id: &UserId, mail: &Mail) -> Result<(), UserError> {
+        let mut tables = self.store.lock();
+        let user = tables
+            .users
+            .iter_mut()
+            .find(|user| &user.user_id == id && &user.mail == mail)
+            .ok_or(UserError::NotFound)?;
+        user.mail_verified = true;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'a> RepositoryWriter<'a, User, UserId> for MemoryUserRepository {
+    type Output = PubUserInfo;
+    type Error = UserError;
+
+    async fn insert(&self, payload: &User) -> Result<Self::Output, Self::Error> {
+        let mut tables = self.store.lock();
+        if tables.user(&payload.user_id).is_some() {
+            return Err(ConstraintError::unique("user_id_idx").into());
+        }
+        if tables.users.iter().any(|user| user.mail == payload.mail) {
+            return Err(UserError::MailTaken);
+        }
+        tables.users.push(payload.clone());
+        Ok(PubUserInfo::from(payload.clone()))
+    }
+
+    async fn update(&self, id: &'a UserId, payload: &User) -> Result<Self::Output, Self::Error> {
+        let mut tables = self.store.lock();
+        let taken = tables
+            .users
+            .iter()
+            .any(|user| user.mail == payload.mail && &user.user_id != id);
+        let user = tables
+            .users
+            .iter_mut()
+            .find(|user| &user.user_id == id)
+            .ok_or(UserError::NotFound)?;
+        if taken {
+            return Err(UserError::MailTaken);
+        }
+        user.user_name = payload.user_name.clone();
+        if user.mail != payload.mail {
+            user.mail_verified = false;
+        }
+        user.mail = payload.mail.clone();
+        Ok(PubUserInfo::from(user.clone()))
+    }
+
+    // Sessions, tokens, preferences and memberships go with the user, as `ON DELETE CASCADE` does.
+    // Households the user shares must keep an owner, the ones nobody else is in go too.
+    async fn delete(&self, id: &'a UserId) -> Result<(), Self::Error> {
+        let mut tables = self.store.lock();
+        if tables.user(id).is_none() {
+            return Err(UserError::NotFound);
+        }
+        let mut solo = Vec::new();
+        for membership in tables.members.iter().filter(|member| &member.user_id == id) {
+            let others: Vec<_> = tables
+                .members
+                .iter()
+                .filter(|member| {
+                    member.household_id == membership.household_id && &member.user_id != id
+                })
+                .collect();
+            if others.is_empty() {
+                solo.push(membership.household_id.clone());
+            } else if membership.role == Role::Owner
+                && others.iter().all(|member| member.role != Role::Owner)
+            {
+                return Err(HouseholdError::LastOwner.into());
+            }
+        }
+        for household_id in &solo {
+            tables.remove_household(household_id);
+        }
+        tables.users.retain(|user| &user.user_id != id);
+
+        tables.sessions.retain(|session| session.user_id() != id);
+        tables.password_resets.retain(|reset| reset.user_id() != id);
+        tables.refresh_grants.retain(|grant| grant.user_id() != id);
+        tables.api_tokens.retain(|token| token.user_id() != id);
+        tables
+            .enrolments
+            .retain(|enrolment| enrolment.user_id() != id);
+        tables.recovery_codes.retain(|code| &code.user_id != id);
+        tables
+            .login_challenges
+            .retain(|challenge| challenge.user_id() != id);
+        tables
+            .preferences
+            .retain(|preference| &preference.user_id != id);
+        tables.members.retain(|member| &member.user_id != id);
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use std::{collections::HashSet, fs, io, path::PathBuf};
+
+use thiserror::Error;
+
+use crate::validation::InvalidValue;
+
+use super::Password;
+
+const DEFAULT_MIN_CHARS: usize = 8;
+
+// What a new password has to live up to. Only checked when a password is set, so tightening the
+// policy does not lock anybody out, it applies from their next change on.
+//
+// `PASSWORD_MIN_LENGTH` overrides the minimum length and `PASSWORD_BREACHED_LIST` names a file of
+// known breached passwords, one per line, which are refused outright.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    min_chars: usize,
+    breached: HashSet<String>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_MIN_CHARS)
+    }
+}
+
+impl PasswordPolicy {
+    pub fn new(min_chars: usize) -> Self {
+        Self {
+            min_chars,
+            breached: HashSet::new(),
+        }
+    }
+
+    pub fn with_breached_list(mut self, list: &str) -> Self {
+        self.breached.extend(
+            list.lines()
+                .map(|line| line.trim_end_matches('\r'))
+                .filter(|line| !line.is_empty())
+                .map(str::to_string),
+        );
+        self
+    }
+
+    pub fn from_env() -> Result<Self, PolicyError> {
+        let mut policy = match dotenvy::var("PASSWORD_MIN_LENGTH") {
+            Ok(min) => Self::new(min.parse().map_err(|_e| PolicyError::MinLength(min))?),
+            Err(_) => Self::default(),
+        };
+        if let Ok(path) = dotenvy::var("PASSWORD_BREACHED_LIST") {
+            let path = PathBuf::from(path);
+            let list = fs::read_to_string(&path)
+                .map_err(|source| PolicyError::BreachedList { path, source })?;
+            policy = policy.with_breached_list(&list);
+        }
+        Ok(policy)
+    }
+
+    pub(crate) fn check(&self, password: &Password) -> Result<(), InvalidValue> {
+        if password.0.chars().count() < self.min_chars {
+            return Err(InvalidValue::new(format!(
+                "must be at least {} characters",
+                self.min_chars
+            )));
+        }
+        if self.breached.contains(&password.0) {
+            return Err(InvalidValue::new(
+                "appears in a list of breached passwords, choose another one",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PolicyError {
+    #[error("invalid PASSWORD_MIN_LENGTH {0}")]
+    MinLength(String),
+    #[error("cannot read breached password list {}", path.display())]
+    BreachedList { path: PathBuf, source: io::Error },
+}
+
+#[cfg(test)]
+mod test {
+    use super::PasswordPolicy;
+    use crate::users::Password;
+
+    fn check(policy: &PasswordPolicy, password: &str) -> Result<(), String> {
+        policy
+            .check(&Password::try_from(password).unwrap())
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_min_length() {
+        let policy = PasswordPolicy::default();
+        assert_eq!(
+            check(&policy, "short"),
+            Err("must be at least 8 characters".to_string())
+        );
+        assert!(check(&policy, "long enough").is_ok());
+        // Characters count, not bytes.
+        assert!(check(&PasswordPolicy::new(4), "ぱすわ").is_err());
+        assert!(check(&PasswordPolicy::new(4), "ぱすわど").is_ok());
+    }
+
+    #[test]
+    fn test_breached_list() {
+        let policy = PasswordPolicy::default().with_breached_list("password1\r\nletmein123\n\n");
+        assert!(check(&policy, "password1").is_err());
+        assert!(check(&policy, "letmein123").is_err());
+        assert!(check(&policy, "correct horse").is_ok());
+    }
+}
@@ -1,18 +1,150 @@
 use async_trait::async_trait;
-use sqlx::{MySql, Pool};
+use sqlx::{query, query_as, query_scalar};
 
-use crate::{RepositoryTargetReader, RepositoryWriter};
+use crate::{
+    db::{with_pool, DbPool},
+    households::{HouseholdError, HouseholdId, Role},
+    RepositoryTargetReader, RepositoryWriter,
+};
 
-use super::{PubUserInfo, User, UserError, UserId};
+use super::{Mail, MailStatus, Password, PubUserInfo, User, UserError, UserId};
+
+// What the handlers need from a user backend, either `UserRepository` or
+// `MemoryUserRepository`.
+#[async_trait]
+pub(crate) trait UserStore:
+    for<'a> RepositoryWriter<'a, User, UserId, Output = PubUserInfo, Error = UserError>
+    + for<'a> RepositoryTargetReader<'a, UserId, QueryRes = PubUserInfo, QueryErr = UserError>
+    + Send
+    + Sync
+{
+    // The user registered with `mail`, which is unique among all users.
+    async fn read_by_mail(&self, mail: &Mail) -> Result<PubUserInfo, UserError>;
+    // The stored hash of the password of the user.
+    async fn password(&self, id: &UserId) -> Result<Password, UserError>;
+    // `update` leaves the password alone, it only ever changes through here. `password` has to be
+    // hashed already.
+    async fn set_password(&self, id: &UserId, password: &Password) -> Result<(), UserError>;
+    async fn mail_status(&self, id: &UserId) -> Result<MailStatus, UserError>;
+    // Marks the mail of the user verified, as long as it still is `mail`.
+    async fn mark_verified(&self, id: &UserId, mail: &Mail) -> Result<(), UserError>;
+}
 
 pub struct UserRepository {
-    pool: Pool<MySql>,
+    pool: DbPool,
 }
 
 impl UserRepository {
-    pub(crate) fn new(pool: Pool<MySql>) -> Self {
+    pub(crate) fn new(pool: DbPool) -> Self {
         Self { pool }
     }
+
+    // The unique indexes on `user_id` and `mail` fail alike, so a clash is only reported as a
+    // taken mail once the mail turns out to belong to someone else.
+    async fn unique_violation(&self, err: UserError, user: &User) -> UserError {
+        if !matches!(err, UserError::Duplicate(_)) {
+            return err;
+        }
+        match self.read_by_mail(&user.mail).await {
+            Ok(owner) if owner.user_id != user.user_id => UserError::MailTaken,
+            _ => err,
+        }
+    }
+}
+
+#[async_trait]
+impl UserStore for UserRepository {
+    async fn read_by_mail(&self, mail: &Mail) -> Result<PubUserInfo, UserError> {
+        let query_res = with_pool!(&self.pool, |pool| {
+            query_as(
+                r#"
+                    SELECT user_id, user_name
+                    FROM user_table
+                    WHERE mail = ?
+                "#,
+            )
+            .bind(mail)
+            .fetch_one(pool)
+            .await
+        })?;
+        Ok(query_res)
+    }
+
+    async fn password(&self, id: &UserId) -> Result<Password, UserError> {
+        let password = with_pool!(&self.pool, |pool| {
+            query_scalar::<_, Password>(
+                r#"
+                    SELECT password
+                    FROM user_table
+                    WHERE user_id = ?
+                "#,
+            )
+            .bind(id)
+            .fetch_one(pool)
+            .await
+        })?;
+        Ok(password)
+    }
+
+    async fn set_password(&self, id: &UserId, password: &Password) -> Result<(), UserError> {
+        let rows = with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    UPDATE user_table
+                    SET password = ?
+                    WHERE user_id = ?
+                "#,
+            )
+            .bind(password)
+            .bind(id)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+
+        if rows == 0 {
+            return Err(UserError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn mail_status(&self, id: &UserId) -> Result<MailStatus, UserError> {
+        let status = with_pool!(&self.pool, |pool| {
+            query_as(
+                r#"
+                    SELECT mail, mail_verified
+                    FROM user_table
+                    WHERE user_id = ?
+                "#,
+            )
+            .bind(id)
+            .fetch_one(pool)
+            .await
+        })?;
+        Ok(status)
+    }
+
+    async fn mark_verified(&self, id: &UserId, mail: &Mail) -> Result<(), UserError> {
+        let rows = with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    UPDATE user_table
+                    SET mail_verified = TRUE
+                    WHERE user_id = ? AND mail = ?
+                "#,
+            )
+            .bind(id)
+            .bind(mail)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+
+        if rows == 0 {
+            return Err(UserError::NotFound);
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -21,75 +153,171 @@ impl<'a> RepositoryTargetReader<'a, UserId> for UserRepository {
     type QueryErr = UserError;
 
     async fn read(&self, id: &'a UserId) -> Result<Self::QueryRes, Self::QueryErr> {
-        let query_res = sqlx::query_as(
-            r#"
-                SELECT user_id, user_name
-                FROM user_table
-                WHERE user_id = ?
-            "#,
-        )
-        .bind(id)
-        .fetch_one(&self.pool)
-        .await
-        .map_err(|_e| UserError::NotFound)?;
+        let query_res = with_pool!(&self.pool, |pool| {
+            query_as(
+                r#"
+                    SELECT user_id, user_name
+                    FROM user_table
+                    WHERE user_id = ?
+                "#,
+            )
+            .bind(id)
+            .fetch_one(pool)
+            .await
+        })?;
         Ok(query_res)
     }
 }
 
 #[async_trait]
-impl<'a> RepositoryWriter<'a, '_, User, UserId> for UserRepository {
-    type Output = ();
+impl<'a> RepositoryWriter<'a, User, UserId> for UserRepository {
+    type Output = PubUserInfo;
     type Error = UserError;
 
     async fn insert(&self, payload: &User) -> Result<Self::Output, Self::Error> {
-        sqlx::query(
-            r#"
-                INSERT INTO user_table
-                (user_id, user_name, mail, password) VALUES (?, ?, ?, ?)
-            "#,
-        )
-        .bind(&payload.user_id)
-        .bind(&payload.user_name)
-        .bind(&payload.mail)
-        .bind(&payload.password)
-        .execute(&self.pool)
-        .await
-        .map_err(|_e| UserError::NotFound)?;
-        Ok(())
+        let res = with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    INSERT INTO user_table
+                    (user_id, user_name, mail, password, mail_verified) VALUES (?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(&payload.user_id)
+            .bind(&payload.user_name)
+            .bind(&payload.mail)
+            .bind(&payload.password)
+            .bind(payload.mail_verified)
+            .execute(pool)
+            .await
+            .map(|_| ())
+        });
+        if let Err(e) = res {
+            return Err(self.unique_violation(e.into(), payload).await);
+        }
+        Ok(PubUserInfo::from(payload.clone()))
     }
 
-    async fn update(&self, _id: &'a UserId, payload: &User) -> Result<Self::Output, Self::Error> {
-        sqlx::query(
-            r#"
-                UPDATE user_table
-                SET
-                user_name = ?,
-                mail = ?,
-                password = ?
-                WHERE user_id = ?
-            "#,
-        )
-        .bind(&payload.user_name)
-        .bind(&payload.mail)
-        .bind(&payload.password)
-        .bind(&payload.user_id)
-        .execute(&self.pool)
-        .await
-        .map_err(|_e| UserError::NotFound)?;
-        Ok(())
+    // A new mail has to be verified again. MySQL assigns from left to right, so `mail_verified`
+    // goes first to still see the old mail.
+    async fn update(&self, id: &'a UserId, payload: &User) -> Result<Self::Output, Self::Error> {
+        let res = with_pool!(&self.pool, |pool| {
+            query(
+                r#"
+                    UPDATE user_table
+                    SET
+                    user_name = ?,
+                    mail_verified = CASE WHEN mail = ? THEN mail_verified ELSE FALSE END,
+                    mail = ?
+                    WHERE user_id = ?
+                "#,
+            )
+            .bind(&payload.user_name)
+            .bind(&payload.mail)
+            .bind(&payload.mail)
+            .bind(id)
+            .execute(pool)
+            .await
+            .map(|res| res.rows_affected())
+        });
+        let rows = match res {
+            Ok(rows) => rows,
+            Err(e) => return Err(self.unique_violation(e.into(), payload).await),
+        };
+
+        if rows == 0 {
+            return Err(UserError::NotFound);
+        }
+        self.read(id).await
     }
-    
+
+    // Households the user shares must keep an owner. The ones nobody else is a member of go with
+    // the user, foods and locations included.
     async fn delete(&self, id: &'a UserId) -> Result<(), Self::Error> {
-        sqlx::query(
-            r#"
-                DELETE FROM user_table
-                WHERE user_id = ?
-            "#,
-        )
-        .bind(id)
-        .execute(&self.pool)
-        .await
-        .map_err(|_e| UserError::NotFound)?;
+        with_pool!(&self.pool, |pool| {
+            let mut tx = pool.begin().await?;
+            let orphaned = query_scalar::<_, i64>(
+                r#"
+                    SELECT COUNT(*)
+                    FROM household_member_table m
+                    WHERE m.user_id = ? AND m.role = ?
+                    AND NOT EXISTS (
+                        SELECT 1
+                        FROM household_member_table o
+                        WHERE o.household_id = m.household_id
+                        AND o.user_id <> m.user_id AND o.role = ?
+                    )
+                    AND EXISTS (
+                        SELECT 1
+                        FROM household_member_table o
+                        WHERE o.household_id = m.household_id AND o.user_id <> m.user_id
+                    )
+                "#,
+            )
+            .bind(id)
+            .bind(Role::Owner.as_str())
+            .bind(Role::Owner.as_str())
+            .fetch_one(&mut *tx)
+            .await?;
+            if orphaned > 0 {
+                return Err(HouseholdError::LastOwner.into());
+            }
+
+            let solo = query_scalar::<_, HouseholdId>(
+                r#"
+                    SELECT m.household_id
+                    FROM household_member_table m
+                    WHERE m.user_id = ?
+                    AND NOT EXISTS (
+                        SELECT 1
+                        FROM household_member_table o
+                        WHERE o.household_id = m.household_id AND o.user_id <> m.user_id
+                    )
+                "#,
+            )
+            .bind(id)
+            .fetch_all(&mut *tx)
+            .await?;
+            for household_id in &solo {
+                query(
+                    r#"
+                        DELETE FROM food_table
+                        WHERE location_id IN (
+                            SELECT location_id
+                            FROM storage_location_table
+                            WHERE household_id = ?
+                        )
+                    "#,
+                )
+                .bind(household_id)
+                .execute(&mut *tx)
+                .await?;
+
+                query(
+                    r#"
+                        DELETE FROM household_table
+                        WHERE household_id = ?
+                    "#,
+                )
+                .bind(household_id)
+                .execute(&mut *tx)
+                .await?;
+            }
+
+            let rows = query(
+                r#"
+                    DELETE FROM user_table
+                    WHERE user_id = ?
+                "#,
+            )
+            .bind(id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            if rows == 0 {
+                return Err(UserError::NotFound);
+            }
+            tx.commit().await?;
+        });
         Ok(())
     }
 }
@@ -97,31 +325,26 @@ impl<'a> RepositoryWriter<'a, '_, User, UserId> for UserRepository {
 #[cfg(test)]
 mod test {
     use rand::random;
-    use sqlx::{query_as, MySqlPool};
+    use serde_json::json;
 
     use crate::{
-        users::{CreateUserPayload, Mail, Password, User, UserName},
-        util::default_hash_password,
-        RepositoryTargetReader, RepositoryWriter,
+        auth::{AuthError, Session},
+        foods::Food,
+        households::{Household, HouseholdError, Invite, Role},
+        testing::{TestDb, FIXTURE_PASSWORD},
+        users::{CreateUserPayload, Mail, Password, UpdateUserPayload, User, UserError, UserName},
+        util::HashConfig,
     };
 
-    use super::{UserId, UserRepository};
-
-    async fn set_up_db() -> UserRepository {
-        let db_url = dotenvy::var("DATABASE_URL").unwrap();
-        let pool = MySqlPool::connect(&db_url).await.unwrap();
-        UserRepository { pool }
-    }
-
     fn user_provider() -> User {
         let num = random::<i32>();
         let payload = CreateUserPayload {
-            user_name: UserName::from(format!("test_user_name_{}", num)),
-            mail: Mail::from(format!("test_user_mail_{}@mail.com", num)),
-            password: Password::from(format!("test_user_pass_{}", num)),
+            user_name: UserName::try_from(format!("test_user_name_{}", num)).unwrap(),
+            mail: Mail::try_from(format!("test_user_mail_{}@mail.com", num)).unwrap(),
+            password: Password::try_from(format!("test_user_pass_{}", num)).unwrap(),
         };
 
-        let hasher = Box::new(default_hash_password);
+        let hasher = Box::new(HashConfig::default());
         User::new(payload, hasher).unwrap()
     }
 
@@ -129,78 +352,313 @@ mod test {
         let num = random::<i32>();
         User {
             user_id: user.user_id,
-            user_name: UserName::from(format!("test_user_name_{}", num)),
-            mail: Mail::from(format!("test_user_mail_{}@mail.com", num)),
-            password: Password::from(format!("test_user_pass_{}", num)),
+            user_name: UserName::try_from(format!("test_user_name_{}", num)).unwrap(),
+            mail: Mail::try_from(format!("test_user_mail_{}@mail.com", num)).unwrap(),
+            password: Password::try_from(format!("test_user_pass_{}", num)).unwrap(),
+            mail_verified: user.mail_verified,
         }
     }
 
-    async fn query_full_data(id: &UserId) -> Result<User, Box<dyn std::error::Error>> {
-        let repo = set_up_db().await;
-        let res = query_as(
-            r#"
-                SELECT user_id, user_name, mail, password
-                FROM user_table
-                WHERE user_id = ?
-            "#,
-        )
-        .bind::<String>(id.clone().into())
-        .fetch_one(&repo.pool)
-        .await
-        .map_err(|e| e)?;
-        Ok(res)
+    // Checks every column of the user through what the stores hand out, which works on every
+    // backend.
+    async fn assert_stored(db: &TestDb, user: &User) {
+        let user_info = db.users().read_by_mail(&user.mail).await.unwrap();
+        assert_eq!(user_info.user_id, user.user_id);
+        assert_eq!(user_info.user_name, user.user_name);
+
+        let credential = db.sessions().credential(&user.mail).await.unwrap();
+        assert_eq!(credential.user_id(), &user.user_id);
+        assert_eq!(credential.password(), &user.password);
+
+        let status = db.users().mail_status(&user.user_id).await.unwrap();
+        assert_eq!(status.verified, user.mail_verified);
     }
 
     #[tokio::test]
     async fn test_insert_user() {
-        let repo = set_up_db().await;
-        let new_user = user_provider();
-
-        repo.insert(&new_user).await.unwrap();
-        let user_info = query_full_data(&new_user.user_id).await.unwrap();
+        for db in TestDb::each().await {
+            let repo = db.users();
+            let new_user = user_provider();
 
-        assert_eq!(user_info.user_id, new_user.user_id);
-        assert_eq!(user_info.user_name, new_user.user_name);
+            let user_info = repo.insert(&new_user).await.unwrap();
+            assert_eq!(user_info.user_id, new_user.user_id);
+            assert_eq!(user_info.user_name, new_user.user_name);
 
-        let inserted_full_data = query_full_data(&new_user.user_id).await.unwrap();
-        assert_eq!(inserted_full_data, new_user);
+            assert_stored(&db, &new_user).await;
+        }
     }
 
     #[tokio::test]
     async fn test_update_user() {
-        let repo = set_up_db().await;
-        let new_user = user_provider();
-        repo.insert(&new_user).await.unwrap();
+        for db in TestDb::each().await {
+            let repo = db.users();
+            let new_user = user_provider();
+            repo.insert(&new_user).await.unwrap();
 
-        let update_user = update_user(new_user);
-        repo.update(&update_user.user_id, &update_user)
-            .await
-            .unwrap();
+            let password = new_user.password.clone();
+            let update_user = update_user(new_user);
+            let user_info = repo
+                .update(&update_user.user_id, &update_user)
+                .await
+                .unwrap();
+            assert_eq!(user_info.user_name, update_user.user_name);
 
-        let modified_full_data = query_full_data(&update_user.user_id).await.unwrap();
-        assert_eq!(modified_full_data, update_user);
+            // Only `set_password` changes the password.
+            assert_stored(
+                &db,
+                &User {
+                    password,
+                    ..update_user
+                },
+            )
+            .await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_set_password() {
+        for db in TestDb::each().await {
+            let repo = db.users();
+
+            let password = Password::try_from("new_password")
+                .unwrap()
+                .hashed(&HashConfig::default())
+                .unwrap();
+            repo.set_password(&db.alice.user_id, &password)
+                .await
+                .unwrap();
+            assert_eq!(repo.password(&db.alice.user_id).await.unwrap(), password);
+            assert!(Password::try_from("new_password")
+                .unwrap()
+                .matches(&password, &HashConfig::default()));
+
+            let nobody = user_provider();
+            assert!(matches!(
+                repo.set_password(&nobody.user_id, &password).await,
+                Err(UserError::NotFound)
+            ));
+            assert!(matches!(
+                repo.password(&nobody.user_id).await,
+                Err(UserError::NotFound)
+            ));
+        }
     }
 
     #[tokio::test]
     async fn test_delete_user() {
-        let repo = set_up_db().await;
-        let new_user = user_provider();
-        repo.insert(&new_user).await.unwrap();
+        for db in TestDb::each().await {
+            let repo = db.users();
+            let session = Session::new(db.alice.user_id.clone());
+            db.sessions().insert(&session).await.unwrap();
 
-        repo.delete(&new_user.user_id).await.unwrap();
-        if let Ok(_) = query_full_data(&new_user.user_id).await {
-            panic!("Expected user is deleted, but found user");
+            repo.delete(&db.alice.user_id).await.unwrap();
+            assert!(matches!(
+                repo.read(&db.alice.user_id).await,
+                Err(UserError::NotFound)
+            ));
+            assert!(matches!(
+                db.sessions().credential(&db.alice.mail).await,
+                Err(AuthError::InvalidCredential)
+            ));
+            // The sessions go with the user.
+            assert!(matches!(
+                db.sessions().read(session.session_id()).await,
+                Err(AuthError::Unauthorized)
+            ));
+            assert!(matches!(
+                repo.delete(&db.alice.user_id).await,
+                Err(UserError::NotFound)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_households() {
+        for db in TestDb::each().await {
+            let repo = db.users();
+            let households = db.households();
+            let (alice, bob) = (&db.alice.user_id, &db.bob.user_id);
+
+            // A home of alice alone, with a food in its fridge.
+            let home = households.default_for(alice).await.unwrap();
+            let home_id = home.household().household_id().clone();
+            let fridge = db.locations().default_for(&home_id, alice).await.unwrap();
+            let payload = serde_json::from_value(json!({"food_name": "milk", "exp": "2025-04-08"}));
+            let food = Food::new(
+                payload.unwrap(),
+                db.alice.info(),
+                fridge.location_id().clone(),
+            );
+            db.foods().insert(&food).await.unwrap();
+
+            // A flat alice owns alone and shares with bob.
+            let payload = serde_json::from_value(json!({"household_name": "Flat 3"}));
+            let flat = Household::new(payload.unwrap());
+            households.insert(&flat, alice).await.unwrap();
+            let payload = serde_json::from_value(json!({"mail": "bob@mail.com", "role": "member"}));
+            let (invite, token) = Invite::new(payload.unwrap(), flat.household_id().clone());
+            households.insert_invite(&invite).await.unwrap();
+            households.accept_invite(&token, bob).await.unwrap();
+
+            assert!(matches!(
+                repo.delete(alice).await,
+                Err(UserError::Household(HouseholdError::LastOwner))
+            ));
+            assert_eq!(repo.read(alice).await.unwrap().user_id, *alice);
+
+            households
+                .update_member(flat.household_id(), bob, Role::Owner)
+                .await
+                .unwrap();
+            repo.delete(alice).await.unwrap();
+            assert_eq!(
+                households.role(flat.household_id(), bob).await.unwrap(),
+                Role::Owner
+            );
+            let payload = serde_json::from_value(json!({"mail": "bob@mail.com", "role": "member"}));
+            let (invite, _) = Invite::new(payload.unwrap(), home_id);
+            assert!(matches!(
+                households.insert_invite(&invite).await,
+                Err(HouseholdError::ForeignKey(_))
+            ));
         }
     }
 
     #[tokio::test]
     async fn test_read_user() {
-        let repo = set_up_db().await;
-        let new_user = user_provider();
-        repo.insert(&new_user).await.unwrap();
+        for db in TestDb::each().await {
+            let user_info = db.users().read(&db.alice.user_id).await.unwrap();
+            assert_eq!(user_info.user_id, db.alice.user_id);
+            assert_eq!(user_info.user_name, db.alice.user_name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_user_id() {
+        for db in TestDb::each().await {
+            let repo = db.users();
+            let user = user_provider();
+            repo.insert(&user).await.unwrap();
+
+            assert!(matches!(
+                repo.insert(&user).await,
+                Err(UserError::Duplicate(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_by_mail() {
+        for db in TestDb::each().await {
+            let repo = db.users();
 
-        let user_info = repo.read(&new_user.user_id).await.unwrap();
-        assert_eq!(user_info.user_id, new_user.user_id);
-        assert_eq!(user_info.user_name, new_user.user_name);
+            let user_info = repo
+                .read_by_mail(&Mail::try_from(" Alice@Mail.com").unwrap())
+                .await
+                .unwrap();
+            assert_eq!(user_info.user_id, db.alice.user_id);
+            assert!(matches!(
+                repo.read_by_mail(&Mail::try_from("nobody@mail.com").unwrap())
+                    .await,
+                Err(UserError::NotFound)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mail_taken() {
+        for db in TestDb::each().await {
+            let repo = db.users();
+
+            let taken = User {
+                mail: Mail::try_from("ALICE@mail.com").unwrap(),
+                ..user_provider()
+            };
+            assert!(matches!(
+                repo.insert(&taken).await,
+                Err(UserError::MailTaken)
+            ));
+
+            let bob = User {
+                user_id: db.bob.user_id.clone(),
+                ..taken
+            };
+            assert!(matches!(
+                repo.update(&bob.user_id, &bob).await,
+                Err(UserError::MailTaken)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_missing_user_not_found() {
+        for db in TestDb::each().await {
+            let repo = db.users();
+            let user = user_provider();
+
+            assert!(matches!(
+                repo.update(&user.user_id, &user).await,
+                Err(UserError::NotFound)
+            ));
+            assert!(matches!(
+                repo.delete(&user.user_id).await,
+                Err(UserError::NotFound)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mark_verified() {
+        for db in TestDb::each().await {
+            let repo = db.users();
+            let carol = &db.carol;
+            assert!(!repo.mail_status(&carol.user_id).await.unwrap().verified);
+
+            // A link for a mail the user no longer has does nothing.
+            let old_mail = Mail::try_from("carol@old.com").unwrap();
+            assert!(matches!(
+                repo.mark_verified(&carol.user_id, &old_mail).await,
+                Err(UserError::NotFound)
+            ));
+            assert!(!repo.mail_status(&carol.user_id).await.unwrap().verified);
+
+            repo.mark_verified(&carol.user_id, &carol.mail)
+                .await
+                .unwrap();
+            // Following the link twice is fine.
+            repo.mark_verified(&carol.user_id, &carol.mail)
+                .await
+                .unwrap();
+            assert!(repo.mail_status(&carol.user_id).await.unwrap().verified);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_new_mail_needs_verification() {
+        for db in TestDb::each().await {
+            let repo = db.users();
+            let alice = &db.alice;
+            let payload = |mail: &Mail| UpdateUserPayload {
+                user_name: alice.user_name.clone(),
+                mail: mail.clone(),
+                current_password: Password::try_from(FIXTURE_PASSWORD).unwrap(),
+            };
+            let password = repo.password(&alice.user_id).await.unwrap();
+
+            let same = User::with_hash(
+                alice.user_id.clone(),
+                payload(&alice.mail),
+                password.clone(),
+            );
+            repo.update(&alice.user_id, &same).await.unwrap();
+            assert!(repo.mail_status(&alice.user_id).await.unwrap().verified);
+
+            let new_mail = Mail::try_from("alice@new.com").unwrap();
+            let moved = User::with_hash(alice.user_id.clone(), payload(&new_mail), password);
+            repo.update(&alice.user_id, &moved).await.unwrap();
+            let status = repo.mail_status(&alice.user_id).await.unwrap();
+            assert_eq!(status.mail, new_mail);
+            assert!(!status.verified);
+        }
     }
 }
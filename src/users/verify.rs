@@ -0,0 +1,214 @@
+use base64::{prelude::BASE64_URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::mail::OutgoingMail;
+
+use super::{Mail, UserId};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const TOKEN_TTL_HOURS: i64 = 24;
+const MIN_KEY_BYTES: usize = 32;
+static DEFAULT_PUBLIC_URL: &str = "http://localhost:8080";
+static VERIFY_SUBJECT: &str = "Confirm your fridge mail address";
+
+// Issues and checks the links that confirm a mail address. A token names the user, the mail and
+// when it expires, signed with a key of the server, so nothing has to be stored until the link is
+// followed. A token for an address the user has moved away from is refused by the store, which
+// only verifies the mail the token names.
+//
+// `MAIL_VERIFICATION_KEY` is the signing key, at least 32 bytes. Without it a random key is used
+// and links stop working when the server restarts. `PUBLIC_URL` is where the links point to.
+#[derive(Clone)]
+pub struct MailVerifier {
+    key: Vec<u8>,
+    public_url: String,
+    ephemeral: bool,
+}
+
+impl Default for MailVerifier {
+    fn default() -> Self {
+        Self::ephemeral(DEFAULT_PUBLIC_URL)
+    }
+}
+
+impl MailVerifier {
+    pub fn new(key: &[u8], public_url: &str) -> Result<Self, VerificationError> {
+        if key.len() < MIN_KEY_BYTES {
+            return Err(VerificationError::Config(format!(
+                "the key must be at least {} bytes",
+                MIN_KEY_BYTES
+            )));
+        }
+        Ok(Self {
+            key: key.to_vec(),
+            public_url: public_url.trim_end_matches('/').to_string(),
+            ephemeral: false,
+        })
+    }
+
+    // Signs with a random key, good for as long as the process runs.
+    pub fn ephemeral(public_url: &str) -> Self {
+        let mut key = vec![0u8; MIN_KEY_BYTES];
+        OsRng.fill_bytes(&mut key);
+        Self {
+            ephemeral: true,
+            ..Self::new(&key, public_url).expect("random key is long enough")
+        }
+    }
+
+    pub fn from_env() -> Result<Self, VerificationError> {
+        let public_url =
+            dotenvy::var("PUBLIC_URL").unwrap_or_else(|_| DEFAULT_PUBLIC_URL.to_string());
+        match dotenvy::var("MAIL_VERIFICATION_KEY") {
+            Ok(key) => Self::new(key.as_bytes(), &public_url),
+            Err(_) => Ok(Self::ephemeral(&public_url)),
+        }
+    }
+
+    pub fn is_ephemeral(&self) -> bool {
+        self.ephemeral
+    }
+
+    fn signature(&self, payload: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+        mac.update(payload);
+        mac
+    }
+
+    pub(crate) fn token(&self, user_id: &UserId, mail: &Mail, expires_at: NaiveDateTime) -> String {
+        let payload = format!(
+            "{}\n{}\n{}",
+            user_id.0,
+            mail.0,
+            expires_at.and_utc().timestamp()
+        );
+        let signature = self.signature(payload.as_bytes()).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            BASE64_URL_SAFE_NO_PAD.encode(payload),
+            BASE64_URL_SAFE_NO_PAD.encode(signature)
+        )
+    }
+
+    // The user and mail a token confirms, if it was signed by this server and has not expired.
+    pub(crate) fn check(
+        &self,
+        token: &str,
+        now: NaiveDateTime,
+    ) -> Result<(UserId, Mail), VerificationError> {
+        let (payload, signature) = token.split_once('.').ok_or(VerificationError::Invalid)?;
+        let payload = BASE64_URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_e| VerificationError::Invalid)?;
+        let signature = BASE64_URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_e| VerificationError::Invalid)?;
+        self.signature(&payload)
+            .verify_slice(&signature)
+            .map_err(|_e| VerificationError::Invalid)?;
+
+        // Signed by us, so the payload is what `token` wrote.
+        let payload = String::from_utf8(payload).map_err(|_e| VerificationError::Invalid)?;
+        let mut parts = payload.split('\n');
+        let (Some(user_id), Some(mail), Some(expires_at)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(VerificationError::Invalid);
+        };
+        let expires_at = expires_at
+            .parse()
+            .ok()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+            .ok_or(VerificationError::Invalid)?;
+        if expires_at.naive_utc() <= now {
+            return Err(VerificationError::Expired);
+        }
+        Ok((UserId::from(user_id), Mail(mail.to_string())))
+    }
+
+    pub(crate) fn mail(&self, user_id: &UserId, mail: &Mail) -> OutgoingMail {
+        let expires_at = Utc::now().naive_utc() + Duration::hours(TOKEN_TTL_HOURS);
+        let body = format!(
+            "Hi,\n\nplease confirm that this is your mail address by opening this link before \
+             {} UTC:\n\n{}/users/verify?token={}\n\n\
+             Until then the fridge sends you no expiry digests and you cannot invite anybody.\n",
+            expires_at.format("%Y-%m-%d %H:%M"),
+            self.public_url,
+            self.token(user_id, mail, expires_at),
+        );
+        OutgoingMail {
+            to: mail.clone(),
+            subject: VERIFY_SUBJECT.to_string(),
+            body,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum VerificationError {
+    #[error("invalid mail verification configuration: {0}")]
+    Config(String),
+    #[error("Invalid verification token")]
+    Invalid,
+    #[error("Verification token has expired")]
+    Expired,
+    #[error("Verification token is for a mail address the user no longer has")]
+    Stale,
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use super::{MailVerifier, VerificationError};
+    use crate::users::{Mail, UserId};
+
+    #[test]
+    fn test_token_round_trip() {
+        let verifier = MailVerifier::ephemeral("http://fridge.test/");
+        let user_id = UserId::from("test_user_id");
+        let mail = Mail::try_from("alice@mail.com").unwrap();
+        let now = Utc::now().naive_utc();
+
+        let token = verifier.token(&user_id, &mail, now + Duration::hours(1));
+        assert_eq!(
+            verifier.check(&token, now).unwrap(),
+            (user_id.clone(), mail.clone())
+        );
+        assert!(matches!(
+            verifier.check(&token, now + Duration::hours(2)),
+            Err(VerificationError::Expired)
+        ));
+
+        let other = MailVerifier::ephemeral("http://fridge.test");
+        assert!(matches!(
+            other.check(&token, now),
+            Err(VerificationError::Invalid)
+        ));
+        let (payload, signature) = token.split_once('.').unwrap();
+        let forged = format!("{}x.{}", payload, signature);
+        assert!(matches!(
+            verifier.check(&forged, now),
+            Err(VerificationError::Invalid)
+        ));
+    }
+
+    #[test]
+    fn test_mail_links_to_server() {
+        let verifier = MailVerifier::ephemeral("http://fridge.test/");
+        let mail = Mail::try_from("alice@mail.com").unwrap();
+        let sent = verifier.mail(&UserId::from("test_user_id"), &mail);
+        assert_eq!(sent.to, mail);
+        assert!(sent.body.contains("http://fridge.test/users/verify?token="));
+    }
+
+    #[test]
+    fn test_short_key_refused() {
+        assert!(MailVerifier::new(b"too short", "http://fridge.test").is_err());
+    }
+}
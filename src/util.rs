@@ -1,8 +1,24 @@
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
-use base64::{prelude::BASE64_STANDARD, Engine};
-use password_hash::{Salt, SaltString};
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::Hash,
+    sync::{Mutex, OnceLock, PoisonError},
+    time::{Duration, Instant},
+};
+
+use argon2::{
+    Algorithm, Argon2, KeyId, Params, ParamsBuilder, PasswordHash, PasswordHasher,
+    PasswordVerifier, Version, ARGON2ID_IDENT,
+};
+use base64::{
+    prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD},
+    Engine,
+};
+use password_hash::SaltString;
+use rand::{rngs::OsRng, RngCore};
+use scrypt::Scrypt;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
-use uuid::Uuid;
 
 pub(crate) trait HashFunc: Send + Sync {
     fn call(&self, password: &str) -> Result<String, HashError>;
@@ -17,50 +33,493 @@ where
     }
 }
 
-pub(crate) fn default_hash_password(password: &str) -> Result<String, HashError> {
-    let salt_string = SaltString::from_b64(&gen_uniq_b64_string()).map_err(|_| HashError::Salt)?;
-    let salt = Salt::from(&salt_string);
+const MIN_PEPPER_BYTES: usize = 32;
+
+// How new passwords are hashed: Argon2id with the cost settings of the argon2 crate unless
+// `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` or `ARGON2_PARALLELISM` say otherwise. Hashes made with
+// other settings still verify, they carry their settings along, and are redone on the next login.
+//
+// `PASSWORD_PEPPERS` lists secrets mixed into the hashes besides the salt, as comma separated
+// `id:base64` pairs, so a dump of the database alone is not enough to start guessing passwords.
+// The first one is used for new hashes, the others only still verify the hashes made with them
+// until their users log in again. To rotate, put a new pepper in front and drop the old one once
+// no hash names it any more.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HashConfig {
+    // Carries the id of the current pepper as `keyid`.
+    params: Params,
+    peppers: Vec<Pepper>,
+    // Made on first use, see `dummy_hash`.
+    dummy: OnceLock<String>,
+}
+
+// A secret for argon2. Its id goes into every hash made with it, to find it again.
+#[derive(Clone, PartialEq)]
+struct Pepper {
+    id: Vec<u8>,
+    secret: Vec<u8>,
+}
+
+impl fmt::Debug for Pepper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pepper")
+            .field("id", &String::from_utf8_lossy(&self.id))
+            .finish_non_exhaustive()
+    }
+}
+
+impl HashConfig {
+    pub fn new(
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    ) -> Result<Self, HashConfigError> {
+        let params = Params::new(memory_kib, iterations, parallelism, None)
+            .map_err(HashConfigError::Params)?;
+        Ok(Self {
+            params,
+            peppers: Vec::new(),
+            dummy: OnceLock::new(),
+        })
+    }
+
+    // The first pepper added is the one new hashes are made with.
+    pub fn with_pepper(mut self, id: &str, secret: &[u8]) -> Result<Self, HashConfigError> {
+        let id = id.as_bytes();
+        if id.is_empty()
+            || id.len() > Params::MAX_KEYID_LEN
+            || !id.iter().all(u8::is_ascii_alphanumeric)
+            || self.peppers.iter().any(|pepper| pepper.id == id)
+        {
+            return Err(HashConfigError::PepperId(
+                String::from_utf8_lossy(id).to_string(),
+            ));
+        }
+        if secret.len() < MIN_PEPPER_BYTES {
+            return Err(HashConfigError::PepperSecret(MIN_PEPPER_BYTES));
+        }
+        if self.peppers.is_empty() {
+            self.params = ParamsBuilder::new()
+                .m_cost(self.params.m_cost())
+                .t_cost(self.params.t_cost())
+                .p_cost(self.params.p_cost())
+                .keyid(KeyId::new(id).map_err(HashConfigError::Params)?)
+                .build()
+                .map_err(HashConfigError::Params)?;
+        }
+        self.peppers.push(Pepper {
+            id: id.to_vec(),
+            secret: secret.to_vec(),
+        });
+        self.dummy = OnceLock::new();
+        Ok(self)
+    }
+
+    pub fn from_env() -> Result<Self, HashConfigError> {
+        let defaults = Params::default();
+        let mut config = Self::new(
+            env_u32("ARGON2_MEMORY_KIB", defaults.m_cost())?,
+            env_u32("ARGON2_ITERATIONS", defaults.t_cost())?,
+            env_u32("ARGON2_PARALLELISM", defaults.p_cost())?,
+        )?;
+        if let Ok(peppers) = dotenvy::var("PASSWORD_PEPPERS") {
+            for pepper in peppers.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+                let invalid = || HashConfigError::Variable("PASSWORD_PEPPERS");
+                let (id, secret) = pepper.split_once(':').ok_or_else(invalid)?;
+                let secret = BASE64_STANDARD.decode(secret).map_err(|_e| invalid())?;
+                config = config.with_pepper(id, &secret)?;
+            }
+        }
+        Ok(config)
+    }
+
+    fn argon2(&self) -> Result<Argon2<'_>, HashError> {
+        match self.peppers.first() {
+            Some(pepper) => Argon2::new_with_secret(
+                &pepper.secret,
+                Algorithm::Argon2id,
+                Version::V0x13,
+                self.params.clone(),
+            )
+            .map_err(|_e| HashError::Hash),
+            None => Ok(Argon2::new(
+                Algorithm::Argon2id,
+                Version::V0x13,
+                self.params.clone(),
+            )),
+        }
+    }
+
+    pub(crate) fn hash(&self, password: &str) -> Result<String, HashError> {
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = self
+            .argon2()?
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|_| HashError::Hash)?;
+        Ok(password_hash.to_string())
+    }
+
+    pub(crate) fn verify(&self, password: &str, password_hash: &str) -> Result<(), HashError> {
+        let argon2 = Argon2Verifier {
+            peppers: &self.peppers,
+        };
+        verifier(&argon2, password_hash)
+            .ok_or(HashError::UnknownFormat)?
+            .verify(password, password_hash)
+    }
+
+    // A hash of no one's password, made like `hash` makes them now. Checking a password against it
+    // takes as long as against a real one, for logins with a mail nobody registered.
+    pub(crate) fn dummy_hash(&self) -> &str {
+        self.dummy
+            .get_or_init(|| self.hash(&gen_random_token()).unwrap_or_default())
+    }
+
+    // Whether `password_hash` was made some other way than `hash` would make it now.
+    pub(crate) fn is_outdated(&self, password_hash: &str) -> bool {
+        let Ok(password_hash) = PasswordHash::new(password_hash) else {
+            return true;
+        };
+        let Ok(params) = Params::try_from(&password_hash) else {
+            return true;
+        };
+        password_hash.algorithm != ARGON2ID_IDENT
+            || password_hash.version != Some(Version::V0x13.into())
+            || params.m_cost() != self.params.m_cost()
+            || params.t_cost() != self.params.t_cost()
+            || params.p_cost() != self.params.p_cost()
+            || params.keyid() != self.params.keyid()
+    }
+}
+
+impl HashFunc for HashConfig {
+    fn call(&self, password: &str) -> Result<String, HashError> {
+        self.hash(password)
+    }
+}
+
+fn env_u32(name: &'static str, default: u32) -> Result<u32, HashConfigError> {
+    match dotenvy::var(name) {
+        Ok(value) => value.parse().map_err(|_e| HashConfigError::Variable(name)),
+        Err(_) => Ok(default),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum HashConfigError {
+    #[error("invalid {0}")]
+    Variable(&'static str),
+    #[error("invalid pepper id {0:?}, ids are up to 8 letters or digits and unique")]
+    PepperId(String),
+    #[error("peppers must be at least {0} bytes")]
+    PepperSecret(usize),
+    #[error("invalid argon2 parameters: {0}")]
+    Params(argon2::Error),
+}
+
+// URL safe random token used for session identifiers.
+pub(crate) fn gen_random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    BASE64_URL_SAFE_NO_PAD.encode(bytes)
+}
+
+// Digest of a token from `gen_random_token`, for tokens that must not be stored as they are. The
+// token carries 256 random bits already, so a plain hash is enough, unlike for passwords.
+pub(crate) fn hash_token(token: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
 
-    let password_hash = Argon2::default()
-        .hash_password(password.as_bytes(), salt)
-        .map_err(|_| HashError::Hash)?;
-    Ok(password_hash.to_string())
+// Lets each key through at most once per `interval`. The counts live in process memory, so every
+// server instance keeps its own.
+pub(crate) struct RateLimiter<K> {
+    interval: Duration,
+    last: Mutex<HashMap<K, Instant>>,
 }
 
-fn gen_uniq_b64_string() -> String {
-    BASE64_STANDARD.encode(Uuid::new_v4().to_string())
+impl<K: Eq + Hash + Clone> RateLimiter<K> {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Ok if `key` may go ahead now, how long it still has to wait otherwise.
+    pub(crate) fn check(&self, key: &K) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut last = self.last.lock().unwrap_or_else(PoisonError::into_inner);
+        last.retain(|_, at| now.duration_since(*at) < self.interval);
+        if let Some(at) = last.get(key) {
+            return Err(self.interval - now.duration_since(*at));
+        }
+        last.insert(key.clone(), now);
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Error)]
 pub(crate) enum HashError {
-    #[error("failed to create salt")]
-    Salt,
     #[error("failed to hash password")]
     Hash,
+    #[error("password does not match")]
+    Mismatch,
+    #[error("unknown password hash format")]
+    UnknownFormat,
+    #[error("password hash names a pepper that is not configured")]
+    UnknownPepper,
+}
+
+// Checks passwords against the hashes of one format, the counterpart of `HashFunc`. Only argon2
+// hashes are made here, the others come from users imported from elsewhere and are replaced by
+// argon2 ones when those users log in.
+pub(crate) trait VerifyFunc: Send + Sync {
+    // Whether `password_hash` is in the format this verifier reads.
+    fn recognises(&self, password_hash: &str) -> bool;
+    fn verify(&self, password: &str, password_hash: &str) -> Result<(), HashError>;
+}
+
+// Argon2 in PHC format, `$argon2id$v=19$...`. The settings are read from the hash, and so is the
+// id of the pepper if it was made with one.
+struct Argon2Verifier<'a> {
+    peppers: &'a [Pepper],
 }
 
-fn verify_pass(password: &str, password_hash: &str) -> Result<(), HashError> {
-    let password_hash = PasswordHash::try_from(password_hash).map_err(|_e| HashError::Hash)?;
-    Argon2::default()
-        .verify_password(password.as_bytes(), &password_hash)
-        .map_err(|_e| HashError::Hash)
+impl VerifyFunc for Argon2Verifier<'_> {
+    fn recognises(&self, password_hash: &str) -> bool {
+        password_hash.starts_with("$argon2")
+    }
+
+    fn verify(&self, password: &str, password_hash: &str) -> Result<(), HashError> {
+        let password_hash =
+            PasswordHash::new(password_hash).map_err(|_e| HashError::UnknownFormat)?;
+        let params = Params::try_from(&password_hash).map_err(|_e| HashError::UnknownFormat)?;
+        let argon2 = if params.keyid().is_empty() {
+            Argon2::default()
+        } else {
+            let pepper = self
+                .peppers
+                .iter()
+                .find(|pepper| pepper.id == params.keyid())
+                .ok_or(HashError::UnknownPepper)?;
+            Argon2::new_with_secret(
+                &pepper.secret,
+                Algorithm::default(),
+                Version::default(),
+                Params::default(),
+            )
+            .map_err(|_e| HashError::Hash)?
+        };
+        argon2
+            .verify_password(password.as_bytes(), &password_hash)
+            .map_err(|_e| HashError::Mismatch)
+    }
+}
+
+// bcrypt in crypt format, `$2b$12$...` and its older `$2a$`, `$2x$` and `$2y$` variants.
+struct BcryptVerifier;
+
+impl VerifyFunc for BcryptVerifier {
+    fn recognises(&self, password_hash: &str) -> bool {
+        ["$2a$", "$2b$", "$2x$", "$2y$"]
+            .iter()
+            .any(|prefix| password_hash.starts_with(prefix))
+    }
+
+    fn verify(&self, password: &str, password_hash: &str) -> Result<(), HashError> {
+        match bcrypt::verify(password, password_hash) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(HashError::Mismatch),
+            Err(_) => Err(HashError::UnknownFormat),
+        }
+    }
+}
+
+// scrypt in PHC format, `$scrypt$ln=15,r=8,p=1$...`.
+struct ScryptVerifier;
+
+impl VerifyFunc for ScryptVerifier {
+    fn recognises(&self, password_hash: &str) -> bool {
+        password_hash.starts_with("$scrypt$")
+    }
+
+    fn verify(&self, password: &str, password_hash: &str) -> Result<(), HashError> {
+        let password_hash =
+            PasswordHash::new(password_hash).map_err(|_e| HashError::UnknownFormat)?;
+        Scrypt
+            .verify_password(password.as_bytes(), &password_hash)
+            .map_err(|_e| HashError::Mismatch)
+    }
+}
+
+fn verifier<'a>(argon2: &'a Argon2Verifier<'a>, password_hash: &str) -> Option<&'a dyn VerifyFunc> {
+    let verifiers: [&dyn VerifyFunc; 3] = [argon2, &BcryptVerifier, &ScryptVerifier];
+    verifiers
+        .into_iter()
+        .find(|verifier| verifier.recognises(password_hash))
+}
+
+// Whether `HashConfig::verify` can read `password_hash`, peppers aside.
+pub(crate) fn is_known_hash(password_hash: &str) -> bool {
+    verifier(&Argon2Verifier { peppers: &[] }, password_hash).is_some()
 }
 
 #[cfg(test)]
 mod test {
-    use super::{default_hash_password, verify_pass};
+    use std::time::Duration;
+
+    use argon2::PasswordHasher;
+    use password_hash::SaltString;
+    use rand::rngs::OsRng;
+    use scrypt::Scrypt;
+
+    use super::{gen_random_token, hash_token, is_known_hash, HashConfig, HashError, RateLimiter};
 
     #[test]
-    fn test_hash_password() {
+    fn test_hash_verify() {
         let password = "test_password";
-        (default_hash_password(password)).unwrap();
+        let password_hash = HashConfig::default().hash(password).unwrap();
+
+        HashConfig::default()
+            .verify(password, &password_hash)
+            .expect("should same pass");
+        assert!(HashConfig::default()
+            .verify("other_password", &password_hash)
+            .is_err());
+        assert_ne!(HashConfig::default().hash(password).unwrap(), password_hash);
     }
 
     #[test]
-    fn test_hash_verify() {
-        let password = "test_password2";
-        let password_hash = (default_hash_password(password)).unwrap();
+    fn test_outdated_hash() {
+        let config = HashConfig::default();
+        let password_hash = config.hash("test_password").unwrap();
+        assert!(!config.is_outdated(&password_hash));
+
+        let stronger = HashConfig::new(32 * 1024, 3, 1).unwrap();
+        assert!(stronger.is_outdated(&password_hash));
+        // Verifying takes the settings from the hash, not from the config.
+        HashConfig::default()
+            .verify("test_password", &stronger.hash("test_password").unwrap())
+            .unwrap();
+
+        assert!(
+            config.is_outdated("$argon2i$v=19$m=19456,t=2,p=1$c2FsdHNhbHQ$aGFzaGhhc2hoYXNoaGFzaA")
+        );
+        assert!(config.is_outdated("not a hash"));
+    }
+
+    #[test]
+    fn test_legacy_hashes() {
+        let bcrypt = bcrypt::hash("test_password", 4).unwrap();
+        let scrypt = Scrypt
+            .hash_password_customized(
+                b"test_password",
+                None,
+                None,
+                scrypt::Params::new(4, 8, 1, 32).unwrap(),
+                &SaltString::generate(&mut OsRng),
+            )
+            .unwrap()
+            .to_string();
+
+        for password_hash in [bcrypt, scrypt] {
+            assert!(is_known_hash(&password_hash));
+            HashConfig::default()
+                .verify("test_password", &password_hash)
+                .unwrap();
+            assert!(matches!(
+                HashConfig::default().verify("other_password", &password_hash),
+                Err(HashError::Mismatch)
+            ));
+            assert!(HashConfig::default().is_outdated(&password_hash));
+        }
+        assert!(!is_known_hash("5f4dcc3b5aa765d61d8327deb882cf99"));
+        assert!(matches!(
+            HashConfig::default().verify("test_password", "$2b$garbage"),
+            Err(HashError::UnknownFormat)
+        ));
+    }
+
+    #[test]
+    fn test_pepper_rotation() {
+        let old = HashConfig::default().with_pepper("k1", &[1; 32]).unwrap();
+        let old_hash = old.hash("test_password").unwrap();
+        assert!(old_hash.contains("keyid="));
+        old.verify("test_password", &old_hash).unwrap();
+        // Without the pepper the hash is worth nothing.
+        assert!(HashConfig::default()
+            .verify("test_password", &old_hash)
+            .is_err());
+
+        let rotated = HashConfig::default()
+            .with_pepper("k2", &[2; 32])
+            .unwrap()
+            .with_pepper("k1", &[1; 32])
+            .unwrap();
+        rotated.verify("test_password", &old_hash).unwrap();
+        assert!(rotated.is_outdated(&old_hash));
+        let new_hash = rotated.hash("test_password").unwrap();
+        assert!(!rotated.is_outdated(&new_hash));
+        assert!(matches!(
+            old.verify("test_password", &new_hash),
+            Err(HashError::UnknownPepper)
+        ));
+        // A pepper that is added later does not turn hashes without one outdated.
+        let plain_hash = HashConfig::default().hash("test_password").unwrap();
+        rotated.verify("test_password", &plain_hash).unwrap();
+        assert!(rotated.is_outdated(&plain_hash));
+        // The ids must fit into the hash and the secrets must be long enough.
+        assert!(HashConfig::default()
+            .with_pepper("too_long_id", &[1; 32])
+            .is_err());
+        assert!(HashConfig::default().with_pepper("k1", &[1; 16]).is_err());
+        assert!(old.with_pepper("k1", &[3; 32]).is_err());
+    }
+
+    #[test]
+    fn test_dummy_hash() {
+        let config = HashConfig::default();
+        assert_eq!(config.dummy_hash(), config.dummy_hash());
+        assert!(!config.is_outdated(config.dummy_hash()));
+        assert!(matches!(
+            config.verify("test_password", config.dummy_hash()),
+            Err(HashError::Mismatch)
+        ));
+
+        let peppered = config.with_pepper("k1", &[1; 32]).unwrap();
+        assert!(!peppered.is_outdated(peppered.dummy_hash()));
+    }
+
+    #[test]
+    fn test_invalid_hash_config() {
+        assert!(HashConfig::new(0, 2, 1).is_err());
+        assert!(HashConfig::new(19 * 1024, 0, 1).is_err());
+    }
+
+    #[test]
+    fn test_random_token() {
+        let token = gen_random_token();
+        assert_eq!(token.len(), 43);
+        assert_ne!(token, gen_random_token());
+    }
+
+    #[test]
+    fn test_hash_token() {
+        let token = gen_random_token();
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), token);
+        assert_ne!(hash_token(&token), hash_token(&gen_random_token()));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(Duration::from_secs(60));
+        assert!(limiter.check(&"alice").is_ok());
+        assert!(limiter.check(&"alice").is_err());
+        assert!(limiter.check(&"bob").is_ok());
 
-        verify_pass(password, &password_hash).expect("should same pass");
+        let limiter = RateLimiter::new(Duration::ZERO);
+        assert!(limiter.check(&"alice").is_ok());
+        assert!(limiter.check(&"alice").is_ok());
     }
 }
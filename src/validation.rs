@@ -0,0 +1,42 @@
+use thiserror::Error;
+
+// Why one of the validating newtypes refused a value. It does not know which field the value came
+// from, the caller or the path through the request body tells.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{0}")]
+pub struct InvalidValue(String);
+
+impl InvalidValue {
+    pub(crate) fn new(reason: impl ToString) -> Self {
+        Self(reason.to_string())
+    }
+}
+
+// `value` without surrounding whitespace, as long as something is left and it is at most
+// `max_chars` characters long.
+pub(crate) fn trimmed_text(value: &str, max_chars: usize) -> Result<String, InvalidValue> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(InvalidValue::new("must not be empty"));
+    }
+    if value.chars().count() > max_chars {
+        return Err(InvalidValue::new(format!(
+            "must be at most {} characters",
+            max_chars
+        )));
+    }
+    Ok(value.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::trimmed_text;
+
+    #[test]
+    fn test_trimmed_text() {
+        assert_eq!(trimmed_text("  milk ", 4).unwrap(), "milk");
+        assert_eq!(trimmed_text("ミルク", 3).unwrap(), "ミルク");
+        assert!(trimmed_text(" \t", 4).is_err());
+        assert!(trimmed_text("yoghurt", 4).is_err());
+    }
+}